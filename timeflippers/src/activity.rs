@@ -0,0 +1,306 @@
+//! Deriving activities from live events the way TimeFlip2 records its history.
+#![deny(missing_docs)]
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+use std::{collections::VecDeque, time::Duration};
+use tokio::{select, time::Instant};
+
+use crate::{
+    timeflip::{Envelope, Event},
+    types::Facet,
+};
+
+/// TimeFlip2 does not record flips shorter than this in its history.
+pub const DEFAULT_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Start or end of an activity, see [debounce()].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActivityEvent {
+    /// A facet has been up for longer than the threshold.
+    Started {
+        /// The facet facing up.
+        facet: Facet,
+        /// Whether the TimeFlip2 is paused.
+        paused: bool,
+        /// The time the facet was flipped up.
+        time: DateTime<Utc>,
+    },
+    /// Another activity has started or the TimeFlip2 has disconnected.
+    Ended {
+        /// The facet which was facing up.
+        facet: Facet,
+        /// Whether the TimeFlip2 was paused.
+        paused: bool,
+        /// The time the activity started.
+        started: DateTime<Utc>,
+        /// The time the activity ended.
+        ended: DateTime<Utc>,
+        /// Duration of the activity.
+        duration: Duration,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Activity {
+    facet: Facet,
+    paused: bool,
+    started: DateTime<Utc>,
+}
+
+impl Activity {
+    fn same(&self, other: &Activity) -> bool {
+        self.facet == other.facet && self.paused == other.paused
+    }
+}
+
+struct Debouncer<'a> {
+    events: BoxStream<'a, Envelope>,
+    threshold: Duration,
+    active: Option<Activity>,
+    pending: Option<(Activity, Instant)>,
+    last: Option<DateTime<Utc>>,
+    output: VecDeque<ActivityEvent>,
+    done: bool,
+}
+
+impl Debouncer<'_> {
+    fn handle(&mut self, envelope: Envelope) {
+        self.last = Some(envelope.received);
+        let (facet, paused) = match envelope.event {
+            Event::Facet(facet) => (facet, false),
+            Event::DoubleTap { facet, pause } => (facet, pause),
+            Event::Disconnected => {
+                self.confirm_until(envelope.received);
+                self.pending = None;
+                self.end(envelope.received);
+                return;
+            }
+            Event::BatteryLevel(_) | Event::Event(_) | Event::SyncState(_) | Event::History(_) => {
+                return
+            }
+        };
+        let candidate = Activity {
+            facet,
+            paused,
+            started: envelope.received,
+        };
+
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|(p, _)| p.same(&candidate))
+        {
+            return;
+        }
+        self.confirm_until(candidate.started);
+        if self.active.as_ref().is_some_and(|a| a.same(&candidate)) {
+            // Flipped back before the other facet counted.
+            self.pending = None;
+            return;
+        }
+        self.pending = Some((candidate, Instant::now() + self.threshold));
+    }
+
+    /// Confirm the pending activity if it lasted until `time`.
+    fn confirm_until(&mut self, time: DateTime<Utc>) {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|(p, _)| (time - p.started).to_std().unwrap_or_default() >= self.threshold)
+        {
+            self.confirm();
+        }
+    }
+
+    fn confirm(&mut self) {
+        let Some((activity, _)) = self.pending.take() else {
+            return;
+        };
+        self.end(activity.started);
+        self.output.push_back(ActivityEvent::Started {
+            facet: activity.facet.clone(),
+            paused: activity.paused,
+            time: activity.started,
+        });
+        self.active = Some(activity);
+    }
+
+    fn end(&mut self, ended: DateTime<Utc>) {
+        if let Some(active) = self.active.take() {
+            self.output.push_back(ActivityEvent::Ended {
+                duration: (ended - active.started).to_std().unwrap_or_default(),
+                facet: active.facet,
+                paused: active.paused,
+                started: active.started,
+                ended,
+            });
+        }
+    }
+}
+
+/// Turn live events into activities lasting at least `threshold`.
+///
+/// Like TimeFlip2's history, facets facing up for less than `threshold` are ignored, so
+/// activities derived from live events line up with the entries read later. An activity is
+/// reported as started once its facet stayed up for `threshold`, with the time it was
+/// flipped up.
+///
+/// Durations are measured between the events' `received` times, so replayed events are
+/// debounced like live ones. If no further event arrives, the last facet is confirmed by a
+/// timer after `threshold`. When `events` ends, the current activity ends with the last event.
+pub fn debounce(
+    events: BoxStream<'_, Envelope>,
+    threshold: Duration,
+) -> BoxStream<'_, ActivityEvent> {
+    let debouncer = Debouncer {
+        events,
+        threshold,
+        active: None,
+        pending: None,
+        last: None,
+        output: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(debouncer, |mut debouncer| async move {
+        loop {
+            if let Some(event) = debouncer.output.pop_front() {
+                return Some((event, debouncer));
+            }
+            if debouncer.done {
+                return None;
+            }
+
+            let deadline = debouncer.pending.as_ref().map(|(_, deadline)| *deadline);
+            select! {
+                biased;
+                envelope = debouncer.events.next() => match envelope {
+                    Some(envelope) => debouncer.handle(envelope),
+                    None => {
+                        debouncer.pending = None;
+                        if let Some(last) = debouncer.last {
+                            debouncer.end(last);
+                        }
+                        debouncer.done = true;
+                    }
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() => debouncer.confirm(),
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64, event: Event) -> Envelope {
+        Envelope {
+            seq: secs as u64,
+            received: time(secs),
+            clock_offset: None,
+            event,
+        }
+    }
+
+    fn time(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, 0).unwrap()
+    }
+
+    fn facet(n: usize) -> Facet {
+        Facet::new(n).unwrap()
+    }
+
+    fn started(n: usize, secs: i64) -> ActivityEvent {
+        ActivityEvent::Started {
+            facet: facet(n),
+            paused: false,
+            time: time(secs),
+        }
+    }
+
+    fn ended(n: usize, started: i64, ended: i64) -> ActivityEvent {
+        ActivityEvent::Ended {
+            facet: facet(n),
+            paused: false,
+            started: time(started),
+            ended: time(ended),
+            duration: Duration::from_secs((ended - started) as u64),
+        }
+    }
+
+    async fn run(events: Vec<Envelope>) -> Vec<ActivityEvent> {
+        debounce(stream::iter(events).boxed(), DEFAULT_THRESHOLD)
+            .collect()
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wobble() {
+        let events = run(vec![
+            at(0, Event::Facet(facet(1))),
+            at(2, Event::Facet(facet(2))),
+            at(10, Event::Facet(facet(3))),
+            at(12, Event::Facet(facet(4))),
+            at(30, Event::Facet(facet(5))),
+        ])
+        .await;
+        assert_eq!(
+            events,
+            vec![
+                started(2, 2),
+                ended(2, 2, 12),
+                started(4, 12),
+                ended(4, 12, 30)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flip_back() {
+        let events = run(vec![
+            at(0, Event::Facet(facet(1))),
+            at(10, Event::Facet(facet(2))),
+            at(12, Event::Facet(facet(1))),
+            at(30, Event::Facet(facet(3))),
+            at(40, Event::Disconnected),
+        ])
+        .await;
+        assert_eq!(
+            events,
+            vec![
+                started(1, 0),
+                ended(1, 0, 30),
+                started(3, 30),
+                ended(3, 30, 40)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disconnect() {
+        let events = run(vec![
+            at(0, Event::Facet(facet(1))),
+            at(10, Event::Facet(facet(2))),
+            at(12, Event::Disconnected),
+            at(20, Event::Facet(facet(3))),
+        ])
+        .await;
+        assert_eq!(events, vec![started(1, 0), ended(1, 0, 12)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timer_confirms_last_facet() {
+        let events = stream::iter([at(0, Event::Facet(facet(1)))]).chain(stream::pending());
+        let mut activities = debounce(events.boxed(), DEFAULT_THRESHOLD);
+        let start = Instant::now();
+        assert_eq!(activities.next().await, Some(started(1, 0)));
+        assert_eq!(start.elapsed(), DEFAULT_THRESHOLD);
+    }
+}
@@ -0,0 +1,346 @@
+//! Full-screen terminal dashboard following the TimeFlip2.
+
+use chrono::{DateTime, Local, Utc};
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::{stream::BoxStream, StreamExt};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{self, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+use timeflippers::{
+    timeflip::{Entry, Event, TimeFlip},
+    Config, Facet, Percent,
+};
+use tokio::{select, time};
+
+use super::{facet_name, HistoryArgs};
+
+/// Number of lines kept in the event log.
+const LOG_LINES: usize = 200;
+
+fn hms(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+struct Dashboard<'a> {
+    config: &'a Config,
+    facet: Option<Facet>,
+    /// Start of the current activity.
+    since: DateTime<Utc>,
+    paused: bool,
+    locked: bool,
+    battery: Option<Percent>,
+    /// Time spent per facet today, excluding the current activity.
+    today: BTreeMap<Facet, Duration>,
+    log: VecDeque<String>,
+}
+
+impl<'a> Dashboard<'a> {
+    fn log(&mut self, message: impl Into<String>) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(format!(
+            "{} {}",
+            Local::now().format("%H:%M:%S"),
+            message.into()
+        ));
+    }
+
+    fn elapsed(&self) -> Duration {
+        (Utc::now() - self.since).to_std().unwrap_or_default()
+    }
+
+    /// Add the current activity to today's totals and start a new one.
+    fn finish_activity(&mut self) {
+        let elapsed = self.elapsed();
+        if let (Some(facet), false) = (&self.facet, self.paused) {
+            let spent = self.today.entry(facet.clone()).or_default();
+            *spent = spent.saturating_add(elapsed);
+        }
+        self.since = Utc::now();
+    }
+
+    fn set_history(&mut self, entries: &[Entry]) {
+        let today = Local::now().date_naive();
+        self.today.clear();
+        for entry in entries
+            .iter()
+            .filter(|entry| !entry.pause && entry.time.with_timezone(&Local).date_naive() == today)
+        {
+            let spent = self.today.entry(entry.facet.clone()).or_default();
+            *spent = spent.saturating_add(entry.duration);
+        }
+    }
+
+    async fn refresh_history(&mut self, timeflip: &TimeFlip, history: &HistoryArgs) {
+        match history.read(timeflip).await {
+            Ok(entries) => {
+                self.set_history(&entries);
+                self.log(format!("read {} history entries", entries.len()));
+            }
+            Err(e) => self.log(format!("cannot read history: {e}")),
+        }
+    }
+
+    /// Update the state from a TimeFlip2 event, returns `false` if the dice disconnected.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::BatteryLevel(level) => {
+                self.log(format!("battery level {level}"));
+                self.battery = Some(level);
+            }
+            Event::Event(event) => self.log(event.to_string()),
+            Event::SyncState(state) => self.log(format!("sync state {:?}", state.sync)),
+            Event::History(_) => {}
+            Event::Facet(facet) => {
+                self.finish_activity();
+                self.log(format!(
+                    "currently up: {}",
+                    facet_name(&facet, Some(self.config))
+                ));
+                self.facet = Some(facet);
+            }
+            Event::DoubleTap { facet, pause } => {
+                self.finish_activity();
+                self.log(format!(
+                    "{} has {}",
+                    facet_name(&facet, Some(self.config)),
+                    if pause { "paused" } else { "started" }
+                ));
+                self.facet = Some(facet);
+                self.paused = pause;
+            }
+            Event::Disconnected => {
+                self.log("TimeFlip has disconnected");
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn toggle_lock(&mut self, timeflip: &TimeFlip) {
+        let result = if self.locked {
+            timeflip.unlock().await
+        } else {
+            timeflip.lock().await
+        };
+        match result {
+            Ok(()) => {
+                self.locked = !self.locked;
+                self.log(if self.locked { "locked" } else { "unlocked" });
+            }
+            Err(e) => self.log(format!("cannot change lock mode: {e}")),
+        }
+    }
+
+    async fn toggle_pause(&mut self, timeflip: &TimeFlip) {
+        let result = if self.paused {
+            timeflip.unpause().await
+        } else {
+            timeflip.pause().await
+        };
+        match result {
+            Ok(()) => {
+                self.finish_activity();
+                self.paused = !self.paused;
+                self.log(if self.paused { "paused" } else { "unpaused" });
+            }
+            Err(e) => self.log(format!("cannot change pause mode: {e}")),
+        }
+    }
+
+    async fn sync_time(&mut self, timeflip: &TimeFlip) {
+        let now = Local::now();
+        match timeflip.set_time(now.into()).await {
+            Ok(()) => self.log(format!("set time to {}", now.format("%F %T"))),
+            Err(e) => self.log(format!("cannot set time: {e}")),
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status_area, today_area, log_area, help_area] = Layout::vertical([
+            Constraint::Length(5),
+            Constraint::Min(6),
+            Constraint::Length(12),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (name, color) = match &self.facet {
+            Some(facet) => {
+                let (r, g, b) = self.config.sides[facet.index_zero()].color.rgb();
+                (
+                    facet_name(facet, Some(self.config)),
+                    style::Color::Rgb((r >> 8) as u8, (g >> 8) as u8, (b >> 8) as u8),
+                )
+            }
+            None => ("<unknown>".into(), style::Color::Reset),
+        };
+        let mut state = vec![Span::raw(if self.paused { "Paused" } else { "Running" })];
+        if self.locked {
+            state.push(Span::raw(", Locked"));
+        }
+        let status = vec![
+            Line::from(vec![
+                Span::styled("██ ", Style::new().fg(color)),
+                Span::styled(name, Style::new().add_modifier(Modifier::BOLD)),
+            ]),
+            Line::from(
+                [
+                    vec![Span::raw(format!("Elapsed: {}   ", hms(self.elapsed())))],
+                    state,
+                ]
+                .concat(),
+            ),
+            Line::from(format!(
+                "Battery: {}",
+                self.battery
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or("unknown".into())
+            )),
+        ];
+        frame.render_widget(
+            Paragraph::new(status).block(Block::bordered().title(" TimeFlip ")),
+            status_area,
+        );
+
+        let mut today = self.today.clone();
+        if let (Some(facet), false) = (&self.facet, self.paused) {
+            let spent = today.entry(facet.clone()).or_default();
+            *spent = spent.saturating_add(self.elapsed());
+        }
+        let total = today
+            .values()
+            .fold(Duration::ZERO, |a, b| a.saturating_add(*b));
+        let rows = today
+            .iter()
+            .map(|(facet, spent)| Row::new([facet_name(facet, Some(self.config)), hms(*spent)]))
+            .chain([Row::new(["Total".to_string(), hms(total)])
+                .style(Style::new().add_modifier(Modifier::BOLD))]);
+        frame.render_widget(
+            Table::new(rows, [Constraint::Min(20), Constraint::Length(10)])
+                .header(
+                    Row::new(["Side", "Today"]).style(Style::new().add_modifier(Modifier::BOLD)),
+                )
+                .block(Block::bordered().title(" Today ")),
+            today_area,
+        );
+
+        let visible = usize::from(log_area.height.saturating_sub(2));
+        let items: Vec<ListItem> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Events ")),
+            log_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(
+                "l lock/unlock · p pause/unpause · t sync time · r refresh history · q quit",
+            )
+            .style(Style::new().add_modifier(Modifier::DIM)),
+            help_area,
+        );
+    }
+
+    async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        timeflip: &TimeFlip,
+        history: &HistoryArgs,
+        events: &mut BoxStream<'_, Event>,
+    ) -> anyhow::Result<()> {
+        let mut keys = EventStream::new();
+        let mut interval = time::interval(Duration::from_secs(1));
+
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            select! {
+                event = events.next() => match event {
+                    Some(event) => {
+                        if !self.handle_event(event) {
+                            terminal.draw(|frame| self.draw(frame))?;
+                            time::sleep(Duration::from_secs(2)).await;
+                            return Ok(());
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                key = keys.next() => match key {
+                    Some(Ok(TermEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                        match key.code {
+                            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                return Ok(())
+                            }
+                            KeyCode::Char('l') => self.toggle_lock(timeflip).await,
+                            KeyCode::Char('p') => self.toggle_pause(timeflip).await,
+                            KeyCode::Char('t') => self.sync_time(timeflip).await,
+                            KeyCode::Char('r') => self.refresh_history(timeflip, history).await,
+                            _ => {}
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Ok(()),
+                },
+                _ = interval.tick() => {}
+            }
+        }
+    }
+}
+
+/// Show the dashboard until the user quits or the TimeFlip2 disconnects.
+pub async fn run(
+    timeflip: &TimeFlip,
+    config: &Config,
+    history: &HistoryArgs,
+) -> anyhow::Result<()> {
+    timeflip.subscribe_battery_level().await?;
+    timeflip.subscribe_events().await?;
+    timeflip.subscribe_facet().await?;
+    timeflip.subscribe_double_tap().await?;
+
+    let status = timeflip.system_status().await?;
+    let facet = timeflip.facet().await?;
+    let elapsed = super::facet_elapsed(timeflip, &facet).await?;
+    let mut dashboard = Dashboard {
+        config,
+        facet: Some(facet),
+        since: Utc::now() - chrono::Duration::from_std(elapsed)?,
+        paused: status.pause_mode,
+        locked: status.lock_mode,
+        battery: Some(timeflip.battery_level().await?),
+        today: BTreeMap::new(),
+        log: VecDeque::with_capacity(LOG_LINES),
+    };
+    dashboard.refresh_history(timeflip, history).await;
+
+    let mut events = timeflip.event_stream().await?;
+    let mut terminal = ratatui::init();
+    let result = dashboard
+        .run(&mut terminal, timeflip, history, &mut events)
+        .await;
+    ratatui::restore();
+    result
+}
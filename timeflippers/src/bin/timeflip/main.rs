@@ -0,0 +1,916 @@
+use anyhow::format_err;
+use chrono::{offset::Local, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures::stream::{self, StreamExt};
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use timeflippers::{
+    activity::{self, ActivityEvent},
+    budget::{BudgetEvent, BudgetTracker},
+    daemon, history,
+    pomodoro::{PomodoroRecord, PomodoroTracker},
+    timeflip::{
+        journal::{self, Journal},
+        Entry, Event, TimeFlip,
+    },
+    view, BluetoothSession, Config, Facet,
+};
+use tokio::{fs, process, select, signal, sync::mpsc, time};
+
+mod dashboard;
+mod status_bar;
+
+async fn read_config(path: impl AsRef<Path>) -> anyhow::Result<Config> {
+    let toml = fs::read_to_string(path).await?;
+    let config: Config = toml::from_str(&toml)?;
+    Ok(config)
+}
+
+fn facet_name(facet: &Facet, config: Option<&Config>) -> String {
+    config
+        .and_then(|config| config.sides[facet.index_zero()].name.clone())
+        .unwrap_or(facet.to_string())
+}
+
+/// Print an event in the format of `timeflip notify`.
+fn print_event(event: &Event, config: Option<&Config>) {
+    match event {
+        Event::BatteryLevel(percent) => println!("Battery Level {percent}"),
+        Event::Event(event) => println!("{event}"),
+        Event::Facet(facet) => println!("Currently Up: {}", facet_name(facet, config)),
+        Event::DoubleTap { facet, pause } => println!(
+            "Facet {} has {}",
+            facet_name(facet, config),
+            if *pause { "paused" } else { "started" }
+        ),
+        Event::SyncState(state) => println!("Sync state: {state:?}"),
+        Event::History(entry) => println!("{entry}"),
+        Event::Disconnected => println!("TimeFlip has disconnected"),
+    }
+}
+
+/// Print an activity in the format of `timeflip activities`.
+fn print_activity(event: &ActivityEvent, config: Option<&Config>) {
+    match event {
+        ActivityEvent::Started {
+            facet,
+            paused,
+            time,
+        } => println!(
+            "{} {} started{}",
+            time.with_timezone(&Local).format("%H:%M:%S"),
+            facet_name(facet, config),
+            if *paused { " (paused)" } else { "" }
+        ),
+        ActivityEvent::Ended {
+            facet,
+            paused,
+            ended,
+            duration,
+            ..
+        } => println!(
+            "{} {} ended{} after {} seconds",
+            ended.with_timezone(&Local).format("%H:%M:%S"),
+            facet_name(facet, config),
+            if *paused { " (paused)" } else { "" },
+            duration.as_secs()
+        ),
+    }
+}
+
+/// Print the events of a journal, and optionally the activities derived from them, and run
+/// the configured hooks on them.
+async fn replay(
+    path: &Path,
+    config: Option<Config>,
+    hooks: bool,
+    activities: Option<Duration>,
+    realtime: bool,
+) -> anyhow::Result<()> {
+    let hooks = match hooks {
+        true => Some(
+            config
+                .clone()
+                .ok_or(format_err!("config is mandatory for running hooks"))?,
+        ),
+        false => None,
+    };
+    let envelopes = journal::read(path).await?;
+    let mut previous = None;
+    let delayed: Vec<_> = envelopes
+        .into_iter()
+        .map(|envelope| {
+            let delay = match previous.replace(envelope.received) {
+                Some(previous) if realtime => {
+                    (envelope.received - previous).to_std().unwrap_or_default()
+                }
+                _ => Duration::ZERO,
+            };
+            (delay, envelope)
+        })
+        .collect();
+
+    let events = stream::iter(delayed)
+        .then(|(delay, envelope)| async move {
+            time::sleep(delay).await;
+            envelope
+        })
+        .inspect(|envelope| {
+            print!(
+                "#{} {} ",
+                envelope.seq,
+                envelope.received.with_timezone(&Local).format("%F %T%.3f")
+            );
+            print_event(&envelope.event, config.as_ref());
+        });
+
+    // The hooks run on their own, so they do not hold up the printed events and activities.
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let events = events
+        .inspect(move |envelope| {
+            let _ = tx.send(envelope.clone());
+        })
+        .boxed();
+    let run_hooks = async {
+        match hooks {
+            Some(config) => {
+                let envelopes = stream::unfold(rx, |mut rx| async move {
+                    rx.recv().await.map(|envelope| (envelope, rx))
+                });
+                daemon::replay_hooks(config, envelopes.boxed()).await;
+            }
+            None => rx.close(),
+        }
+    };
+    let print = async {
+        match activities {
+            Some(threshold) => {
+                let mut activities = activity::debounce(events, threshold);
+                while let Some(event) = activities.next().await {
+                    print_activity(&event, config.as_ref());
+                }
+            }
+            None => events.for_each(|_| async {}).await,
+        }
+    };
+    tokio::join!(print, run_hooks);
+    Ok(())
+}
+
+/// Communicate with a TimeFlip2 cube.
+///
+/// Note: Use `bluetoothctl` to pair (and potentially connect) the TimeFlip2.
+/// Currently, the TimeFlip2's password is expected to be the default value.
+#[derive(Parser)]
+#[clap(about)]
+struct Options {
+    #[arg(short, long, help = "path to the timeflip.toml file")]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "append the TimeFlip2's notifications to this journal file"
+    )]
+    journal: Option<PathBuf>,
+    #[command(subcommand)]
+    cmd: AnyCommand,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum HistoryStyle {
+    Lines,
+    Tabular,
+    Summarized,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum InvoiceFormat {
+    Html,
+    Markdown,
+}
+
+impl From<InvoiceFormat> for view::InvoiceFormat {
+    fn from(format: InvoiceFormat) -> Self {
+        match format {
+            InvoiceFormat::Html => view::InvoiceFormat::Html,
+            InvoiceFormat::Markdown => view::InvoiceFormat::Markdown,
+        }
+    }
+}
+
+/// Arguments selecting the history entries to process.
+#[derive(Args)]
+struct HistoryArgs {
+    #[arg(long, help = "read events from and write new events to file")]
+    update: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "start reading with entry ID, latest event in `--update` takes precedence"
+    )]
+    start_with: Option<u32>,
+    #[arg(long, help = "start displaying with entries after DATE (YYYY-MM-DD)")]
+    since: Option<NaiveDate>,
+    #[arg(
+        long,
+        help = "stop displaying with entries of DATE (YYYY-MM-DD), including that day"
+    )]
+    until: Option<NaiveDate>,
+}
+
+impl HistoryArgs {
+    /// Read new entries from the TimeFlip2, merged with the ones in `--update`.
+    async fn read(&self, timeflip: &TimeFlip) -> anyhow::Result<Vec<Entry>> {
+        Ok(history::update(timeflip, self.update.as_deref(), self.start_with).await?)
+    }
+
+    fn dates(&self) -> view::DateRange {
+        view::DateRange {
+            since: self.since,
+            until: self.until,
+        }
+    }
+
+    /// Select the entries in the range given by `--since` and `--until`.
+    fn filter<'a>(&self, history: &'a view::History) -> view::HistoryFiltered<'a> {
+        self.dates().filter(history)
+    }
+
+    /// Like [HistoryArgs::filter], but keep entries in pause mode.
+    fn range<'a>(&self, history: &'a view::History) -> view::HistoryFiltered<'a> {
+        self.dates().range(history)
+    }
+}
+
+/// Get the invoice number following the one saved in `path`.
+///
+/// The number is only saved by [save_invoice_number], once the invoice has been written, so
+/// failures do not leave gaps in the numbering.
+async fn next_invoice_number(path: &Path) -> anyhow::Result<u32> {
+    let last = match fs::read_to_string(path).await {
+        Ok(s) => s.trim().parse::<u32>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    Ok(last + 1)
+}
+
+/// Save the number of the invoice written last to `path`.
+async fn save_invoice_number(path: &Path, number: u32) -> anyhow::Result<()> {
+    fs::write(path, format!("{number}\n")).await?;
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum AnyCommand {
+    #[command(flatten)]
+    Device(Command),
+    #[command(flatten)]
+    Local(LocalCommand),
+}
+
+/// Commands not connecting to the TimeFlip2 itself.
+#[derive(Subcommand)]
+enum LocalCommand {
+    /// Print the events of a journal recorded with `--journal` and optionally run hooks.
+    ///
+    /// Does not connect to the TimeFlip2 itself.
+    Replay {
+        #[arg(help = "path to the journal file")]
+        journal: PathBuf,
+        #[arg(long, help = "run the configured hooks on the replayed events")]
+        hooks: bool,
+        #[arg(
+            long,
+            value_name = "THRESHOLD",
+            help = "print activities lasting at least THRESHOLD seconds, like `activities` does"
+        )]
+        activities: Option<u64>,
+        #[arg(long, help = "wait between events as long as when they were recorded")]
+        realtime: bool,
+    },
+    /// Print the current side for status bars, as reported by `timeflip daemon --listen`.
+    ///
+    /// Does not connect to the TimeFlip2 itself.
+    StatusBar {
+        #[arg(
+            long,
+            default_value = "http://127.0.0.1:7070",
+            help = "URL of the daemon's HTTP API"
+        )]
+        daemon: String,
+        #[arg(
+            long,
+            help = "bearer token of the daemon's HTTP API, defaults to daemon.token of the config"
+        )]
+        token: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        format: status_bar::Format,
+        #[arg(long, default_value_t = 1, help = "seconds between updates")]
+        interval: u64,
+    },
+}
+
+impl LocalCommand {
+    async fn run(self, config: Option<Config>) -> anyhow::Result<()> {
+        match self {
+            LocalCommand::Replay {
+                journal,
+                hooks,
+                activities,
+                realtime,
+            } => {
+                let activities = activities.map(Duration::from_secs);
+                replay(&journal, config, hooks, activities, realtime).await
+            }
+            LocalCommand::StatusBar {
+                daemon,
+                token,
+                format,
+                interval,
+            } => {
+                let token = token.or_else(|| config.and_then(|config| config.daemon.token));
+                status_bar::run(
+                    &daemon,
+                    token.as_deref(),
+                    format,
+                    Duration::from_secs(interval),
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Commands talking to the TimeFlip2.
+#[derive(Subcommand)]
+enum Command {
+    /// Print activities as they start and end, ignoring flips the history would ignore.
+    Activities {
+        #[arg(
+            long,
+            default_value_t = activity::DEFAULT_THRESHOLD.as_secs(),
+            help = "seconds a facet has to be up to count as an activity"
+        )]
+        threshold: u64,
+    },
+    /// Print the current battery level.
+    Battery,
+    /// Share the TimeFlip2 with other programs, e.g. through an HTTP API.
+    Daemon {
+        #[arg(long, help = "serve the HTTP API on ADDRESS, e.g. 127.0.0.1:7070")]
+        listen: Option<SocketAddr>,
+        #[arg(
+            long,
+            help = "only log the actions of rules and scripts instead of taking them"
+        )]
+        dry_run: bool,
+    },
+    /// Show a full-screen dashboard of the TimeFlip2's state and today's tracked time.
+    Dashboard {
+        #[command(flatten)]
+        history: HistoryArgs,
+    },
+    /// Print the TimeFlip2's manufacturer, model, serial number and revisions.
+    Info,
+    /// Print logged TimeFlip events.
+    History {
+        #[command(flatten)]
+        history: HistoryArgs,
+        #[arg(long, help = "choose output style", default_value = "tabular")]
+        style: HistoryStyle,
+    },
+    /// Print the overtime balance against the configured working time targets.
+    Balance {
+        #[command(flatten)]
+        history: HistoryArgs,
+    },
+    /// Check working times and breaks against the configured compliance rules.
+    Compliance {
+        #[command(flatten)]
+        history: HistoryArgs,
+    },
+    /// Generate an invoice from logged TimeFlip events.
+    Invoice {
+        #[command(flatten)]
+        history: HistoryArgs,
+        #[arg(
+            long,
+            help = "invoice number, defaults to the next number in `number_file`"
+        )]
+        number: Option<u32>,
+        #[arg(long, help = "choose output format", default_value = "markdown")]
+        format: InvoiceFormat,
+        #[arg(long, help = "write the invoice to file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    /// Print the facet currently facing up.
+    Facet,
+    /// Put the TimeFlip2 in lock mode.
+    Lock,
+    /// Release the TimeFlip2 from lock mode.
+    Unlock,
+    /// Subscribe to properties and get notified if they change.
+    Notify {
+        #[arg(long, help = "listen for battery events")]
+        battery: bool,
+        #[arg(long, help = "listen for facet events")]
+        facet: bool,
+        #[arg(long, help = "listen for double-tap events")]
+        double_tap: bool,
+        #[arg(long, help = "listen for log events")]
+        log_event: bool,
+        #[arg(long, help = "listen for synchronization state events")]
+        sync_state: bool,
+        #[arg(long, help = "listen for history entries")]
+        history: bool,
+        #[arg(
+            long,
+            help = "prefix events with their sequence number, receive time and device time"
+        )]
+        timestamps: bool,
+    },
+    /// Put the TimeFlip2 into pause mode.
+    Pause,
+    /// Release the TimeFlip2 from pause mode.
+    Unpause,
+    /// Follow the pomodoro timers of the sides and record completed and interrupted ones.
+    Pomodoro {
+        #[arg(long, help = "read pomodoros from and record new pomodoros to file")]
+        log: Option<PathBuf>,
+        #[arg(long, help = "shell command to run when a pomodoro is completed")]
+        on_complete: Option<String>,
+        #[arg(
+            long,
+            help = "print the pomodoros per side and day recorded in `--log`"
+        )]
+        report: bool,
+    },
+    /// Print the TimeFlip2's system status.
+    Status,
+    /// Get the TimeFlip2's synchronization state.
+    SyncState,
+    /// Synchronize TimeFlip2. Do nothing if the cube reports it is synchronized.
+    Sync,
+    /// Get the TimeFlip2's current time.
+    Time {
+        #[arg(long, help = "set TimeFlip2's time to the current time")]
+        set: bool,
+    },
+    /// Track facets live and warn when a side exceeds its time budget.
+    Watch {
+        #[command(flatten)]
+        history: HistoryArgs,
+        #[arg(
+            long,
+            help = "do not change the LED color of sides exceeding their budget"
+        )]
+        no_color: bool,
+    },
+    /// Write config from the toml file to the TimeFlip2's memory.
+    WriteConfig,
+}
+
+impl Command {
+    async fn run(&self, timeflip: &mut TimeFlip, config: Option<Config>) -> anyhow::Result<()> {
+        use Command::*;
+        match self {
+            Activities { threshold } => {
+                timeflip.subscribe_facet().await?;
+                timeflip.subscribe_double_tap().await?;
+                let events = timeflip.envelope_stream(None).await?;
+                let mut activities = activity::debounce(events, Duration::from_secs(*threshold));
+                while let Some(event) = activities.next().await {
+                    print_activity(&event, config.as_ref());
+                }
+            }
+            Battery => {
+                println!("Battery level: {}", timeflip.battery_level().await?);
+            }
+            Daemon { listen, dry_run } => {
+                let mut config = config.unwrap_or_default();
+                if listen.is_some() {
+                    config.daemon.listen = *listen;
+                }
+                config.daemon.dry_run |= dry_run;
+                daemon::Daemon::new(timeflip.clone(), config).run().await?;
+            }
+            Dashboard { history } => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                dashboard::run(timeflip, &config, history).await?;
+            }
+            History { history, style } => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                let entries = history.read(timeflip).await?;
+
+                let all = view::History::new(entries, config);
+                let filtered = history.filter(&all);
+                use HistoryStyle::*;
+                match style {
+                    Lines => println!("{}", filtered),
+                    Tabular => println!("{}", filtered.table_by_day()),
+                    Summarized => println!("{}", filtered.summarized()),
+                }
+            }
+            Balance { history } => {
+                let mut config =
+                    config.ok_or(format_err!("config is mandatory for this command"))?;
+                let targets = config
+                    .targets
+                    .take()
+                    .ok_or(format_err!("config has no [targets] section"))?;
+
+                let mut days_off = view::DaysOff::default();
+                for (path, description) in [
+                    (&targets.holidays, "Holiday"),
+                    (&targets.vacation, "Vacation"),
+                ] {
+                    if let Some(path) = path {
+                        let list = fs::read_to_string(path).await?;
+                        days_off.extend(view::DaysOff::parse(&list, description)?);
+                    }
+                }
+
+                let entries = history.read(timeflip).await?;
+                let all = view::History::new(entries, config);
+                let balance =
+                    history
+                        .filter(&all)
+                        .balance(&targets, &days_off, Local::now().date_naive());
+                println!("{balance}");
+            }
+            Compliance { history } => {
+                let mut config =
+                    config.ok_or(format_err!("config is mandatory for this command"))?;
+                let rules = config.compliance.take().unwrap_or_default();
+
+                let entries = history.read(timeflip).await?;
+                let all = view::History::new(entries, config);
+                println!("{}", history.range(&all).compliance(&rules));
+            }
+            Invoice {
+                history,
+                number,
+                format,
+                output,
+            } => {
+                let mut config =
+                    config.ok_or(format_err!("config is mandatory for this command"))?;
+                let invoice_config = config
+                    .invoice
+                    .take()
+                    .ok_or(format_err!("config has no [invoice] section"))?;
+                let format = view::InvoiceFormat::from(*format);
+                let template = match format {
+                    view::InvoiceFormat::Html => invoice_config.html_template.as_ref(),
+                    view::InvoiceFormat::Markdown => invoice_config.markdown_template.as_ref(),
+                };
+                let template = match template {
+                    Some(path) => fs::read_to_string(path).await?,
+                    None => format.builtin_template().to_string(),
+                };
+
+                let entries = history.read(timeflip).await?;
+                let (number, number_file) = match (number, &invoice_config.number_file) {
+                    (Some(number), _) => (*number, None),
+                    (None, Some(path)) => (next_invoice_number(path).await?, Some(path.clone())),
+                    (None, None) => {
+                        return Err(format_err!(
+                            "either pass `--number` or configure `number_file`"
+                        ))
+                    }
+                };
+
+                let all = view::History::new(entries, config);
+                let invoice = history.filter(&all).invoice(&invoice_config, number);
+                let rendered = invoice.render(format, &template)?;
+                match output {
+                    Some(path) => fs::write(path, rendered).await?,
+                    None => {
+                        let mut stdout = io::stdout().lock();
+                        writeln!(stdout, "{rendered}")?;
+                        stdout.flush()?;
+                    }
+                }
+                if let Some(path) = number_file {
+                    save_invoice_number(&path, number).await?;
+                }
+            }
+            Facet => {
+                let facet = timeflip.facet().await?;
+                println!("Currently up: {}", facet_name(&facet, config.as_ref()));
+            }
+            Info => {
+                println!("{}", timeflip.device_info().await?);
+                println!("Protocol profile: {}", timeflip.profile().name);
+            }
+            Lock => timeflip.lock().await?,
+            Unlock => timeflip.unlock().await?,
+            Notify {
+                battery,
+                facet,
+                double_tap,
+                log_event,
+                sync_state,
+                history,
+                timestamps,
+            } => {
+                if *battery {
+                    timeflip.subscribe_battery_level().await?;
+                }
+                if *facet {
+                    timeflip.subscribe_facet().await?;
+                }
+                if *double_tap {
+                    timeflip.subscribe_double_tap().await?;
+                }
+                if *log_event {
+                    timeflip.subscribe_events().await?;
+                }
+                if *sync_state {
+                    timeflip.subscribe_sync_state().await?;
+                }
+                if *history {
+                    timeflip.subscribe_history().await?;
+                }
+
+                let clock_offset = if *timestamps {
+                    Some(timeflip.clock_offset().await?)
+                } else {
+                    None
+                };
+                let mut stream = timeflip.envelope_stream(clock_offset).await?;
+                while let Some(envelope) = stream.next().await {
+                    if *timestamps {
+                        let format = "%H:%M:%S%.3f";
+                        print!(
+                            "#{} {} ",
+                            envelope.seq,
+                            envelope.received.with_timezone(&Local).format(format)
+                        );
+                        if let Some(time) = envelope.device_time() {
+                            print!("(device {}) ", time.with_timezone(&Local).format(format));
+                        }
+                    }
+                    print_event(&envelope.event, config.as_ref());
+                    if envelope.event == Event::Disconnected {
+                        break;
+                    }
+                }
+            }
+            Pause => timeflip.pause().await?,
+            Unpause => timeflip.unpause().await?,
+            Pomodoro {
+                log,
+                on_complete,
+                report,
+            } => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                let mut records: Vec<PomodoroRecord> = match log {
+                    Some(path) => match fs::read_to_string(path).await {
+                        Ok(s) => serde_json::from_str(&s)?,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                        Err(e) => return Err(e.into()),
+                    },
+                    None => vec![],
+                };
+                if *report {
+                    println!("{}", view::PomodoroReport::new(&records, &config));
+                    return Ok(());
+                }
+
+                let mut tracker = PomodoroTracker::new(&config);
+                timeflip.subscribe_facet().await?;
+                timeflip.subscribe_double_tap().await?;
+                if !timeflip.system_status().await?.pause_mode {
+                    let facet = timeflip.facet().await?;
+                    let elapsed = pomodoro_elapsed(timeflip, &tracker, &facet).await?;
+                    tracker.start(facet, elapsed, Utc::now());
+                }
+
+                let mut stream = timeflip.event_stream().await?;
+                let mut interval = time::interval(Duration::from_secs(1));
+                loop {
+                    let finished = select! {
+                        event = stream.next() => match event {
+                            Some(Event::Facet(facet))
+                            | Some(Event::DoubleTap { facet, pause: false }) => {
+                                let elapsed = pomodoro_elapsed(timeflip, &tracker, &facet).await?;
+                                tracker.start(facet, elapsed, Utc::now())
+                            }
+                            Some(Event::DoubleTap { pause: true, .. }) => tracker.stop(Utc::now()),
+                            Some(Event::Disconnected) | None => {
+                                println!("TimeFlip has disconnected");
+                                break;
+                            }
+                            Some(Event::BatteryLevel(_))
+                            | Some(Event::Event(_))
+                            | Some(Event::SyncState(_))
+                            | Some(Event::History(_)) => None,
+                        },
+                        _ = interval.tick() => tracker.tick(Utc::now()),
+                    };
+
+                    if let Some(record) = finished {
+                        let name = facet_name(&record.facet, Some(&config));
+                        if record.completed {
+                            println!("\r\x07{name}: pomodoro completed      ");
+                            if let Some(command) = on_complete {
+                                run_hook(command, &record.facet, &name);
+                            }
+                        } else {
+                            println!("\r{name}: pomodoro interrupted      ");
+                        }
+
+                        records.push(record);
+                        if let Some(path) = log {
+                            fs::write(path, serde_json::to_vec(&records)?).await?;
+                        }
+                    }
+
+                    if let Some(pomodoro) = tracker.current() {
+                        let remaining = pomodoro.remaining(Utc::now()).as_secs();
+                        print!(
+                            "\r{}: {:02}:{:02} left ",
+                            facet_name(&pomodoro.facet, Some(&config)),
+                            remaining / 60,
+                            remaining % 60
+                        );
+                        io::stdout().flush()?;
+                    }
+                }
+            }
+            Status => {
+                println!("System status: {:?}", timeflip.system_status().await?);
+            }
+            SyncState => {
+                println!("Sync state: {:?}", timeflip.sync_state().await?);
+            }
+            Sync => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                timeflip.sync(&config).await?;
+            }
+            Time { set } => {
+                if *set {
+                    let now = Local::now();
+                    println!("Setting time to: {now}");
+                    timeflip.set_time(now.into()).await?;
+                } else {
+                    let tz = Local::now().timezone();
+                    let time = timeflip.time().await?;
+                    println!("Time set on TimeFlip: {}", time.with_timezone(&tz));
+                }
+            }
+            Watch { history, no_color } => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                let entries = history.read(timeflip).await?;
+                let mut tracker = BudgetTracker::new(&config, &entries);
+
+                timeflip.subscribe_facet().await?;
+                timeflip.subscribe_double_tap().await?;
+                if !timeflip.system_status().await?.pause_mode {
+                    let facet = timeflip.facet().await?;
+                    let elapsed = facet_elapsed(timeflip, &facet).await?;
+                    tracker.start(facet, Utc::now() - chrono::Duration::from_std(elapsed)?);
+                }
+
+                let mut stream = timeflip.event_stream().await?;
+                let mut interval = time::interval(Duration::from_secs(10));
+                loop {
+                    select! {
+                        event = stream.next() => match event {
+                            Some(Event::Disconnected) | None => {
+                                println!("TimeFlip has disconnected");
+                                break;
+                            }
+                            Some(event) => {
+                                tracker.handle_event(&event, Utc::now());
+                                if let Event::Facet(facet) = &event {
+                                    if let Some((used, limit)) = tracker.spent(facet, Utc::now()) {
+                                        println!(
+                                            "Currently Up: {} ({} of {} minutes used)",
+                                            facet_name(facet, Some(&config)),
+                                            used.as_secs() / 60,
+                                            limit.as_secs() / 60,
+                                        );
+                                    }
+                                }
+                            }
+                        },
+                        _ = interval.tick() => {}
+                    }
+
+                    for event in tracker.check(Utc::now()) {
+                        match event {
+                            BudgetEvent::Exceeded {
+                                facet,
+                                used,
+                                limit,
+                                color,
+                            } => {
+                                println!(
+                                    "Budget of {} exceeded: {} of {} minutes used",
+                                    facet_name(&facet, Some(&config)),
+                                    used.as_secs() / 60,
+                                    limit.as_secs() / 60,
+                                );
+                                if let (Some(color), false) = (color, *no_color) {
+                                    if let Err(e) = timeflip.color(facet, color).await {
+                                        log::error!("cannot signal exceeded budget: {e}");
+                                    }
+                                }
+                            }
+                            BudgetEvent::Reset { facet, color } => {
+                                log::info!("new budget period for {facet}");
+                                if !*no_color {
+                                    if let Err(e) = timeflip.color(facet, color).await {
+                                        log::error!("cannot restore the color: {e}");
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            WriteConfig => {
+                let config = config.ok_or(format_err!("config is mandatory for this command"))?;
+                timeflip.write_config(config).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The time the facet's pomodoro timer has been running, as reported by TimeFlip2.
+async fn pomodoro_elapsed(
+    timeflip: &TimeFlip,
+    tracker: &PomodoroTracker,
+    facet: &Facet,
+) -> anyhow::Result<Duration> {
+    if tracker.length(facet).is_none() {
+        return Ok(Duration::ZERO);
+    }
+    facet_elapsed(timeflip, facet).await
+}
+
+/// The time `facet` has been facing up, as reported by TimeFlip2.
+async fn facet_elapsed(timeflip: &TimeFlip, facet: &Facet) -> anyhow::Result<Duration> {
+    let settings = timeflip.get_task(facet.clone()).await?;
+    Ok(Duration::from_secs(settings.seconds_since_start.into()))
+}
+
+/// Run a shell command in the background, passing the facet in the environment.
+fn run_hook(command: &str, facet: &Facet, name: &str) {
+    let result = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("TIMEFLIP_FACET", facet.index().to_string())
+        .env("TIMEFLIP_SIDE", name)
+        .spawn();
+    if let Err(e) = result {
+        log::error!("cannot run `{command}`: {e}");
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let opt = Options::parse();
+    let config = if let Some(path) = opt.config {
+        Some(read_config(path).await?)
+    } else {
+        None
+    };
+
+    let cmd = match opt.cmd {
+        AnyCommand::Local(cmd) => return cmd.run(config).await,
+        AnyCommand::Device(cmd) => cmd,
+    };
+
+    let (mut bg_task, session) = BluetoothSession::new().await?;
+
+    let mut timeflip = TimeFlip::connect(&session).await?;
+    log::info!("connected");
+    if let Some(path) = opt.journal {
+        timeflip.record(Journal::open(path)?);
+    }
+
+    select! {
+        _ = signal::ctrl_c() => {
+            log::info!("shutting down");
+        }
+        res = &mut bg_task => {
+            if let Err(e) =res {
+                log::error!("bluetooth session background task exited with error: {e}");
+            }
+        }
+        res = cmd.run(&mut timeflip, config) => {
+            res?;
+        }
+    }
+
+    Ok(())
+}
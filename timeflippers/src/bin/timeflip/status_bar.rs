@@ -0,0 +1,232 @@
+//! Status bar output following the daemon's activity.
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde_json::json;
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+use timeflippers::{daemon::Activity, Color};
+use tokio::time;
+
+/// Status bar protocols.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// JSON lines for waybar's custom module with pango markup.
+    #[default]
+    Waybar,
+    /// The i3bar protocol, also used by swaybar.
+    I3bar,
+    /// Plain lines with polybar's color tags.
+    Polybar,
+    /// Plain lines with tmux's style tags.
+    Tmux,
+}
+
+/// The color as `#rrggbb`.
+fn hex(color: &Color) -> String {
+    let (r, g, b) = color.rgb();
+    format!("#{:02x}{:02x}{:02x}", r >> 8, g >> 8, b >> 8)
+}
+
+fn text(activity: &Activity, now: DateTime<Utc>) -> String {
+    let seconds = (now - activity.since).num_seconds().max(0);
+    let name = activity
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("Side {}", activity.facet.index()));
+    format!(
+        "{}{name} {}:{:02}:{:02}",
+        if activity.paused { "⏸ " } else { "" },
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+fn escape_markup(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Render a status line for `activity` at `now`, `activity` is `None` if the daemon is
+/// unreachable or the TimeFlip2 is disconnected.
+fn line(format: Format, activity: Option<&Activity>, now: DateTime<Utc>) -> String {
+    const OFFLINE: &str = "TimeFlip offline";
+
+    match (format, activity) {
+        (Format::Waybar, Some(activity)) => json!({
+            "text": format!(
+                "<span color=\"{}\">{}</span>",
+                hex(&activity.color),
+                escape_markup(&text(activity, now))
+            ),
+            "class": if activity.paused { "paused" } else { "active" },
+        })
+        .to_string(),
+        (Format::Waybar, None) => json!({ "text": OFFLINE, "class": "offline" }).to_string(),
+        (Format::I3bar, Some(activity)) => format!(
+            "{},",
+            json!([{
+                "name": "timeflip",
+                "full_text": text(activity, now),
+                "color": hex(&activity.color),
+            }])
+        ),
+        (Format::I3bar, None) => {
+            format!("{},", json!([{ "name": "timeflip", "full_text": OFFLINE }]))
+        }
+        (Format::Polybar, Some(activity)) => {
+            format!(
+                "%{{F{}}}{}%{{F-}}",
+                hex(&activity.color),
+                text(activity, now)
+            )
+        }
+        (Format::Tmux, Some(activity)) => {
+            format!(
+                "#[fg={}]{}#[default]",
+                hex(&activity.color),
+                text(activity, now)
+            )
+        }
+        (Format::Polybar | Format::Tmux, None) => OFFLINE.to_string(),
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str, token: Option<&str>) -> Option<Activity> {
+    let result = async {
+        let mut request = client.get(format!("{url}/activity"));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Option<Activity>>()
+            .await
+    }
+    .await;
+    match result {
+        Ok(activity) => activity,
+        Err(e) => {
+            log::debug!("cannot read activity from daemon: {e}");
+            None
+        }
+    }
+}
+
+/// Print a status line every `interval`, reading the activity from the daemon at `url`.
+pub async fn run(
+    url: &str,
+    token: Option<&str>,
+    format: Format,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder().timeout(interval).build()?;
+    let url = url.trim_end_matches('/');
+
+    let mut stdout = io::stdout();
+    if let Format::I3bar = format {
+        writeln!(stdout, "{}", json!({ "version": 1 }))?;
+        writeln!(stdout, "[")?;
+    }
+
+    let mut interval = time::interval(interval);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let activity = fetch(&client, url, token).await;
+        writeln!(stdout, "{}", line(format, activity.as_ref(), Utc::now()))?;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use timeflippers::Facet;
+
+    fn activity(name: Option<&str>, paused: bool) -> Activity {
+        Activity {
+            facet: Facet::new(3).unwrap(),
+            name: name.map(Into::into),
+            color: Color::from_rgb(0xff00, 0x8000, 0x0000),
+            paused,
+            since: Utc.timestamp_opt(1_000_000, 0).unwrap(),
+        }
+    }
+
+    /// 1 hour, 2 minutes and 5 seconds after the activity started.
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_opt(1_003_725, 0).unwrap()
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_markup("R&D <b>\"x\"</b>"),
+            "R&amp;D &lt;b&gt;\"x\"&lt;/b&gt;"
+        );
+        assert_eq!(escape_markup("Work"), "Work");
+    }
+
+    #[test]
+    fn texts() {
+        assert_eq!(text(&activity(Some("Work"), false), now()), "Work 1:02:05");
+        assert_eq!(text(&activity(None, true), now()), "⏸ Side 3 1:02:05");
+        // The daemon's clock may be ahead of the status bar's.
+        let early = Utc.timestamp_opt(999_000, 0).unwrap();
+        assert_eq!(text(&activity(Some("Work"), false), early), "Work 0:00:00");
+    }
+
+    #[test]
+    fn lines() {
+        let work = activity(Some("R&D"), false);
+        let paused = activity(Some("R&D"), true);
+
+        let waybar: serde_json::Value =
+            serde_json::from_str(&line(Format::Waybar, Some(&work), now())).unwrap();
+        assert_eq!(
+            waybar,
+            json!({
+                "text": "<span color=\"#ff8000\">R&amp;D 1:02:05</span>",
+                "class": "active",
+            })
+        );
+        let waybar: serde_json::Value =
+            serde_json::from_str(&line(Format::Waybar, Some(&paused), now())).unwrap();
+        assert_eq!(waybar["class"], "paused");
+        assert_eq!(
+            line(Format::Waybar, None, now()),
+            r#"{"class":"offline","text":"TimeFlip offline"}"#
+        );
+
+        let i3bar = line(Format::I3bar, Some(&work), now());
+        let i3bar: serde_json::Value =
+            serde_json::from_str(i3bar.strip_suffix(',').unwrap()).unwrap();
+        assert_eq!(
+            i3bar,
+            json!([{ "name": "timeflip", "full_text": "R&D 1:02:05", "color": "#ff8000" }])
+        );
+        assert_eq!(
+            line(Format::I3bar, None, now()),
+            r#"[{"full_text":"TimeFlip offline","name":"timeflip"}],"#
+        );
+
+        assert_eq!(
+            line(Format::Polybar, Some(&work), now()),
+            "%{F#ff8000}R&D 1:02:05%{F-}"
+        );
+        assert_eq!(
+            line(Format::Tmux, Some(&paused), now()),
+            "#[fg=#ff8000]⏸ R&D 1:02:05#[default]"
+        );
+        assert_eq!(line(Format::Polybar, None, now()), "TimeFlip offline");
+        assert_eq!(line(Format::Tmux, None, now()), "TimeFlip offline");
+    }
+}
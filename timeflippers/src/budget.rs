@@ -0,0 +1,345 @@
+//! Tracking the time spent on facets against their budgets.
+#![deny(missing_docs)]
+
+use chrono::{DateTime, Datelike, IsoWeek, Local, NaiveDate, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{
+    config::{Budget, BudgetPeriod, Config},
+    timeflip::{Entry, Event},
+    types::{Color, Facet},
+};
+
+/// Notification about the budget of a facet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetEvent {
+    /// The time spent on the facet exceeds its budget.
+    Exceeded {
+        /// The facet.
+        facet: Facet,
+        /// Time spent in the current period.
+        used: Duration,
+        /// The budget of the current period.
+        limit: Duration,
+        /// The color to set the facet's LED to.
+        color: Option<Color>,
+    },
+    /// A new period has started for a facet which exceeded its budget.
+    Reset {
+        /// The facet.
+        facet: Facet,
+        /// The facet's configured color, to be restored.
+        color: Color,
+    },
+}
+
+/// A period of a [Budget].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Period {
+    Day(NaiveDate),
+    Week(IsoWeek),
+}
+
+impl Period {
+    fn of(per: BudgetPeriod, date: NaiveDate) -> Self {
+        match per {
+            BudgetPeriod::Day => Period::Day(date),
+            BudgetPeriod::Week => Period::Week(date.iso_week()),
+        }
+    }
+
+    fn contains(&self, date: NaiveDate) -> bool {
+        match self {
+            Period::Day(day) => *day == date,
+            Period::Week(week) => *week == date.iso_week(),
+        }
+    }
+}
+
+/// Tracks the time spent per facet from history entries and live [Event]s.
+///
+/// Feed events with [BudgetTracker::handle_event] and call [BudgetTracker::check]
+/// periodically to get notified when a budget is exceeded.
+#[derive(Debug)]
+pub struct BudgetTracker {
+    budgets: Vec<(Facet, Budget, Color)>,
+    /// Time spent per local date and facet, excluding the currently running activity.
+    spent: HashMap<(NaiveDate, Facet), Duration>,
+    /// The facet currently tracked and when its activity started.
+    active: Option<(Facet, DateTime<Utc>)>,
+    /// Facets which exceeded their budget in the given period.
+    exceeded: HashSet<(Facet, Period)>,
+}
+
+impl BudgetTracker {
+    /// Construct a tracker for the budgets in `config`, counting the given history entries.
+    pub fn new(config: &Config, history: &[Entry]) -> Self {
+        let budgets = config
+            .sides
+            .iter()
+            .filter_map(|side| {
+                side.budget
+                    .clone()
+                    .map(|budget| (side.facet.clone(), budget, side.color.clone()))
+            })
+            .collect();
+
+        let mut tracker = BudgetTracker {
+            budgets,
+            spent: HashMap::new(),
+            active: None,
+            exceeded: HashSet::new(),
+        };
+        for entry in history.iter().filter(|entry| !entry.pause) {
+            tracker.add(entry.facet.clone(), entry.time, entry.duration);
+        }
+        tracker
+    }
+
+    fn add(&mut self, facet: Facet, time: DateTime<Utc>, duration: Duration) {
+        let date = time.with_timezone(&Local).date_naive();
+        let spent = self.spent.entry((date, facet)).or_default();
+        *spent = spent.saturating_add(duration);
+    }
+
+    /// Finish the running activity, if any, at `now`.
+    fn finish(&mut self, now: DateTime<Utc>) {
+        if let Some((facet, start)) = self.active.take() {
+            if let Ok(duration) = (now - start).to_std() {
+                self.add(facet, start, duration);
+            }
+        }
+    }
+
+    /// Start tracking `facet`, e.g. the facet facing up when starting to watch.
+    pub fn start(&mut self, facet: Facet, now: DateTime<Utc>) {
+        self.finish(now);
+        self.active = Some((facet, now));
+    }
+
+    /// Stop tracking, e.g. because pause mode was entered.
+    pub fn stop(&mut self, now: DateTime<Utc>) {
+        self.finish(now);
+    }
+
+    /// Update the active facet from an [Event] received at `now`.
+    pub fn handle_event(&mut self, event: &Event, now: DateTime<Utc>) {
+        match event {
+            Event::Facet(facet) => self.start(facet.clone(), now),
+            Event::DoubleTap { facet, pause } => {
+                if *pause {
+                    self.stop(now);
+                } else {
+                    self.start(facet.clone(), now);
+                }
+            }
+            Event::Disconnected => self.stop(now),
+            Event::BatteryLevel(_) | Event::Event(_) | Event::SyncState(_) | Event::History(_) => {}
+        }
+    }
+
+    /// The time spent on `facet` in `period`, including the running activity.
+    fn used(&self, facet: &Facet, period: Period, now: DateTime<Utc>) -> Duration {
+        let finished = self
+            .spent
+            .iter()
+            .filter(|((date, f), _)| f == facet && period.contains(*date))
+            .fold(Duration::ZERO, |sum, (_, spent)| sum.saturating_add(*spent));
+        let running = match &self.active {
+            Some((f, start)) if f == facet => (now - *start).to_std().unwrap_or_default(),
+            _ => Duration::ZERO,
+        };
+        finished.saturating_add(running)
+    }
+
+    /// The time spent on `facet` in its current budget period and its limit.
+    ///
+    /// Returns `None` if the facet has no budget.
+    pub fn spent(&self, facet: &Facet, now: DateTime<Utc>) -> Option<(Duration, Duration)> {
+        let today = now.with_timezone(&Local).date_naive();
+        self.budgets
+            .iter()
+            .find(|(f, _, _)| f == facet)
+            .map(|(facet, budget, _)| {
+                let period = Period::of(budget.per, today);
+                (self.used(facet, period, now), budget.limit.duration())
+            })
+    }
+
+    /// Check the budgets at `now`, returning newly exceeded and reset budgets.
+    pub fn check(&mut self, now: DateTime<Utc>) -> Vec<BudgetEvent> {
+        let today = now.with_timezone(&Local).date_naive();
+        let mut events = vec![];
+
+        for (facet, budget, color) in &self.budgets {
+            let period = Period::of(budget.per, today);
+
+            let outdated: Vec<_> = self
+                .exceeded
+                .iter()
+                .filter(|(f, p)| f == facet && *p != period)
+                .cloned()
+                .collect();
+            if !outdated.is_empty() {
+                for key in outdated {
+                    self.exceeded.remove(&key);
+                }
+                events.push(BudgetEvent::Reset {
+                    facet: facet.clone(),
+                    color: color.clone(),
+                });
+            }
+
+            let used = self.used(facet, period, now);
+            let limit = budget.limit.duration();
+            if used > limit && self.exceeded.insert((facet.clone(), period)) {
+                events.push(BudgetEvent::Exceeded {
+                    facet: facet.clone(),
+                    used,
+                    limit,
+                    color: budget.exceeded_color.clone(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Hours;
+    use chrono::{NaiveDateTime, TimeZone};
+
+    fn time(s: &str) -> DateTime<Utc> {
+        let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&time).unwrap().into()
+    }
+
+    fn red() -> Color {
+        Color::from_rgb(255, 0, 0)
+    }
+
+    fn config(per: BudgetPeriod) -> Config {
+        let mut config = Config::default();
+        config.sides[0].budget = Some(Budget {
+            limit: Hours::new(1.0).unwrap(),
+            per,
+            exceeded_color: Some(red()),
+        });
+        config
+    }
+
+    fn entry(facet: usize, start: &str, minutes: u64) -> Entry {
+        Entry {
+            id: 0,
+            facet: Facet::new(facet).unwrap(),
+            pause: false,
+            time: time(start),
+            duration: Duration::from_secs(minutes * 60),
+        }
+    }
+
+    #[test]
+    fn history_and_running_activity() {
+        let facet = Facet::new(1).unwrap();
+        let history = [
+            entry(1, "2024-06-03 08:00", 40),
+            entry(1, "2024-06-02 08:00", 60),
+            entry(2, "2024-06-03 09:00", 60),
+        ];
+        let mut tracker = BudgetTracker::new(&config(BudgetPeriod::Day), &history);
+        tracker.start(facet.clone(), time("2024-06-03 10:00"));
+
+        assert_eq!(
+            tracker.spent(&facet, time("2024-06-03 10:10")),
+            Some((Duration::from_secs(50 * 60), Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            tracker.spent(&Facet::new(2).unwrap(), time("2024-06-03 10:10")),
+            None
+        );
+        assert!(tracker.check(time("2024-06-03 10:20")).is_empty());
+
+        let events = tracker.check(time("2024-06-03 10:21"));
+        assert_eq!(
+            events,
+            vec![BudgetEvent::Exceeded {
+                facet: facet.clone(),
+                used: Duration::from_secs(61 * 60),
+                limit: Duration::from_secs(3600),
+                color: Some(red()),
+            }]
+        );
+        // Exceeding is only reported once per period.
+        assert!(tracker.check(time("2024-06-03 10:30")).is_empty());
+    }
+
+    #[test]
+    fn pause_and_other_facets_stop_the_activity() {
+        let facet = Facet::new(1).unwrap();
+        let mut tracker = BudgetTracker::new(&config(BudgetPeriod::Day), &[]);
+        tracker.handle_event(&Event::Facet(facet.clone()), time("2024-06-03 08:00"));
+        tracker.handle_event(
+            &Event::DoubleTap {
+                facet: facet.clone(),
+                pause: true,
+            },
+            time("2024-06-03 08:30"),
+        );
+        tracker.handle_event(
+            &Event::DoubleTap {
+                facet: facet.clone(),
+                pause: false,
+            },
+            time("2024-06-03 09:00"),
+        );
+        tracker.handle_event(
+            &Event::Facet(Facet::new(2).unwrap()),
+            time("2024-06-03 09:10"),
+        );
+
+        assert_eq!(
+            tracker.spent(&facet, time("2024-06-03 12:00")),
+            Some((Duration::from_secs(40 * 60), Duration::from_secs(3600)))
+        );
+    }
+
+    #[test]
+    fn reset_in_new_period() {
+        let facet = Facet::new(1).unwrap();
+        let history = [entry(1, "2024-06-03 08:00", 90)];
+        let mut tracker = BudgetTracker::new(&config(BudgetPeriod::Day), &history);
+
+        assert_eq!(tracker.check(time("2024-06-03 12:00")).len(), 1);
+        assert_eq!(
+            tracker.check(time("2024-06-04 08:00")),
+            vec![BudgetEvent::Reset {
+                facet,
+                color: Color::default(),
+            }]
+        );
+        assert!(tracker.check(time("2024-06-04 09:00")).is_empty());
+    }
+
+    #[test]
+    fn weekly_budget() {
+        let facet = Facet::new(1).unwrap();
+        // Sunday, Monday and Tuesday.
+        let history = [
+            entry(1, "2024-06-02 08:00", 30),
+            entry(1, "2024-06-03 08:00", 30),
+            entry(1, "2024-06-04 08:00", 20),
+        ];
+        let tracker = BudgetTracker::new(&config(BudgetPeriod::Week), &history);
+
+        assert_eq!(
+            tracker.spent(&facet, time("2024-06-05 08:00")),
+            Some((Duration::from_secs(50 * 60), Duration::from_secs(3600)))
+        );
+    }
+}
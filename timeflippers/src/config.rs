@@ -1,13 +1,19 @@
-use crate::types::{BlinkInterval, Color, Facet, FacetError, FacetTask, Minutes, Percent};
+use crate::types::{
+    Amount, BlinkInterval, Color, Facet, FacetError, FacetTask, Hours, Minutes, Percent, Rounding,
+    TaxRate, TimeWindow,
+};
+use chrono::{Datelike, NaiveDate, Weekday};
 use serde::{
     de::{self, Error},
     Deserialize,
 };
-use std::default::Default;
+use std::{
+    collections::HashMap, default::Default, fmt, net::SocketAddr, path::PathBuf, time::Duration,
+};
 use thiserror::Error as ThisError;
 
 /// Configuration of a TimeFlip2 facet.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Side {
     /// The name of the facet.
     pub facet: Facet,
@@ -17,6 +23,16 @@ pub struct Side {
     pub color: Color,
     /// The task assigned to the facet.
     pub task: FacetTask,
+    /// Maximum time to spend on the facet.
+    pub budget: Option<Budget>,
+    /// Command run when the facet is flipped up.
+    pub on_enter: Option<String>,
+    /// Command run when the facet is flipped away.
+    pub on_leave: Option<String>,
+    /// Command run when pause mode is entered on the facet.
+    pub on_pause: Option<String>,
+    /// Command run when pause mode is left on the facet.
+    pub on_unpause: Option<String>,
 }
 
 impl Side {
@@ -27,12 +43,48 @@ impl Side {
             name: None,
             color: Color::default(),
             task: FacetTask::Simple,
+            budget: None,
+            on_enter: None,
+            on_leave: None,
+            on_pause: None,
+            on_unpause: None,
         })
     }
+
+    /// Whether any command is configured to run on the facet's events.
+    pub fn has_hooks(&self) -> bool {
+        self.on_enter.is_some()
+            || self.on_leave.is_some()
+            || self.on_pause.is_some()
+            || self.on_unpause.is_some()
+    }
+}
+
+/// Period a [Budget] applies to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    /// The budget is reset every day.
+    #[default]
+    Day,
+    /// The budget is reset every (ISO) week.
+    Week,
+}
+
+/// Maximum time to spend on a facet.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Budget {
+    /// The time available per period.
+    pub limit: Hours,
+    /// The period the limit applies to.
+    #[serde(default)]
+    pub per: BudgetPeriod,
+    /// LED color of the facet once the budget is exceeded.
+    pub exceeded_color: Option<Color>,
 }
 
 /// Configuration of a TimeFlip2.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(rename = "Timeflip")]
 pub struct Config {
     /// The password to access the TimeFlip2.
@@ -46,6 +98,22 @@ pub struct Config {
     /// Configuration for each facet/side.
     #[serde(deserialize_with = "deserialize_sides")]
     pub sides: [Side; 12],
+    /// Rounding policy applied to reports and invoices.
+    pub rounding: Option<Rounding>,
+    /// Configuration for generating invoices.
+    pub invoice: Option<InvoiceConfig>,
+    /// Working time targets for the overtime balance.
+    pub targets: Option<TargetsConfig>,
+    /// Rules for the working time compliance report.
+    pub compliance: Option<ComplianceConfig>,
+    /// Commands run by the daemon on the TimeFlip2's events.
+    pub hooks: Option<HooksConfig>,
+    /// Rules evaluated by the daemon.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Configuration of the daemon.
+    #[serde(default)]
+    pub daemon: DaemonConfig,
 }
 
 impl Default for Config {
@@ -56,10 +124,484 @@ impl Default for Config {
             blink_interval: BlinkInterval::new(30).expect("is a valid value"),
             auto_pause: Minutes(8 * 60),
             sides: sides_from_vec(vec![]).expect("cannot fail"),
+            rounding: None,
+            invoice: None,
+            targets: None,
+            compliance: None,
+            hooks: None,
+            rules: vec![],
+            daemon: DaemonConfig::default(),
         }
     }
 }
 
+/// How the line items of an invoice are grouped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceGrouping {
+    /// One line item per side.
+    #[default]
+    Project,
+    /// One line item per side and day.
+    Day,
+}
+
+/// Hourly rate for a single facet, overriding [InvoiceConfig::hourly_rate].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FacetRate {
+    /// The facet billed at this rate.
+    pub facet: Facet,
+    /// The hourly rate.
+    pub rate: Amount,
+}
+
+/// Configuration for generating invoices from the history.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InvoiceConfig {
+    /// The currency amounts are given in, e.g. "EUR".
+    pub currency: String,
+    /// The default hourly rate.
+    pub hourly_rate: Amount,
+    /// Hourly rates of facets differing from the default.
+    #[serde(default)]
+    pub rates: Vec<FacetRate>,
+    /// Facets which are never billed, e.g. a side used for breaks.
+    #[serde(default)]
+    pub exclude: Vec<Facet>,
+    /// Tax rate added to the net amount.
+    #[serde(default)]
+    pub tax_rate: TaxRate,
+    /// How line items are grouped.
+    #[serde(default)]
+    pub group_by: InvoiceGrouping,
+    /// Format of the invoice number, `{year}` and `{number}` are replaced.
+    #[serde(default = "InvoiceConfig::default_number_format")]
+    pub number_format: String,
+    /// File keeping track of the last invoice number used.
+    pub number_file: Option<PathBuf>,
+    /// Template replacing the builtin HTML template.
+    pub html_template: Option<PathBuf>,
+    /// Template replacing the builtin Markdown template.
+    pub markdown_template: Option<PathBuf>,
+    /// Address lines of the issuer.
+    #[serde(default)]
+    pub issuer: Vec<String>,
+    /// Address lines of the recipient.
+    #[serde(default)]
+    pub recipient: Vec<String>,
+}
+
+impl InvoiceConfig {
+    fn default_number_format() -> String {
+        "{year}-{number}".into()
+    }
+
+    /// The hourly rate of a facet.
+    pub fn rate(&self, facet: &Facet) -> Amount {
+        self.rates
+            .iter()
+            .find(|r| r.facet == *facet)
+            .map(|r| r.rate)
+            .unwrap_or(self.hourly_rate)
+    }
+
+    /// Format an invoice number according to [InvoiceConfig::number_format].
+    pub fn format_number(&self, year: i32, number: u32) -> String {
+        self.number_format
+            .replace("{year}", &year.to_string())
+            .replace("{number}", &number.to_string())
+    }
+}
+
+/// Working time targets.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TargetsConfig {
+    /// Target working time per week, spread evenly over Monday to Friday.
+    pub weekly: Option<Hours>,
+    /// Target working time per weekday, takes precedence over `weekly` for the days listed.
+    #[serde(default)]
+    pub schedule: HashMap<Weekday, Hours>,
+    /// Facets not counted as working time, e.g. a side used for breaks.
+    #[serde(default)]
+    pub exclude: Vec<Facet>,
+    /// File listing public holidays.
+    pub holidays: Option<PathBuf>,
+    /// File listing vacation days.
+    pub vacation: Option<PathBuf>,
+    /// First day of the overtime account, given as "YYYY-MM-DD".
+    pub start: Option<NaiveDate>,
+}
+
+impl TargetsConfig {
+    /// The target working time of a day, not considering days off.
+    pub fn daily(&self, date: NaiveDate) -> Duration {
+        let weekday = date.weekday();
+        if let Some(hours) = self.schedule.get(&weekday) {
+            return hours.duration();
+        }
+
+        match (&self.weekly, weekday) {
+            (Some(_), Weekday::Sat | Weekday::Sun) | (None, _) => Duration::ZERO,
+            (Some(weekly), _) => weekly.duration() / 5,
+        }
+    }
+}
+
+/// Break required after working for a given time.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BreakRule {
+    /// Working time after which the break is required.
+    pub after: Hours,
+    /// Minimum total break time.
+    pub minimum: Minutes,
+}
+
+/// Rules for the working time compliance report.
+///
+/// The defaults follow the German working hours act (ArbZG).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ComplianceConfig {
+    /// Facets counted as breaks, in addition to pause mode.
+    pub break_facets: Vec<Facet>,
+    /// Required breaks, the rule with the longest `after` matching the working time applies.
+    pub breaks: Vec<BreakRule>,
+    /// Shorter interruptions are not counted as break.
+    pub min_break_length: Minutes,
+    /// Maximum working time per day.
+    pub max_daily: Option<Hours>,
+    /// Minimum rest period between two working days.
+    pub rest_period: Option<Hours>,
+}
+
+impl Default for ComplianceConfig {
+    fn default() -> Self {
+        ComplianceConfig {
+            break_facets: vec![],
+            breaks: vec![
+                BreakRule {
+                    after: Hours::new(6.0).expect("is a valid value"),
+                    minimum: Minutes(30),
+                },
+                BreakRule {
+                    after: Hours::new(9.0).expect("is a valid value"),
+                    minimum: Minutes(45),
+                },
+            ],
+            min_break_length: Minutes(15),
+            max_daily: Some(Hours::new(10.0).expect("is a valid value")),
+            rest_period: Some(Hours::new(11.0).expect("is a valid value")),
+        }
+    }
+}
+
+impl ComplianceConfig {
+    /// The break required after working for `worked`.
+    pub fn required_break(&self, worked: Duration) -> Duration {
+        self.breaks
+            .iter()
+            .filter(|rule| worked > rule.after.duration())
+            .max_by_key(|rule| rule.after)
+            .map(|rule| rule.minimum.duration())
+            .unwrap_or_default()
+    }
+}
+
+/// Action taken by a [Rule].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Enter pause mode.
+    Pause,
+    /// Leave pause mode.
+    Unpause,
+    /// Enter lock mode.
+    Lock,
+    /// Leave lock mode.
+    Unlock,
+    /// Set the LED's brightness.
+    Brightness(Percent),
+    /// Set the LED's color of the rule's facet, or of the active facet.
+    Color(Color),
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAction::Pause => write!(f, "pause"),
+            RuleAction::Unpause => write!(f, "unpause"),
+            RuleAction::Lock => write!(f, "lock"),
+            RuleAction::Unlock => write!(f, "unlock"),
+            RuleAction::Brightness(value) => write!(f, "set brightness to {value}"),
+            RuleAction::Color(color) => write!(f, "set color to {color}"),
+        }
+    }
+}
+
+/// An action taken once all of the rule's conditions hold.
+///
+/// The action is taken again only after the conditions stopped holding in between, e.g.
+/// `{ facet = 8, active_for = 90, action = "pause" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rule {
+    /// Name of the rule in log messages.
+    pub name: Option<String>,
+    /// The facet facing up.
+    pub facet: Option<Facet>,
+    /// Minutes the facet faces up for, not counting pauses.
+    pub active_for: Option<Minutes>,
+    /// Local time window the current time lies in, e.g. `"08:00-18:00"`.
+    pub inside: Option<TimeWindow>,
+    /// Local time window the current time lies outside of.
+    pub outside: Option<TimeWindow>,
+    /// Battery level the current level is below.
+    pub battery_below: Option<Percent>,
+    /// Whether the TimeFlip2 is paused.
+    pub paused: Option<bool>,
+    /// The action to take.
+    pub action: RuleAction,
+}
+
+/// How to handle a hook starting while others are still running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookConcurrency {
+    /// Wait for the running hooks, preserving the order of events.
+    #[default]
+    Queue,
+    /// Run hooks concurrently.
+    Parallel,
+    /// Do not run the hook.
+    Skip,
+    /// Kill the running hooks.
+    Replace,
+}
+
+/// Commands run on the TimeFlip2's events, in addition to the ones configured per side.
+///
+/// Commands are run with `sh -c`, the event is described by environment variables:
+/// `TIMEFLIP_EVENT`, `TIMEFLIP_FACET`, `TIMEFLIP_SIDE`, `TIMEFLIP_PREVIOUS_FACET` (on enter),
+/// `TIMEFLIP_ELAPSED` and `TIMEFLIP_BATTERY` (on low battery).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HooksConfig {
+    /// Command run when any facet is flipped up.
+    pub on_enter: Option<String>,
+    /// Command run when any facet is flipped away.
+    pub on_leave: Option<String>,
+    /// Command run when pause mode is entered.
+    pub on_pause: Option<String>,
+    /// Command run when pause mode is left.
+    pub on_unpause: Option<String>,
+    /// Command run when the battery level drops below the threshold.
+    pub on_low_battery: Option<String>,
+    /// Seconds after which a command is killed.
+    #[serde(default = "HooksConfig::default_timeout")]
+    pub timeout: u64,
+    /// How to handle a command starting while others are still running.
+    #[serde(default)]
+    pub concurrency: HookConcurrency,
+}
+
+impl HooksConfig {
+    fn default_timeout() -> u64 {
+        30
+    }
+}
+
+/// Configuration of the daemon sharing the TimeFlip2 with other programs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct DaemonConfig {
+    /// Address to serve the HTTP API on, the API is disabled if not set.
+    ///
+    /// Without a `token`, anyone who can connect controls the TimeFlip2, so only bind to
+    /// a loopback address then.
+    pub listen: Option<SocketAddr>,
+    /// Bearer token required in the `Authorization` header of each HTTP API request.
+    pub token: Option<String>,
+    /// File to keep the history in, like `timeflip history --update`.
+    pub history: Option<PathBuf>,
+    /// MQTT broker to publish the TimeFlip2's state to.
+    pub mqtt: Option<MqttConfig>,
+    /// Battery level below which the battery is considered low, defaults to 20%.
+    pub low_battery: Option<Percent>,
+    /// HTTP webhooks called on the TimeFlip2's events.
+    pub webhooks: Option<WebhooksConfig>,
+    /// Pause while the computer sleeps, as announced by logind.
+    #[serde(default)]
+    pub pause_on_sleep: bool,
+    /// Pause while the session is locked, as announced by logind.
+    #[serde(default)]
+    pub pause_on_lock: bool,
+    /// Publish the `io.timeflippers.TimeFlip` service on the session bus.
+    #[serde(default)]
+    pub dbus: bool,
+    /// Desktop notifications on the TimeFlip2's events.
+    pub notifications: Option<NotificationsConfig>,
+    /// Directory of Rhai scripts run on the TimeFlip2's events.
+    pub scripts: Option<PathBuf>,
+    /// Only log the actions of rules and scripts instead of taking them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl DaemonConfig {
+    /// Battery level in percent below which the battery is considered low.
+    pub fn low_battery(&self) -> u8 {
+        self.low_battery.as_ref().map_or(20, Percent::get)
+    }
+}
+
+/// Connection to an MQTT broker.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MqttConfig {
+    /// Host name of the broker.
+    pub host: String,
+    /// Port of the broker.
+    #[serde(default = "MqttConfig::default_port")]
+    pub port: u16,
+    /// Client ID, also used to identify the device in Home Assistant.
+    #[serde(default = "MqttConfig::default_client_id")]
+    pub client_id: String,
+    /// User name for authentication.
+    pub username: Option<String>,
+    /// Password for authentication.
+    pub password: Option<String>,
+    /// Base of all state and command topics.
+    #[serde(default = "MqttConfig::default_topic")]
+    pub topic: String,
+    /// Discovery prefix of Home Assistant, usually `homeassistant`.
+    ///
+    /// Discovery payloads are only published if this is set.
+    pub discovery: Option<String>,
+}
+
+impl MqttConfig {
+    fn default_port() -> u16 {
+        1883
+    }
+
+    fn default_client_id() -> String {
+        "timeflippers".into()
+    }
+
+    fn default_topic() -> String {
+        "timeflip".into()
+    }
+
+    /// The topic `name` below the base topic.
+    pub fn topic(&self, name: &str) -> String {
+        format!("{}/{name}", self.topic)
+    }
+}
+
+/// Events a desktop notification can be shown for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// The facet has changed.
+    Facet,
+    /// Pause mode has been entered or left.
+    Pause,
+    /// A pomodoro has been completed.
+    Pomodoro,
+    /// The battery level has dropped below the threshold.
+    LowBattery,
+    /// The TimeFlip2 has disconnected.
+    Disconnect,
+}
+
+impl NotificationEvent {
+    fn all() -> Vec<NotificationEvent> {
+        use NotificationEvent::*;
+        vec![Facet, Pause, Pomodoro, LowBattery, Disconnect]
+    }
+}
+
+/// Desktop notifications sent through `org.freedesktop.Notifications`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NotificationsConfig {
+    /// Address of the D-Bus to send the notifications to, defaults to the session bus.
+    pub address: Option<String>,
+    /// Events to show notifications for, defaults to all events.
+    #[serde(default = "NotificationEvent::all")]
+    pub events: Vec<NotificationEvent>,
+}
+
+/// Events a webhook can be called on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// The facet has changed.
+    Facet,
+    /// Pause mode has been entered.
+    Pause,
+    /// Pause mode has been left.
+    Unpause,
+    /// The battery level has dropped below the threshold.
+    LowBattery,
+    /// The TimeFlip2 has disconnected.
+    Disconnect,
+    /// A history entry has been finished.
+    Entry,
+}
+
+impl WebhookEvent {
+    fn all() -> Vec<WebhookEvent> {
+        use WebhookEvent::*;
+        vec![Facet, Pause, Unpause, LowBattery, Disconnect, Entry]
+    }
+
+    /// Name of the event in request bodies.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Facet => "facet",
+            WebhookEvent::Pause => "pause",
+            WebhookEvent::Unpause => "unpause",
+            WebhookEvent::LowBattery => "low_battery",
+            WebhookEvent::Disconnect => "disconnect",
+            WebhookEvent::Entry => "entry",
+        }
+    }
+}
+
+/// An HTTP endpoint events are posted to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Webhook {
+    /// Identifies the webhook's undelivered requests in the queue, defaults to a digest of
+    /// its settings.
+    ///
+    /// Set it to keep queued requests when changing the webhook's settings.
+    pub name: Option<String>,
+    /// URL the events are posted to.
+    pub url: String,
+    /// Events to post, defaults to all events.
+    #[serde(default = "WebhookEvent::all")]
+    pub events: Vec<WebhookEvent>,
+    /// Template of the JSON body, defaults to the event's data as JSON object.
+    pub body: Option<String>,
+    /// Secret for signing the body with HMAC-SHA256.
+    pub secret: Option<String>,
+    /// Header carrying the signature.
+    #[serde(default = "Webhook::default_signature_header")]
+    pub signature_header: String,
+}
+
+impl Webhook {
+    fn default_signature_header() -> String {
+        "X-TimeFlip-Signature".into()
+    }
+}
+
+/// HTTP webhooks called on the TimeFlip2's events.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WebhooksConfig {
+    /// File keeping undelivered requests across restarts.
+    pub queue: Option<PathBuf>,
+    /// The webhooks.
+    #[serde(default)]
+    pub hooks: Vec<Webhook>,
+}
+
 #[derive(Debug, ThisError)]
 enum ExpectedSides {
     #[error("too many sides ({0}), up to 12 sides supported")]
@@ -0,0 +1,543 @@
+//! Long-running service sharing a TimeFlip2 with other programs.
+#![deny(missing_docs)]
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use std::{fmt, future::Future, ops::Deref, sync::Arc};
+use thiserror::Error;
+use tokio::{
+    sync::{broadcast, broadcast::error::RecvError, Mutex, MutexGuard},
+    task::JoinSet,
+    time::sleep,
+};
+
+use crate::{
+    config::Config,
+    timeflip::{self, Entry, Envelope, Event, SystemStatus, TimeFlip},
+    types::{Color, Facet, Percent},
+};
+
+mod away;
+#[cfg(test)]
+mod fake;
+mod hooks;
+mod http;
+mod metrics;
+mod mqtt;
+mod notifications;
+mod rules;
+mod scripts;
+mod session_bus;
+mod webhook;
+
+/// Number of events buffered for subscribers lagging behind.
+const EVENT_BUFFER: usize = 64;
+/// Time to wait before reconnecting to the TimeFlip2.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Error running the daemon.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    TimeFlip(#[from] timeflip::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("MQTT: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+    #[error("D-Bus: {0}")]
+    DBus(#[from] dbus::Error),
+    #[error("lost connection to the {bus}: {reason}")]
+    BusLost { bus: &'static str, reason: String },
+    #[error("HTTP client: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("daemon task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// What the TimeFlip2 is currently tracking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Activity {
+    /// The facet facing up.
+    pub facet: Facet,
+    /// The configured name of the facet's side.
+    pub name: Option<String>,
+    /// The configured color of the facet's side.
+    pub color: Color,
+    /// Whether the TimeFlip2 is paused.
+    pub paused: bool,
+    /// The time the facet was flipped up or pause mode toggled.
+    pub since: DateTime<Utc>,
+}
+
+/// A command changing the TimeFlip2's state, requested by one of the daemon's services.
+///
+/// The daemon relays the resulting [SystemStatus] to all services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Pause,
+    Unpause,
+    Lock,
+    Unlock,
+    Brightness(Percent),
+    Color(Facet, Color),
+    SetTime(DateTime<Utc>),
+    Sync,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Pause => write!(f, "pause"),
+            Command::Unpause => write!(f, "unpause"),
+            Command::Lock => write!(f, "lock"),
+            Command::Unlock => write!(f, "unlock"),
+            Command::Brightness(value) => write!(f, "set brightness to {value}"),
+            Command::Color(facet, color) => write!(f, "set color of {facet} to {color}"),
+            Command::SetTime(time) => write!(f, "set time to {time}"),
+            Command::Sync => write!(f, "synchronize"),
+        }
+    }
+}
+
+/// The state of the TimeFlip2 published by the daemon's services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DeviceState {
+    facet: Facet,
+    paused: bool,
+    locked: bool,
+    battery: Percent,
+}
+
+/// A change relayed to the daemon's services.
+#[derive(Debug, Clone, PartialEq)]
+enum Update {
+    /// An event of the TimeFlip2.
+    Event(Envelope),
+    /// A finished history entry.
+    Entry(Entry),
+    /// The system status after a command, e.g. pause mode entered through the HTTP API.
+    Status(SystemStatus),
+}
+
+/// Access to the TimeFlip2 for the daemon's services.
+///
+/// Implemented by [Daemon], services are generic over it to run against a fake in tests.
+trait Control: Send + Sync + 'static {
+    /// The configuration the daemon was started with.
+    fn config(&self) -> &Config;
+
+    /// Execute a command.
+    fn execute(&self, command: Command)
+        -> impl Future<Output = Result<(), timeflip::Error>> + Send;
+
+    /// Read the current state.
+    fn state(&self) -> impl Future<Output = Result<DeviceState, timeflip::Error>> + Send;
+
+    /// Time since the task of `facet` was started, as counted by the TimeFlip2.
+    fn elapsed(
+        &self,
+        facet: Facet,
+    ) -> impl Future<Output = Result<Duration, timeflip::Error>> + Send;
+
+    /// The time the task of `facet` was started, derived from [Control::elapsed()].
+    fn started(
+        &self,
+        facet: Facet,
+    ) -> impl Future<Output = Result<DateTime<Utc>, timeflip::Error>> + Send {
+        async move {
+            let elapsed = self.elapsed(facet).await?;
+            Ok(Utc::now() - chrono::Duration::seconds(elapsed.as_secs() as i64))
+        }
+    }
+
+    /// Count a failed communication with the TimeFlip2.
+    fn record_error(&self, e: &timeflip::Error);
+
+    /// The configured name of `facet`'s side.
+    fn side_name(&self, facet: Facet) -> Option<&str> {
+        self.config().sides[facet.index_zero()].name.as_deref()
+    }
+}
+
+/// The error of a lost connection to `bus`, given the result of the connection's task.
+fn bus_lost(bus: &'static str, result: Result<impl fmt::Display, tokio::task::JoinError>) -> Error {
+    let reason = match result {
+        Ok(e) => e.to_string(),
+        Err(e) => e.to_string(),
+    };
+    Error::BusLost { bus, reason }
+}
+
+/// Turn a broadcast receiver into a stream, logging the items `service` missed.
+fn receive<T: Clone + Send + 'static>(
+    receiver: broadcast::Receiver<T>,
+    service: &'static str,
+    items: &'static str,
+) -> BoxStream<'static, T> {
+    stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(item) => return Some((item, receiver)),
+                Err(RecvError::Lagged(n)) => log::warn!("{service} missed {n} {items}"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Exclusive access to the TimeFlip2.
+///
+/// Commands consist of several GATT reads and writes, which must not interleave.
+pub struct Device<'a> {
+    timeflip: &'a TimeFlip,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Deref for Device<'_> {
+    type Target = TimeFlip;
+
+    fn deref(&self) -> &TimeFlip {
+        self.timeflip
+    }
+}
+
+/// Shares a connected TimeFlip2 and its events with the daemon's services.
+pub struct Daemon {
+    timeflip: TimeFlip,
+    config: Config,
+    commands: Mutex<()>,
+    events: broadcast::Sender<Envelope>,
+    entries: broadcast::Sender<Entry>,
+    statuses: broadcast::Sender<SystemStatus>,
+    last_entry: Mutex<Option<u32>>,
+    metrics: metrics::Metrics,
+    activity: std::sync::Mutex<Option<Activity>>,
+}
+
+impl Daemon {
+    /// Construct a daemon for a connected TimeFlip2.
+    pub fn new(timeflip: TimeFlip, config: Config) -> Arc<Self> {
+        Arc::new(Daemon {
+            timeflip,
+            config,
+            commands: Mutex::new(()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            entries: broadcast::channel(EVENT_BUFFER).0,
+            statuses: broadcast::channel(EVENT_BUFFER).0,
+            last_entry: Mutex::new(None),
+            metrics: metrics::Metrics::default(),
+            activity: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// The configuration the daemon was started with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The configured name of `facet`'s side.
+    pub fn side_name(&self, facet: Facet) -> Option<&str> {
+        self.config.sides[facet.index_zero()].name.as_deref()
+    }
+
+    /// What the TimeFlip2 is currently tracking, once the daemon runs and unless the TimeFlip2
+    /// is disconnected.
+    pub fn activity(&self) -> Option<Activity> {
+        self.activity.lock().expect("not poisoned").clone()
+    }
+
+    fn set_activity(&self, facet: Facet, paused: bool, since: DateTime<Utc>) {
+        let side = &self.config.sides[facet.index_zero()];
+        *self.activity.lock().expect("not poisoned") = Some(Activity {
+            name: side.name.clone(),
+            color: side.color.clone(),
+            facet,
+            paused,
+            since,
+        });
+    }
+
+    /// Read the activity from the TimeFlip2, e.g. after connecting to it.
+    ///
+    /// The activity started when the TimeFlip2 started the facet's task.
+    async fn read_activity(&self) -> Result<(), timeflip::Error> {
+        let state = Control::state(self).await?;
+        let since = self.started_or_now(state.facet.clone()).await;
+        self.set_activity(state.facet, state.paused, since);
+        Ok(())
+    }
+
+    /// The time the task of `facet` was started, or now if the TimeFlip2 cannot tell.
+    async fn started_or_now(&self, facet: Facet) -> DateTime<Utc> {
+        match self.started(facet).await {
+            Ok(since) => since,
+            Err(e) => {
+                log::warn!("cannot read the time since the activity started: {e}");
+                self.record_error(&e);
+                Utc::now()
+            }
+        }
+    }
+
+    /// Wait for exclusive access to the TimeFlip2.
+    pub async fn device(&self) -> Device<'_> {
+        Device {
+            timeflip: &self.timeflip,
+            _guard: self.commands.lock().await,
+        }
+    }
+
+    /// Receive the TimeFlip2's events with the time they were received.
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.events.subscribe()
+    }
+
+    /// Receive history entries as they are finished.
+    pub fn subscribe_entries(&self) -> broadcast::Receiver<Entry> {
+        self.entries.subscribe()
+    }
+
+    /// Receive the events and finished history entries, as needed by most services.
+    fn updates(&self, service: &'static str) -> BoxStream<'static, Update> {
+        stream::select_all([
+            receive(self.subscribe(), service, "events")
+                .map(Update::Event)
+                .boxed(),
+            receive(self.subscribe_entries(), service, "history entries")
+                .map(Update::Entry)
+                .boxed(),
+            receive(self.statuses.subscribe(), service, "status changes")
+                .map(Update::Status)
+                .boxed(),
+        ])
+        .boxed()
+    }
+
+    /// Relay the system status after a command to the services.
+    fn relay_status(&self, status: SystemStatus) {
+        self.metrics.status(&status);
+        if let Some(activity) = self.activity().filter(|a| a.paused != status.pause_mode) {
+            self.set_activity(activity.facet, status.pause_mode, Utc::now());
+        }
+        // Having no subscribers is fine.
+        let _ = self.statuses.send(status);
+    }
+
+    /// Count a failed communication with the TimeFlip2.
+    pub(crate) fn record_error(&self, e: &timeflip::Error) {
+        self.metrics.gatt_error(e.kind());
+    }
+
+    /// Relay the history entries finished since the last relayed one.
+    async fn relay_entries(&self) -> Result<(), timeflip::Error> {
+        let mut last_entry = self.last_entry.lock().await;
+        let device = self.device().await;
+        let Some(last_id) = *last_entry else {
+            *last_entry = Some(device.read_last_history_entry().await?.id);
+            return Ok(());
+        };
+
+        for entry in device.read_history_since(last_id).await? {
+            if entry.id > last_entry.unwrap_or(last_id) {
+                *last_entry = Some(entry.id);
+                self.metrics.entry(&entry);
+                // Having no subscribers is fine.
+                let _ = self.entries.send(entry);
+            }
+        }
+        self.metrics.synced();
+        Ok(())
+    }
+
+    /// Enable the notifications the daemon relays.
+    async fn subscribe_notifications(&self) -> Result<(), timeflip::Error> {
+        let device = self.device().await;
+        device.subscribe_battery_level().await?;
+        device.subscribe_events().await?;
+        device.subscribe_facet().await?;
+        device.subscribe_double_tap().await?;
+        device.subscribe_sync_state().await
+    }
+
+    /// Try to connect to the TimeFlip2 until it succeeds.
+    async fn reconnect(&self) {
+        loop {
+            sleep(RECONNECT_DELAY).await;
+            let result = async {
+                self.device().await.reconnect().await?;
+                self.subscribe_notifications().await
+            }
+            .await;
+            match result {
+                Ok(()) => {
+                    log::info!("reconnected to TimeFlip");
+                    self.metrics.reconnected();
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("cannot reconnect to TimeFlip: {e}");
+                    self.record_error(&e);
+                }
+            }
+        }
+    }
+
+    /// Relay the TimeFlip2's events, reconnecting whenever it disconnects.
+    async fn relay_events(&self) -> Result<(), Error> {
+        loop {
+            let clock_offset = match self.device().await.clock_offset().await {
+                Ok(offset) => Some(offset),
+                Err(e) => {
+                    log::warn!("cannot read the TimeFlip's clock: {e}");
+                    self.record_error(&e);
+                    None
+                }
+            };
+            let mut stream = self.timeflip.envelope_stream(clock_offset).await?;
+            while let Some(envelope) = stream.next().await {
+                let event = &envelope.event;
+                self.metrics.event(event);
+                match event {
+                    Event::Facet(facet) => {
+                        let current = self.activity();
+                        if current.as_ref().is_none_or(|a| a.facet != *facet) {
+                            let paused = current.is_some_and(|a| a.paused);
+                            self.set_activity(facet.clone(), paused, envelope.received);
+                        }
+                    }
+                    Event::DoubleTap { facet, pause } => {
+                        self.set_activity(facet.clone(), *pause, envelope.received)
+                    }
+                    // The time since the last flip is unknown until reconnected.
+                    Event::Disconnected => *self.activity.lock().expect("not poisoned") = None,
+                    _ => {}
+                }
+                let finished_entry = matches!(event, Event::Facet(_) | Event::DoubleTap { .. });
+                let disconnected = *event == Event::Disconnected;
+                // Having no subscribers is fine.
+                let _ = self.events.send(envelope);
+                if finished_entry {
+                    if let Err(e) = self.relay_entries().await {
+                        log::warn!("cannot read finished history entries: {e}");
+                        self.record_error(&e);
+                    }
+                }
+                if disconnected {
+                    break;
+                }
+            }
+            drop(stream);
+
+            log::warn!("TimeFlip has disconnected");
+            self.reconnect().await;
+            if let Err(e) = self.read_activity().await {
+                log::warn!("cannot read the activity after reconnecting: {e}");
+                self.record_error(&e);
+            }
+            // Entries finished while disconnected are only in the TimeFlip2's history.
+            if let Err(e) = self.relay_entries().await {
+                log::warn!("cannot read history entries finished while disconnected: {e}");
+                self.record_error(&e);
+            }
+        }
+    }
+
+    /// Run the configured services until one of them fails.
+    ///
+    /// The daemon reconnects whenever the TimeFlip2 disconnects.
+    pub async fn run(self: Arc<Self>) -> Result<(), Error> {
+        self.subscribe_notifications().await?;
+        self.metrics.connected();
+        self.read_activity().await?;
+        self.relay_entries().await?;
+
+        let mut services = JoinSet::new();
+        if self.config.hooks.is_some() || self.config.sides.iter().any(|side| side.has_hooks()) {
+            services.spawn(hooks::run(self.clone()));
+        }
+        if !self.config.rules.is_empty() {
+            services.spawn(rules::run(self.clone()));
+        }
+        if let Some(config) = self.config.daemon.notifications.clone() {
+            services.spawn(notifications::run(self.clone(), config));
+        }
+        if self.config.daemon.pause_on_sleep || self.config.daemon.pause_on_lock {
+            services.spawn(away::run(self.clone()));
+        }
+        if self.config.daemon.dbus {
+            services.spawn(session_bus::serve(self.clone()));
+        }
+        if let Some(dir) = self.config.daemon.scripts.clone() {
+            services.spawn(scripts::run(self.clone(), dir));
+        }
+        if let Some(addr) = self.config.daemon.listen {
+            services.spawn(http::serve(self.clone(), addr));
+            services.spawn(metrics::sample(self.clone()));
+        }
+        if let Some(config) = self.config.daemon.mqtt.clone() {
+            services.spawn(mqtt::bridge(self.clone(), config));
+        }
+        if let Some(config) = self.config.daemon.webhooks.clone() {
+            services.spawn(webhook::dispatch(self.clone(), config));
+        }
+
+        tokio::select! {
+            res = self.relay_events() => res,
+            Some(res) = services.join_next() => res?,
+        }
+    }
+}
+
+impl Control for Daemon {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    async fn execute(&self, command: Command) -> Result<(), timeflip::Error> {
+        let device = self.device().await;
+        match command {
+            Command::Pause => device.pause().await?,
+            Command::Unpause => device.unpause().await?,
+            Command::Lock => device.lock().await?,
+            Command::Unlock => device.unlock().await?,
+            Command::Brightness(value) => device.brightness(value).await?,
+            Command::Color(facet, color) => device.color(facet, color).await?,
+            Command::SetTime(time) => device.set_time(time).await?,
+            Command::Sync => device.sync(&self.config).await?,
+        }
+        let status = device.system_status().await?;
+        drop(device);
+        self.relay_status(status);
+        Ok(())
+    }
+
+    async fn state(&self) -> Result<DeviceState, timeflip::Error> {
+        let device = self.device().await;
+        let status = device.system_status().await?;
+        Ok(DeviceState {
+            facet: device.facet().await?,
+            paused: status.pause_mode,
+            locked: status.lock_mode,
+            battery: device.battery_level().await?,
+        })
+    }
+
+    async fn elapsed(&self, facet: Facet) -> Result<Duration, timeflip::Error> {
+        let settings = self.device().await.get_task(facet).await?;
+        Ok(Duration::from_secs(settings.seconds_since_start.into()))
+    }
+
+    fn record_error(&self, e: &timeflip::Error) {
+        Daemon::record_error(self, e)
+    }
+}
+
+/// Run the hooks configured in `config` on `events` without a TimeFlip2, e.g. read from a
+/// [journal](timeflip::journal).
+pub async fn replay_hooks(config: Config, events: BoxStream<'_, Envelope>) {
+    hooks::replay(config, events).await
+}
@@ -0,0 +1,250 @@
+//! Pausing while the computer sleeps or the session is locked.
+//!
+//! Sleep and session locks are announced by logind on the system bus. A delay inhibitor makes
+//! sure the TimeFlip2 is paused before the computer goes to sleep.
+
+use dbus::{
+    arg::OwnedFd,
+    message::MatchRule,
+    nonblock::{Proxy, SyncConnection},
+    Path,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+use super::{bus_lost, Command, Control, Daemon, Error};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SESSION: &str = "org.freedesktop.login1.Session";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The reasons for being away from the TimeFlip2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    Sleep,
+    Lock,
+}
+
+/// A change announced by logind.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    reason: Reason,
+    away: bool,
+}
+
+/// Delays sleep until the TimeFlip2 is paused, using a logind inhibitor.
+struct Inhibitor {
+    connection: Arc<SyncConnection>,
+    lock: Option<OwnedFd>,
+}
+
+impl Inhibitor {
+    fn manager(&self) -> Proxy<'_, Arc<SyncConnection>> {
+        Proxy::new(LOGIND, LOGIND_PATH, DBUS_TIMEOUT, self.connection.clone())
+    }
+
+    async fn inhibit(&mut self) {
+        let result: Result<(OwnedFd,), _> = self
+            .manager()
+            .method_call(
+                MANAGER,
+                "Inhibit",
+                ("sleep", "timeflippers", "Pause time tracking", "delay"),
+            )
+            .await;
+        match result {
+            Ok((fd,)) => self.lock = Some(fd),
+            Err(e) => log::warn!("cannot delay sleep for pausing: {e}"),
+        }
+    }
+
+    /// Let the computer go to sleep.
+    fn release(&mut self) {
+        self.lock = None;
+    }
+}
+
+/// Pauses the TimeFlip2 while away and unpauses it once back from all reasons.
+struct Away<C> {
+    control: Arc<C>,
+    /// Reasons for being away the TimeFlip2 was paused for.
+    reasons: Vec<Reason>,
+}
+
+impl<C: Control> Away<C> {
+    async fn change(&mut self, change: Change) {
+        if change.away {
+            if self.reasons.is_empty() {
+                match self.pause().await {
+                    Ok(true) => self.reasons.push(change.reason),
+                    Ok(false) => {}
+                    Err(e) => {
+                        log::warn!("cannot pause while away: {e}");
+                        self.control.record_error(&e);
+                    }
+                }
+            } else if !self.reasons.contains(&change.reason) {
+                self.reasons.push(change.reason);
+            }
+        } else {
+            let was_paused = !self.reasons.is_empty();
+            self.reasons.retain(|reason| *reason != change.reason);
+            if was_paused && self.reasons.is_empty() {
+                log::info!("back, unpausing");
+                if let Err(e) = self.control.execute(Command::Unpause).await {
+                    log::warn!("cannot unpause: {e}");
+                    self.control.record_error(&e);
+                }
+            }
+        }
+    }
+
+    /// Pause the TimeFlip2 unless it is paused already, returning whether it was paused.
+    async fn pause(&self) -> Result<bool, crate::timeflip::Error> {
+        if self.control.state().await?.paused {
+            return Ok(false);
+        }
+        log::info!("away, pausing");
+        self.control.execute(Command::Pause).await?;
+        Ok(true)
+    }
+}
+
+/// Pause the TimeFlip2 while away, failing when the connection to the system bus is lost.
+pub(super) async fn run(daemon: Arc<Daemon>) -> Result<(), Error> {
+    let (resource, connection) = dbus_tokio::connection::new_system_sync()?;
+    let mut lost = tokio::spawn(resource);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let mut away = Away {
+        control: daemon.clone(),
+        reasons: vec![],
+    };
+    let mut inhibitor = Inhibitor {
+        connection: connection.clone(),
+        lock: None,
+    };
+
+    // The matches are kept alive until the function returns.
+    let mut matches = vec![];
+    if daemon.config().daemon.pause_on_sleep {
+        let rule = MatchRule::new_signal(MANAGER, "PrepareForSleep")
+            .with_sender(LOGIND)
+            .with_path(LOGIND_PATH);
+        let tx = tx.clone();
+        matches.push(
+            connection
+                .add_match(rule)
+                .await?
+                .cb(move |_, (start,): (bool,)| {
+                    let _ = tx.send(Change {
+                        reason: Reason::Sleep,
+                        away: start,
+                    });
+                    true
+                }),
+        );
+        inhibitor.inhibit().await;
+    }
+    if daemon.config().daemon.pause_on_lock {
+        let (session,): (Path<'static>,) = inhibitor
+            .manager()
+            .method_call(MANAGER, "GetSession", ("auto",))
+            .await?;
+        for (signal, locked) in [("Lock", true), ("Unlock", false)] {
+            let rule = MatchRule::new_signal(SESSION, signal)
+                .with_sender(LOGIND)
+                .with_path(session.clone());
+            let tx = tx.clone();
+            matches.push(connection.add_match(rule).await?.cb(move |_, (): ()| {
+                let _ = tx.send(Change {
+                    reason: Reason::Lock,
+                    away: locked,
+                });
+                true
+            }));
+        }
+    }
+
+    let e = loop {
+        tokio::select! {
+            e = &mut lost => break e,
+            Some(change) = rx.recv() => {
+                away.change(change).await;
+                match (change.reason, change.away) {
+                    (Reason::Sleep, true) => inhibitor.release(),
+                    (Reason::Sleep, false) => inhibitor.inhibit().await,
+                    (Reason::Lock, _) => {}
+                }
+            }
+        }
+    };
+    drop(matches);
+    Err(bus_lost("system bus", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, daemon::fake::FakeControl};
+
+    fn away() -> (Arc<FakeControl>, Away<FakeControl>) {
+        let control = Arc::new(FakeControl::new(Config::default()));
+        let away = Away {
+            control: control.clone(),
+            reasons: vec![],
+        };
+        (control, away)
+    }
+
+    async fn change(away: &mut Away<FakeControl>, reason: Reason, away_now: bool) {
+        away.change(Change {
+            reason,
+            away: away_now,
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn sleep_while_locked() {
+        let (control, mut away) = away();
+        change(&mut away, Reason::Lock, true).await;
+        change(&mut away, Reason::Sleep, true).await;
+        assert_eq!(control.commands(), vec![Command::Pause]);
+
+        // Still locked after waking up.
+        change(&mut away, Reason::Sleep, false).await;
+        assert_eq!(control.commands(), vec![Command::Pause]);
+        change(&mut away, Reason::Lock, false).await;
+        assert_eq!(control.commands(), vec![Command::Pause, Command::Unpause]);
+
+        // Unlocked by the lock screen while waking up.
+        change(&mut away, Reason::Sleep, true).await;
+        change(&mut away, Reason::Lock, true).await;
+        change(&mut away, Reason::Lock, false).await;
+        change(&mut away, Reason::Sleep, false).await;
+        assert_eq!(
+            control.commands(),
+            vec![
+                Command::Pause,
+                Command::Unpause,
+                Command::Pause,
+                Command::Unpause
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn paused_by_user() {
+        let (control, mut away) = away();
+        control.update(|state| state.paused = true);
+        change(&mut away, Reason::Lock, true).await;
+        change(&mut away, Reason::Sleep, true).await;
+        change(&mut away, Reason::Sleep, false).await;
+        change(&mut away, Reason::Lock, false).await;
+        assert_eq!(control.commands(), vec![]);
+        assert!(control.state().await.unwrap().paused);
+    }
+}
@@ -0,0 +1,187 @@
+//! A fake TimeFlip2 for testing the daemon's services.
+
+use chrono::{TimeZone, Utc};
+use dbus::{channel::Channel, nonblock::SyncConnection};
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command as Process, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{Command, Control, DeviceState};
+use crate::{
+    config::Config,
+    timeflip::{self, Envelope, Event, SystemStatus},
+    types::{Facet, Minutes, Percent},
+};
+
+/// An envelope for `event`, received at the unix timestamp `seq` with an unknown clock offset.
+pub(super) fn envelope(seq: u64, event: Event) -> Envelope {
+    Envelope {
+        seq,
+        received: Utc.timestamp_opt(seq as i64, 0).unwrap(),
+        clock_offset: None,
+        event,
+    }
+}
+
+/// A system status as relayed by the daemon after a command.
+pub(super) fn status(paused: bool, locked: bool) -> SystemStatus {
+    SystemStatus {
+        lock_mode: locked,
+        pause_mode: paused,
+        auto_pause_time: Minutes(0),
+    }
+}
+
+/// Records the commands executed and applies them to its state.
+pub(super) struct FakeControl {
+    config: Config,
+    state: Mutex<DeviceState>,
+    elapsed: Mutex<Duration>,
+    commands: Mutex<Vec<Command>>,
+    disconnected: Mutex<bool>,
+}
+
+impl FakeControl {
+    pub(super) fn new(config: Config) -> Self {
+        FakeControl {
+            config,
+            state: Mutex::new(DeviceState {
+                facet: Facet::new(1).expect("is a valid facet"),
+                paused: false,
+                locked: false,
+                battery: Percent::new(80).expect("is a valid value"),
+            }),
+            elapsed: Mutex::default(),
+            commands: Mutex::default(),
+            disconnected: Mutex::default(),
+        }
+    }
+
+    /// Fail the commands and reads as if the TimeFlip2 was disconnected.
+    pub(super) fn disconnect(&self, disconnected: bool) {
+        *self.disconnected.lock().expect("not poisoned") = disconnected;
+    }
+
+    /// The error of a disconnected TimeFlip2.
+    pub(super) fn check_connected(&self) -> Result<(), timeflip::Error> {
+        if *self.disconnected.lock().expect("not poisoned") {
+            return Err(timeflip::Error::NoDevice);
+        }
+        Ok(())
+    }
+
+    /// Set the time since the active task was started, as counted by the TimeFlip2.
+    pub(super) fn set_elapsed(&self, elapsed: Duration) {
+        *self.elapsed.lock().expect("not poisoned") = elapsed;
+    }
+
+    /// Change the state, e.g. as if the TimeFlip2 had been paused by double tapping it.
+    pub(super) fn update(&self, f: impl FnOnce(&mut DeviceState)) {
+        f(&mut self.state.lock().expect("not poisoned"))
+    }
+
+    /// The commands executed so far.
+    pub(super) fn commands(&self) -> Vec<Command> {
+        self.commands.lock().expect("not poisoned").clone()
+    }
+}
+
+impl Control for FakeControl {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    async fn execute(&self, command: Command) -> Result<(), timeflip::Error> {
+        self.check_connected()?;
+        self.update(|state| match &command {
+            Command::Pause => state.paused = true,
+            Command::Unpause => state.paused = false,
+            Command::Lock => state.locked = true,
+            Command::Unlock => state.locked = false,
+            Command::Brightness(_) | Command::Color(..) | Command::SetTime(_) | Command::Sync => {}
+        });
+        self.commands.lock().expect("not poisoned").push(command);
+        Ok(())
+    }
+
+    async fn state(&self) -> Result<DeviceState, timeflip::Error> {
+        self.check_connected()?;
+        Ok(self.state.lock().expect("not poisoned").clone())
+    }
+
+    async fn elapsed(&self, _: Facet) -> Result<Duration, timeflip::Error> {
+        self.check_connected()?;
+        Ok(*self.elapsed.lock().expect("not poisoned"))
+    }
+
+    fn record_error(&self, _: &timeflip::Error) {}
+}
+
+const BUS_CONFIG: &str = r#"<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>"#;
+
+/// A private D-Bus, stopped when dropped.
+pub(super) struct Bus {
+    process: Child,
+    pub(super) address: String,
+    config: PathBuf,
+}
+
+impl Bus {
+    /// Start a bus for the test `name`, unless `dbus-daemon` is not installed.
+    pub(super) fn start(name: &str) -> Option<Bus> {
+        let config =
+            std::env::temp_dir().join(format!("timeflip-{name}-{}.conf", std::process::id()));
+        std::fs::write(&config, BUS_CONFIG).unwrap();
+        let mut process = match Process::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(process) => process,
+            Err(e) => {
+                eprintln!("skipping test, cannot start dbus-daemon: {e}");
+                return None;
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(process.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        Some(Bus {
+            process,
+            address: address.trim().into(),
+            config,
+        })
+    }
+
+    /// Open a new connection to the bus.
+    pub(super) fn connect(&self) -> Arc<SyncConnection> {
+        let mut channel = Channel::open_private(&self.address).unwrap();
+        channel.register().unwrap();
+        let (resource, connection) =
+            dbus_tokio::connection::from_channel::<SyncConnection>(channel).unwrap();
+        tokio::spawn(resource);
+        connection
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
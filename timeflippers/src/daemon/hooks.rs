@@ -0,0 +1,432 @@
+//! Commands run on the TimeFlip2's events.
+
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::{process, select, sync::broadcast::error::RecvError, task::JoinSet, time};
+
+use super::{Control, Daemon, Error};
+use crate::{
+    config::{Config, HookConcurrency, HooksConfig},
+    timeflip::{Envelope, Event},
+    types::Facet,
+};
+
+/// A command to run and its environment.
+struct Invocation {
+    command: String,
+    env: Vec<(&'static str, String)>,
+}
+
+async fn invoke(invocation: Invocation, timeout: Duration) {
+    let command = &invocation.command;
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(invocation.env)
+        .kill_on_drop(true)
+        .status();
+    match time::timeout(timeout, status).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => log::warn!("hook `{command}` failed: {status}"),
+        Ok(Err(e)) => log::error!("cannot run hook `{command}`: {e}"),
+        Err(_) => log::warn!("hook `{command}` timed out after {}s", timeout.as_secs()),
+    }
+}
+
+/// Starts invocations according to the concurrency policy.
+struct Runner {
+    concurrency: HookConcurrency,
+    timeout: Duration,
+    running: JoinSet<()>,
+    queued: VecDeque<Invocation>,
+}
+
+impl Runner {
+    fn new(global: Option<&HooksConfig>) -> Self {
+        Runner {
+            concurrency: global.map(|g| g.concurrency).unwrap_or_default(),
+            timeout: Duration::from_secs(global.map_or(30, |g| g.timeout)),
+            running: JoinSet::new(),
+            queued: VecDeque::new(),
+        }
+    }
+
+    fn start(&mut self, invocation: Invocation) {
+        self.running.spawn(invoke(invocation, self.timeout));
+    }
+
+    fn submit(&mut self, invocation: Invocation) {
+        while self.running.try_join_next().is_some() {}
+
+        match self.concurrency {
+            HookConcurrency::Queue => {
+                self.queued.push_back(invocation);
+                self.finished();
+            }
+            HookConcurrency::Parallel => self.start(invocation),
+            HookConcurrency::Skip if self.running.is_empty() => self.start(invocation),
+            HookConcurrency::Skip => {
+                log::info!(
+                    "skipping hook `{}`, another one is running",
+                    invocation.command
+                );
+            }
+            HookConcurrency::Replace => {
+                self.running.abort_all();
+                self.start(invocation);
+            }
+        }
+    }
+
+    /// Start the next queued invocation once the running ones have finished.
+    fn finished(&mut self) {
+        if self.running.is_empty() {
+            if let Some(invocation) = self.queued.pop_front() {
+                self.start(invocation);
+            }
+        }
+    }
+}
+
+/// State of the TimeFlip2 the hooks' environment is derived from.
+struct Hooks {
+    config: Config,
+    global: Option<HooksConfig>,
+    facet: Facet,
+    /// The time `facet` was flipped up.
+    since: DateTime<Utc>,
+    paused: bool,
+    /// The time `facet` was flipped up or pause mode was last left.
+    active_since: DateTime<Utc>,
+    /// The time pause mode was last entered.
+    paused_since: DateTime<Utc>,
+    low_battery: bool,
+}
+
+impl Hooks {
+    fn new(config: Config, facet: Facet, paused: bool, since: DateTime<Utc>) -> Self {
+        Hooks {
+            global: config.hooks.clone(),
+            config,
+            facet,
+            since,
+            paused,
+            active_since: since,
+            paused_since: since,
+            low_battery: false,
+        }
+    }
+
+    fn invocation(&self, command: &str, event: &str, facet: &Facet) -> Invocation {
+        let env = vec![
+            ("TIMEFLIP_EVENT", event.to_string()),
+            ("TIMEFLIP_FACET", facet.index().to_string()),
+            (
+                "TIMEFLIP_SIDE",
+                self.config.sides[facet.index_zero()]
+                    .name
+                    .clone()
+                    .unwrap_or_default(),
+            ),
+        ];
+        Invocation {
+            command: command.to_string(),
+            env,
+        }
+    }
+
+    /// The commands to run for `envelope`, with the time elapsed until it was received.
+    fn event(&mut self, envelope: Envelope) -> Vec<Invocation> {
+        let now = envelope.received;
+        let elapsed = |since: DateTime<Utc>| (now - since).num_seconds().max(0).to_string();
+        let mut invocations = vec![];
+        let global = self.global.as_ref();
+
+        match envelope.event {
+            Event::Facet(facet) if facet != self.facet => {
+                let elapsed = elapsed(self.since);
+                let previous = self.facet.index().to_string();
+
+                let left = &self.config.sides[self.facet.index_zero()];
+                for command in [
+                    left.on_leave.as_deref(),
+                    global.and_then(|g| g.on_leave.as_deref()),
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    let mut invocation = self.invocation(command, "leave", &self.facet);
+                    invocation.env.push(("TIMEFLIP_ELAPSED", elapsed.clone()));
+                    invocations.push(invocation);
+                }
+
+                let entered = &self.config.sides[facet.index_zero()];
+                for command in [
+                    entered.on_enter.as_deref(),
+                    global.and_then(|g| g.on_enter.as_deref()),
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    let mut invocation = self.invocation(command, "enter", &facet);
+                    invocation
+                        .env
+                        .push(("TIMEFLIP_PREVIOUS_FACET", previous.clone()));
+                    invocation.env.push(("TIMEFLIP_ELAPSED", elapsed.clone()));
+                    invocations.push(invocation);
+                }
+
+                self.facet = facet;
+                self.since = now;
+                self.active_since = now;
+            }
+            Event::DoubleTap { facet, pause } if pause != self.paused => {
+                let side = &self.config.sides[facet.index_zero()];
+                let (name, commands, since) = if pause {
+                    (
+                        "pause",
+                        [
+                            side.on_pause.as_deref(),
+                            global.and_then(|g| g.on_pause.as_deref()),
+                        ],
+                        self.active_since,
+                    )
+                } else {
+                    (
+                        "unpause",
+                        [
+                            side.on_unpause.as_deref(),
+                            global.and_then(|g| g.on_unpause.as_deref()),
+                        ],
+                        self.paused_since,
+                    )
+                };
+                let elapsed = elapsed(since);
+                for command in commands.into_iter().flatten() {
+                    let mut invocation = self.invocation(command, name, &facet);
+                    invocation.env.push(("TIMEFLIP_ELAPSED", elapsed.clone()));
+                    invocations.push(invocation);
+                }
+                self.paused = pause;
+                if pause {
+                    self.paused_since = now;
+                } else {
+                    self.active_since = now;
+                }
+            }
+            Event::BatteryLevel(battery) => {
+                let low = battery.get() < self.config.daemon.low_battery();
+                if let (true, false, Some(command)) = (
+                    low,
+                    self.low_battery,
+                    global.and_then(|g| g.on_low_battery.as_ref()),
+                ) {
+                    let mut invocation = self.invocation(command, "low_battery", &self.facet);
+                    invocation
+                        .env
+                        .push(("TIMEFLIP_BATTERY", battery.get().to_string()));
+                    invocations.push(invocation);
+                }
+                self.low_battery = low;
+            }
+            _ => {}
+        }
+        invocations
+    }
+}
+
+/// Run the configured commands on the TimeFlip2's events.
+pub(super) async fn run(daemon: Arc<Daemon>) -> Result<(), Error> {
+    let mut events = daemon.subscribe();
+    let state = Control::state(daemon.as_ref()).await?;
+    let since = daemon.started_or_now(state.facet.clone()).await;
+
+    let mut hooks = Hooks::new(daemon.config().clone(), state.facet, state.paused, since);
+    let mut runner = Runner::new(hooks.global.as_ref());
+
+    loop {
+        select! {
+            event = events.recv() => match event {
+                Ok(envelope) => {
+                    for invocation in hooks.event(envelope) {
+                        runner.submit(invocation);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => log::warn!("hooks missed {n} events"),
+                Err(RecvError::Closed) => break,
+            },
+            Some(_) = runner.running.join_next(), if !runner.running.is_empty() => {
+                runner.finished();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the configured commands on `events`, e.g. read from a journal.
+///
+/// The first event with a facet sets the initial state, no commands are run for it. Elapsed
+/// times are derived from the events' `received` times.
+pub(super) async fn replay(config: Config, mut events: BoxStream<'_, Envelope>) {
+    let mut hooks = loop {
+        let Some(envelope) = events.next().await else {
+            return;
+        };
+        match envelope.event {
+            Event::Facet(facet) => break Hooks::new(config, facet, false, envelope.received),
+            Event::DoubleTap { facet, pause } => {
+                break Hooks::new(config, facet, pause, envelope.received)
+            }
+            _ => {}
+        }
+    };
+    let mut runner = Runner::new(hooks.global.as_ref());
+
+    loop {
+        select! {
+            event = events.next() => match event {
+                Some(event) => {
+                    for invocation in hooks.event(event) {
+                        runner.submit(invocation);
+                    }
+                }
+                None => break,
+            },
+            Some(_) = runner.running.join_next(), if !runner.running.is_empty() => {
+                runner.finished();
+            }
+        }
+    }
+    while runner.running.join_next().await.is_some() {
+        runner.finished();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64, event: Event) -> Envelope {
+        Envelope {
+            seq: secs as u64,
+            received: time(secs),
+            clock_offset: None,
+            event,
+        }
+    }
+
+    fn time(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, 0).unwrap()
+    }
+
+    fn facet(index: usize) -> Facet {
+        Facet::new(index).unwrap()
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.sides[0].name = Some("Work".into());
+        config.sides[0].on_leave = Some("leave work".into());
+        config.sides[0].on_pause = Some("pause work".into());
+        config.sides[1].on_enter = Some("enter break".into());
+        config.hooks = Some(HooksConfig {
+            on_enter: Some("enter".into()),
+            on_leave: None,
+            on_pause: Some("pause".into()),
+            on_unpause: Some("unpause".into()),
+            on_low_battery: Some("battery".into()),
+            timeout: 30,
+            concurrency: HookConcurrency::default(),
+        });
+        config
+    }
+
+    fn env<'a>(invocation: &'a Invocation, name: &str) -> Option<&'a str> {
+        invocation
+            .env
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn commands(invocations: &[Invocation]) -> Vec<&str> {
+        invocations.iter().map(|i| i.command.as_str()).collect()
+    }
+
+    #[test]
+    fn flip() {
+        let mut hooks = Hooks::new(config(), facet(1), false, time(0));
+        assert!(hooks.event(at(10, Event::Facet(facet(1)))).is_empty());
+
+        let invocations = hooks.event(at(90, Event::Facet(facet(2))));
+        assert_eq!(
+            commands(&invocations),
+            ["leave work", "enter break", "enter"]
+        );
+
+        let leave = &invocations[0];
+        assert_eq!(env(leave, "TIMEFLIP_EVENT"), Some("leave"));
+        assert_eq!(env(leave, "TIMEFLIP_FACET"), Some("1"));
+        assert_eq!(env(leave, "TIMEFLIP_SIDE"), Some("Work"));
+        assert_eq!(env(leave, "TIMEFLIP_PREVIOUS_FACET"), None);
+        assert_eq!(env(leave, "TIMEFLIP_ELAPSED"), Some("90"));
+
+        let enter = &invocations[1];
+        assert_eq!(env(enter, "TIMEFLIP_EVENT"), Some("enter"));
+        assert_eq!(env(enter, "TIMEFLIP_FACET"), Some("2"));
+        assert_eq!(env(enter, "TIMEFLIP_SIDE"), Some(""));
+        assert_eq!(env(enter, "TIMEFLIP_PREVIOUS_FACET"), Some("1"));
+
+        let invocations = hooks.event(at(100, Event::Facet(facet(1))));
+        assert_eq!(commands(&invocations), ["enter"]);
+        assert_eq!(env(&invocations[0], "TIMEFLIP_ELAPSED"), Some("10"));
+    }
+
+    #[test]
+    fn pause() {
+        let mut hooks = Hooks::new(config(), facet(1), false, time(0));
+        let pause = |secs, pause| {
+            at(
+                secs,
+                Event::DoubleTap {
+                    facet: facet(1),
+                    pause,
+                },
+            )
+        };
+
+        let invocations = hooks.event(pause(60, true));
+        assert_eq!(commands(&invocations), ["pause work", "pause"]);
+        assert_eq!(env(&invocations[0], "TIMEFLIP_EVENT"), Some("pause"));
+        assert_eq!(env(&invocations[0], "TIMEFLIP_ELAPSED"), Some("60"));
+        assert!(hooks.event(pause(70, true)).is_empty());
+        let invocations = hooks.event(pause(75, false));
+        assert_eq!(commands(&invocations), ["unpause"]);
+        assert_eq!(env(&invocations[0], "TIMEFLIP_ELAPSED"), Some("15"));
+
+        // Only the time since pause mode was left counts, not the earlier pause.
+        let invocations = hooks.event(pause(100, true));
+        assert_eq!(env(&invocations[0], "TIMEFLIP_ELAPSED"), Some("25"));
+        assert_eq!(hooks.event(pause(110, false)).len(), 1);
+
+        // Flipping restarts the active time.
+        hooks.event(at(120, Event::Facet(facet(2))));
+        hooks.event(at(130, Event::Facet(facet(1))));
+        let invocations = hooks.event(pause(160, true));
+        assert_eq!(env(&invocations[0], "TIMEFLIP_ELAPSED"), Some("30"));
+    }
+
+    #[test]
+    fn low_battery() {
+        let mut hooks = Hooks::new(config(), facet(1), false, time(0));
+        let battery = |level| at(0, Event::BatteryLevel(crate::Percent::new(level).unwrap()));
+
+        assert!(hooks.event(battery(50)).is_empty());
+        let invocations = hooks.event(battery(10));
+        assert_eq!(commands(&invocations), ["battery"]);
+        assert_eq!(env(&invocations[0], "TIMEFLIP_BATTERY"), Some("10"));
+        assert!(hooks.event(battery(9)).is_empty());
+    }
+}
@@ -0,0 +1,729 @@
+//! HTTP API with a WebSocket relaying events.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, Request, State,
+    },
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::{future::Future, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    select,
+    sync::broadcast::{self, error::RecvError},
+};
+
+use super::{metrics::Metrics, Activity, Command, Control, Daemon, Error};
+use crate::{
+    history,
+    timeflip::{self, Entry, Envelope, SyncState, SystemStatus},
+    types::{Color, Facet, Percent},
+    view,
+};
+
+/// Error response of the API.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    /// Kind of the error communicating with the TimeFlip2, if any.
+    gatt_error: Option<&'static str>,
+}
+
+/// Kind of the error communicating with the TimeFlip2, passed on to the metrics.
+#[derive(Clone, Copy)]
+struct GattError(&'static str);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(json!({ "error": self.message }))).into_response();
+        if let Some(kind) = self.gatt_error {
+            response.extensions_mut().insert(GattError(kind));
+        }
+        response
+    }
+}
+
+impl From<timeflip::Error> for ApiError {
+    fn from(e: timeflip::Error) -> Self {
+        ApiError {
+            status: StatusCode::BAD_GATEWAY,
+            message: e.to_string(),
+            gatt_error: Some(e.kind()),
+        }
+    }
+}
+
+impl From<history::Error> for ApiError {
+    fn from(e: history::Error) -> Self {
+        let gatt_error = match &e {
+            history::Error::TimeFlip(e) => Some(e.kind()),
+            _ => None,
+        };
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: e.to_string(),
+            gatt_error,
+        }
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Query selecting history entries, like the arguments of `timeflip history`.
+#[derive(Deserialize)]
+struct HistoryQuery {
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    start_with: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct BrightnessRequest {
+    value: Percent,
+}
+
+#[derive(Deserialize)]
+struct ColorRequest {
+    facet: Facet,
+    color: Color,
+}
+
+/// What the HTTP API reads from the daemon in addition to [Control].
+trait Api: Control {
+    /// What the TimeFlip2 is currently tracking, if connected.
+    fn activity(&self) -> Option<Activity>;
+
+    /// Read the system status.
+    fn status(&self) -> impl Future<Output = Result<SystemStatus, timeflip::Error>> + Send;
+
+    /// Read the synchronization state.
+    fn sync_state(&self) -> impl Future<Output = Result<SyncState, timeflip::Error>> + Send;
+
+    /// Read the TimeFlip2's clock.
+    fn time(&self) -> impl Future<Output = Result<DateTime<Utc>, timeflip::Error>> + Send;
+
+    /// Read the history, starting with entry `start_with` or the last saved one.
+    fn history(
+        &self,
+        start_with: Option<u32>,
+    ) -> impl Future<Output = Result<Vec<Entry>, history::Error>> + Send;
+
+    /// Write the configuration to the TimeFlip2.
+    fn write_config(&self) -> impl Future<Output = Result<(), timeflip::Error>> + Send;
+
+    /// The collected metrics.
+    fn metrics(&self) -> &Metrics;
+
+    /// Receive the TimeFlip2's events with the time they were received.
+    fn subscribe(&self) -> broadcast::Receiver<Envelope>;
+}
+
+impl Api for Daemon {
+    fn activity(&self) -> Option<Activity> {
+        Daemon::activity(self)
+    }
+
+    async fn status(&self) -> Result<SystemStatus, timeflip::Error> {
+        self.device().await.system_status().await
+    }
+
+    async fn sync_state(&self) -> Result<SyncState, timeflip::Error> {
+        self.device().await.sync_state().await
+    }
+
+    async fn time(&self) -> Result<DateTime<Utc>, timeflip::Error> {
+        self.device().await.time().await
+    }
+
+    async fn history(&self, start_with: Option<u32>) -> Result<Vec<Entry>, history::Error> {
+        let device = self.device().await;
+        history::update(&device, self.config().daemon.history.as_deref(), start_with).await
+    }
+
+    async fn write_config(&self) -> Result<(), timeflip::Error> {
+        self.device()
+            .await
+            .write_config(self.config().clone())
+            .await
+    }
+
+    fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        Daemon::subscribe(self)
+    }
+}
+
+async fn battery<A: Api>(State(api): State<Arc<A>>) -> ApiResult<Json<serde_json::Value>> {
+    let battery = api.state().await?.battery;
+    Ok(Json(json!({ "battery": battery })))
+}
+
+async fn facet<A: Api>(State(api): State<Arc<A>>) -> ApiResult<Json<serde_json::Value>> {
+    let facet = api.state().await?.facet;
+    Ok(Json(
+        json!({ "facet": facet, "name": api.side_name(facet) }),
+    ))
+}
+
+async fn activity<A: Api>(State(api): State<Arc<A>>) -> Json<Option<Activity>> {
+    Json(api.activity())
+}
+
+async fn status<A: Api>(State(api): State<Arc<A>>) -> ApiResult<Json<SystemStatus>> {
+    Ok(Json(api.status().await?))
+}
+
+async fn sync_state<A: Api>(State(api): State<Arc<A>>) -> ApiResult<Json<SyncState>> {
+    Ok(Json(api.sync_state().await?))
+}
+
+async fn time<A: Api>(State(api): State<Arc<A>>) -> ApiResult<Json<serde_json::Value>> {
+    let time = api.time().await?;
+    Ok(Json(json!({ "time": time })))
+}
+
+async fn history<A: Api>(
+    State(api): State<Arc<A>>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Json<Vec<Entry>>> {
+    let entries = api.history(query.start_with).await?;
+
+    let all = view::History::new(entries, api.config().clone());
+    let dates = view::DateRange {
+        since: query.since,
+        until: query.until,
+    };
+    let filtered = dates.filter(&all);
+    Ok(Json(
+        filtered.entries().iter().map(|e| (*e).clone()).collect(),
+    ))
+}
+
+async fn execute(api: &impl Api, command: Command) -> ApiResult<StatusCode> {
+    api.execute(command).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn lock<A: Api>(State(api): State<Arc<A>>) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Lock).await
+}
+
+async fn unlock<A: Api>(State(api): State<Arc<A>>) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Unlock).await
+}
+
+async fn pause<A: Api>(State(api): State<Arc<A>>) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Pause).await
+}
+
+async fn unpause<A: Api>(State(api): State<Arc<A>>) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Unpause).await
+}
+
+async fn brightness<A: Api>(
+    State(api): State<Arc<A>>,
+    Json(request): Json<BrightnessRequest>,
+) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Brightness(request.value)).await
+}
+
+async fn color<A: Api>(
+    State(api): State<Arc<A>>,
+    Json(request): Json<ColorRequest>,
+) -> ApiResult<StatusCode> {
+    execute(api.as_ref(), Command::Color(request.facet, request.color)).await
+}
+
+async fn write_config<A: Api>(State(api): State<Arc<A>>) -> ApiResult<StatusCode> {
+    api.write_config().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn metrics<A: Api>(State(api): State<Arc<A>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        api.metrics().render(api.as_ref()),
+    )
+}
+
+/// Count the errors communicating with the TimeFlip2 in the metrics.
+async fn count_errors<A: Api>(State(api): State<Arc<A>>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if let Some(GattError(kind)) = response.extensions().get() {
+        api.metrics().gatt_error(kind);
+    }
+    response
+}
+
+/// Whether the request carries `token` as bearer token, or no token is required.
+fn is_authorized(headers: &HeaderMap, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| value == token)
+}
+
+/// Reject requests without the configured bearer token.
+async fn authorize<A: Api>(
+    State(api): State<Arc<A>>,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    if !is_authorized(request.headers(), api.config().daemon.token.as_deref()) {
+        return Err(ApiError {
+            status: StatusCode::UNAUTHORIZED,
+            message: "missing or invalid bearer token".to_string(),
+            gatt_error: None,
+        });
+    }
+    Ok(next.run(request).await)
+}
+
+async fn events<A: Api>(State(api): State<Arc<A>>, ws: WebSocketUpgrade) -> Response {
+    let events = api.subscribe();
+    ws.on_upgrade(move |socket| relay(socket, events))
+}
+
+/// Send each event with its envelope as JSON text message until the client disconnects.
+async fn relay(mut socket: WebSocket, mut events: broadcast::Receiver<Envelope>) {
+    loop {
+        select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = serde_json::to_string(&event).expect("event is serializable");
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(n)) => log::warn!("websocket client missed {n} events"),
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn router<A: Api>(api: Arc<A>) -> Router {
+    Router::new()
+        .route("/battery", get(battery::<A>))
+        .route("/facet", get(facet::<A>))
+        .route("/activity", get(activity::<A>))
+        .route("/status", get(status::<A>))
+        .route("/sync-state", get(sync_state::<A>))
+        .route("/time", get(time::<A>))
+        .route("/history", get(history::<A>))
+        .route("/lock", post(lock::<A>))
+        .route("/unlock", post(unlock::<A>))
+        .route("/pause", post(pause::<A>))
+        .route("/unpause", post(unpause::<A>))
+        .route("/brightness", post(brightness::<A>))
+        .route("/color", post(color::<A>))
+        .route("/write-config", post(write_config::<A>))
+        .route("/events", get(events::<A>))
+        .route("/metrics", get(metrics::<A>))
+        .layer(middleware::from_fn_with_state(
+            api.clone(),
+            count_errors::<A>,
+        ))
+        .layer(middleware::from_fn_with_state(api.clone(), authorize::<A>))
+        .with_state(api)
+}
+
+/// Serve the HTTP API on `addr`.
+pub(super) async fn serve(daemon: Arc<Daemon>, addr: SocketAddr) -> Result<(), Error> {
+    if daemon.config().daemon.token.is_none() && !addr.ip().is_loopback() {
+        log::warn!("serving HTTP API on {addr} without a token, anyone can control the TimeFlip2");
+    }
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("serving HTTP API on {addr}");
+    axum::serve(listener, router(daemon)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::{
+            fake::{self, envelope, FakeControl},
+            DeviceState,
+        },
+        timeflip::{Event, SyncType},
+    };
+    use chrono::{Local, NaiveDateTime, TimeZone};
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+        time::timeout,
+    };
+
+    /// The daemon's state the API reads, backed by a [FakeControl].
+    struct Fake {
+        control: FakeControl,
+        entries: Vec<Entry>,
+        metrics: Metrics,
+        events: broadcast::Sender<Envelope>,
+    }
+
+    impl Fake {
+        fn new(config: Config) -> Self {
+            Fake {
+                control: FakeControl::new(config),
+                entries: vec![],
+                metrics: Metrics::default(),
+                events: broadcast::channel(4).0,
+            }
+        }
+    }
+
+    impl Control for Fake {
+        fn config(&self) -> &Config {
+            self.control.config()
+        }
+
+        async fn execute(&self, command: Command) -> Result<(), timeflip::Error> {
+            self.control.execute(command).await
+        }
+
+        async fn state(&self) -> Result<DeviceState, timeflip::Error> {
+            self.control.state().await
+        }
+
+        async fn elapsed(&self, facet: Facet) -> Result<Duration, timeflip::Error> {
+            self.control.elapsed(facet).await
+        }
+
+        fn record_error(&self, e: &timeflip::Error) {
+            self.control.record_error(e)
+        }
+    }
+
+    impl Api for Fake {
+        fn activity(&self) -> Option<Activity> {
+            None
+        }
+
+        async fn status(&self) -> Result<SystemStatus, timeflip::Error> {
+            let state = self.control.state().await?;
+            Ok(fake::status(state.paused, state.locked))
+        }
+
+        async fn sync_state(&self) -> Result<SyncState, timeflip::Error> {
+            self.control.check_connected()?;
+            Ok(SyncState {
+                sync: SyncType::Synchronized,
+                accelerometer_error: false,
+                flash_error: false,
+            })
+        }
+
+        async fn time(&self) -> Result<DateTime<Utc>, timeflip::Error> {
+            self.control.check_connected()?;
+            Ok(Utc.timestamp_opt(1_700_000_000, 0).unwrap())
+        }
+
+        async fn history(&self, start_with: Option<u32>) -> Result<Vec<Entry>, history::Error> {
+            self.control.check_connected()?;
+            let start_with = start_with.unwrap_or(0);
+            Ok(self
+                .entries
+                .iter()
+                .filter(|e| e.id >= start_with)
+                .cloned()
+                .collect())
+        }
+
+        async fn write_config(&self) -> Result<(), timeflip::Error> {
+            self.control.check_connected()
+        }
+
+        fn metrics(&self) -> &Metrics {
+            &self.metrics
+        }
+
+        fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+            self.events.subscribe()
+        }
+    }
+
+    /// Serve the API for `fake`, returning its address.
+    async fn serve(fake: Arc<Fake>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(fake)).await });
+        addr
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> (StatusCode, Value) {
+        let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+        let status = response.status().as_u16();
+        let body = response.json().await.unwrap_or(Value::Null);
+        (StatusCode::from_u16(status).unwrap(), body)
+    }
+
+    async fn post(addr: SocketAddr, path: &str, body: Value) -> (StatusCode, Value) {
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}{path}"))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        let body = response.json().await.unwrap_or(Value::Null);
+        (StatusCode::from_u16(status).unwrap(), body)
+    }
+
+    fn entry(id: u32, start: &str) -> Entry {
+        let start = NaiveDateTime::parse_from_str(start, "%Y-%m-%d %H:%M").unwrap();
+        Entry {
+            id,
+            facet: Facet::new(1).unwrap(),
+            pause: false,
+            time: Local.from_local_datetime(&start).unwrap().into(),
+            duration: Duration::from_secs(3600),
+        }
+    }
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn bearer_token() {
+        assert!(is_authorized(&HeaderMap::new(), None));
+        assert!(!is_authorized(&HeaderMap::new(), Some("secret")));
+        assert!(is_authorized(&headers("Bearer secret"), Some("secret")));
+        assert!(!is_authorized(&headers("Bearer other"), Some("secret")));
+        assert!(!is_authorized(&headers("Basic secret"), Some("secret")));
+        assert!(!is_authorized(&headers("secret"), Some("secret")));
+    }
+
+    #[tokio::test]
+    async fn reads() {
+        let mut config = Config::default();
+        config.sides[0].name = Some("Work".into());
+        let addr = serve(Arc::new(Fake::new(config))).await;
+
+        assert_eq!(
+            get(addr, "/battery").await,
+            (StatusCode::OK, json!({ "battery": 80 }))
+        );
+        assert_eq!(
+            get(addr, "/facet").await,
+            (StatusCode::OK, json!({ "facet": 1, "name": "Work" }))
+        );
+        let (status, body) = get(addr, "/status").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["pause_mode"], false);
+        assert_eq!(get(addr, "/activity").await, (StatusCode::OK, Value::Null));
+        assert_eq!(
+            get(addr, "/time").await,
+            (StatusCode::OK, json!({ "time": "2023-11-14T22:13:20Z" }))
+        );
+        let (status, body) = get(addr, "/sync-state").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["sync"], "Synchronized");
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let fake = Arc::new(Fake::new(Config::default()));
+        let addr = serve(fake.clone()).await;
+
+        assert_eq!(
+            post(addr, "/pause", Value::Null).await.0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post(addr, "/brightness", json!({ "value": 50 })).await.0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post(
+                addr,
+                "/color",
+                json!({ "facet": 2, "color": { "red": 1, "green": 2, "blue": 3 } })
+            )
+            .await
+            .0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post(addr, "/write-config", Value::Null).await.0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post(addr, "/brightness", json!({ "value": 101 })).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            fake.control.commands(),
+            [
+                Command::Pause,
+                Command::Brightness(Percent::new(50).unwrap()),
+                Command::Color(Facet::new(2).unwrap(), Color::from_rgb(1, 2, 3)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn timeflip_errors() {
+        let fake = Arc::new(Fake::new(Config::default()));
+        fake.control.disconnect(true);
+        let addr = serve(fake.clone()).await;
+
+        assert_eq!(
+            get(addr, "/battery").await,
+            (
+                StatusCode::BAD_GATEWAY,
+                json!({ "error": "no TimeFlip2 bluetooth device found" })
+            )
+        );
+        assert_eq!(
+            post(addr, "/lock", Value::Null).await.0,
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            get(addr, "/history").await.0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(fake.control.commands().is_empty());
+
+        let metrics = reqwest::get(format!("http://{addr}/metrics"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(metrics
+            .lines()
+            .any(|line| line == r#"timeflip_gatt_errors_total{kind="no_device"} 3"#));
+    }
+
+    #[tokio::test]
+    async fn history_dates() {
+        let mut fake = Fake::new(Config::default());
+        fake.entries = vec![
+            entry(1, "2024-03-04 09:00"),
+            entry(2, "2024-03-05 09:00"),
+            entry(3, "2024-03-05 23:30"),
+            entry(4, "2024-03-06 00:30"),
+        ];
+        let addr = serve(Arc::new(fake)).await;
+        let ids = |body: Value| -> Vec<u64> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|e| e["id"].as_u64().unwrap())
+                .collect()
+        };
+
+        let (status, body) = get(addr, "/history").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(body), [1, 2, 3, 4]);
+        let (_, body) = get(addr, "/history?since=2024-03-05&until=2024-03-05").await;
+        assert_eq!(ids(body), [2, 3]);
+        let (_, body) = get(addr, "/history?since=2024-03-05").await;
+        assert_eq!(ids(body), [2, 3, 4]);
+        let (_, body) = get(addr, "/history?until=2024-03-04").await;
+        assert_eq!(ids(body), [1]);
+        let (_, body) = get(addr, "/history?start_with=3").await;
+        assert_eq!(ids(body), [3, 4]);
+        assert_eq!(
+            get(addr, "/history?since=yesterday").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn authorization() {
+        let mut config = Config::default();
+        config.daemon.token = Some("secret".into());
+        let addr = serve(Arc::new(Fake::new(config))).await;
+
+        let (status, body) = get(addr, "/activity").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, json!({ "error": "missing or invalid bearer token" }));
+
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/activity"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn websocket_events() {
+        let fake = Arc::new(Fake::new(Config::default()));
+        let addr = serve(fake.clone()).await;
+
+        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        stream
+            .get_mut()
+            .write_all(
+                b"GET /events HTTP/1.1\r\n\
+                  Host: localhost\r\n\
+                  Upgrade: websocket\r\n\
+                  Connection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("HTTP/1.1 101"), "{line}");
+        while line != "\r\n" {
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+        }
+
+        let sent = envelope(7, Event::Facet(Facet::new(3).unwrap()));
+        fake.events.send(sent.clone()).unwrap();
+
+        // An unmasked text frame from the server.
+        let frame = async {
+            let mut header = [0; 2];
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x81);
+            let len = match header[1] {
+                126 => usize::from(stream.read_u16().await.unwrap()),
+                len => usize::from(len),
+            };
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).await.unwrap();
+            payload
+        };
+        let payload = timeout(Duration::from_secs(5), frame).await.unwrap();
+        let received: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(received, serde_json::to_value(&sent).unwrap());
+    }
+}
@@ -0,0 +1,353 @@
+//! Metrics in the Prometheus text format.
+
+use chrono::{DateTime, Utc};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{interval, MissedTickBehavior};
+
+use super::{Control, Daemon, Error};
+use crate::{
+    timeflip::{Entry, Event, SystemStatus},
+    types::Facet,
+};
+
+/// Interval in which the state not announced by events is read.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+struct State {
+    battery: Option<u8>,
+    facet: Option<u8>,
+    paused: Option<bool>,
+    locked: Option<bool>,
+    connected: bool,
+    clock_drift: Option<i64>,
+    last_sync: Option<DateTime<Utc>>,
+    flips: BTreeMap<u8, u64>,
+    reconnects: u64,
+    gatt_errors: BTreeMap<&'static str, u64>,
+    script_failures: u64,
+    tracked: BTreeMap<u8, Duration>,
+}
+
+/// The TimeFlip2's state and statistics collected by the daemon.
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    state: Mutex<State>,
+}
+
+impl Metrics {
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.state.lock().expect("metrics are not poisoned"));
+    }
+
+    pub(super) fn connected(&self) {
+        self.update(|s| s.connected = true);
+    }
+
+    pub(super) fn reconnected(&self) {
+        self.update(|s| {
+            s.connected = true;
+            s.reconnects += 1;
+        });
+    }
+
+    pub(super) fn event(&self, event: &Event) {
+        self.update(|s| match event {
+            Event::Disconnected => s.connected = false,
+            Event::BatteryLevel(battery) => s.battery = Some(battery.get()),
+            Event::Facet(facet) => {
+                if s.facet != Some(facet.index()) {
+                    *s.flips.entry(facet.index()).or_default() += 1;
+                }
+                s.facet = Some(facet.index());
+            }
+            Event::DoubleTap { pause, .. } => s.paused = Some(*pause),
+            Event::Event(_) | Event::SyncState(_) | Event::History(_) => {}
+        });
+    }
+
+    pub(super) fn entry(&self, entry: &Entry) {
+        if !entry.pause {
+            self.update(|s| *s.tracked.entry(entry.facet.index()).or_default() += entry.duration);
+        }
+    }
+
+    pub(super) fn synced(&self) {
+        self.update(|s| s.last_sync = Some(Utc::now()));
+    }
+
+    /// Count a failed communication with the TimeFlip2.
+    ///
+    /// Only errors reported through [Daemon::record_error()] or returned by the HTTP API are
+    /// counted, errors handled elsewhere are only logged.
+    pub(super) fn gatt_error(&self, kind: &'static str) {
+        self.update(|s| *s.gatt_errors.entry(kind).or_default() += 1);
+    }
+
+    pub(super) fn script_failure(&self) {
+        self.update(|s| s.script_failures += 1);
+    }
+
+    pub(super) fn status(&self, status: &SystemStatus) {
+        self.update(|s| {
+            s.paused = Some(status.pause_mode);
+            s.locked = Some(status.lock_mode);
+        });
+    }
+
+    /// Render the metrics, labelling facets with the configured side names.
+    pub(super) fn render(&self, control: &impl Control) -> String {
+        let s = self.state.lock().expect("metrics are not poisoned");
+        let mut out = String::new();
+        let bool_gauge = |v: Option<bool>| v.map(|v| u8::from(v).to_string());
+        let facet_labels = |facet: u8| {
+            let name = Facet::new(usize::from(facet))
+                .ok()
+                .and_then(|f| control.side_name(f))
+                .unwrap_or_default();
+            format!("facet=\"{facet}\",side=\"{}\"", escape(name))
+        };
+
+        let gauges = [
+            (
+                "timeflip_battery_percent",
+                "Battery level of the TimeFlip2.",
+                s.battery.map(|v| v.to_string()),
+            ),
+            (
+                "timeflip_facet",
+                "Index of the facet facing up.",
+                s.facet.map(|v| v.to_string()),
+            ),
+            (
+                "timeflip_paused",
+                "Whether the TimeFlip2 is paused.",
+                bool_gauge(s.paused),
+            ),
+            (
+                "timeflip_locked",
+                "Whether the TimeFlip2 is locked.",
+                bool_gauge(s.locked),
+            ),
+            (
+                "timeflip_connected",
+                "Whether the TimeFlip2 is connected.",
+                bool_gauge(Some(s.connected)),
+            ),
+            (
+                "timeflip_clock_drift_seconds",
+                "Difference between the TimeFlip2's and the host's clock.",
+                s.clock_drift.map(|v| v.to_string()),
+            ),
+            (
+                "timeflip_last_sync_timestamp_seconds",
+                "Time of the last successful history sync.",
+                s.last_sync.map(|t| t.timestamp().to_string()),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            if let Some(value) = value {
+                header(&mut out, name, help, "gauge");
+                let _ = writeln!(out, "{name} {value}");
+            }
+        }
+
+        header(
+            &mut out,
+            "timeflip_reconnects_total",
+            "Reconnects after the TimeFlip2 disconnected.",
+            "counter",
+        );
+        let _ = writeln!(out, "timeflip_reconnects_total {}", s.reconnects);
+
+        header(
+            &mut out,
+            "timeflip_flips_total",
+            "Flips to each facet.",
+            "counter",
+        );
+        for (facet, count) in &s.flips {
+            let _ = writeln!(
+                out,
+                "timeflip_flips_total{{{}}} {count}",
+                facet_labels(*facet)
+            );
+        }
+
+        header(
+            &mut out,
+            "timeflip_tracked_seconds_total",
+            "Time tracked on each facet in finished history entries.",
+            "counter",
+        );
+        for (facet, duration) in &s.tracked {
+            let _ = writeln!(
+                out,
+                "timeflip_tracked_seconds_total{{{}}} {}",
+                facet_labels(*facet),
+                duration.as_secs()
+            );
+        }
+
+        header(
+            &mut out,
+            "timeflip_gatt_errors_total",
+            "Failed communication with the TimeFlip2 by kind, as reported by the daemon's services and HTTP API.",
+            "counter",
+        );
+        for (kind, count) in &s.gatt_errors {
+            let _ = writeln!(out, "timeflip_gatt_errors_total{{kind=\"{kind}\"}} {count}");
+        }
+
+        header(
+            &mut out,
+            "timeflip_script_failures_total",
+            "Scripts that failed to load or failed in a callback.",
+            "counter",
+        );
+        let _ = writeln!(out, "timeflip_script_failures_total {}", s.script_failures);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Read the state not announced by events periodically.
+pub(super) async fn sample(daemon: Arc<Daemon>) -> Result<(), Error> {
+    let mut tick = interval(SAMPLE_INTERVAL);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        let device = daemon.device().await;
+        let result = async {
+            daemon.metrics.status(&device.system_status().await?);
+            let drift = device.time().await? - Utc::now();
+            daemon
+                .metrics
+                .update(|s| s.clock_drift = Some(drift.num_seconds()));
+            let battery = device.battery_level().await?;
+            daemon.metrics.update(|s| s.battery = Some(battery.get()));
+            let facet = device.facet().await?;
+            daemon.metrics.update(|s| s.facet = Some(facet.index()));
+            Ok(())
+        }
+        .await;
+        drop(device);
+        if let Err(e) = result {
+            log::warn!("cannot read metrics: {e}");
+            daemon.record_error(&e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, daemon::fake::FakeControl, types::Percent};
+
+    fn facet(index: usize) -> Event {
+        Event::Facet(Facet::new(index).unwrap())
+    }
+
+    /// Check that `text` is a valid exposition: every sample follows the `HELP` and `TYPE` of
+    /// its metric and has a numeric value.
+    fn check_exposition(text: &str) {
+        let mut help = None;
+        let mut kind = None;
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# HELP ") {
+                let (name, _) = rest.split_once(' ').expect("HELP has a text");
+                help = Some(name);
+                kind = None;
+            } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind_) = rest.split_once(' ').expect("TYPE has a kind");
+                assert_eq!(Some(name), help, "TYPE follows HELP: {line}");
+                assert!(["gauge", "counter"].contains(&kind_), "{line}");
+                kind = Some(name);
+            } else {
+                let (series, value) = line.rsplit_once(' ').expect("sample has a value");
+                let name = series.split('{').next().unwrap();
+                assert_eq!(Some(name), kind, "sample follows its TYPE: {line}");
+                assert!(value.parse::<f64>().is_ok(), "{line}");
+                if let Some(labels) = series.strip_prefix(name) {
+                    if !labels.is_empty() {
+                        assert!(labels.starts_with('{') && labels.ends_with('}'), "{line}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exposition() {
+        let metrics = Metrics::default();
+        metrics.connected();
+        metrics.event(&Event::BatteryLevel(Percent::new(42).unwrap()));
+        metrics.event(&facet(2));
+        metrics.gatt_error("timeout");
+        metrics.reconnected();
+        metrics.status(&crate::daemon::fake::status(true, false));
+
+        let text = metrics.render(&FakeControl::new(Config::default()));
+        check_exposition(&text);
+        for sample in [
+            "timeflip_battery_percent 42",
+            "timeflip_facet 2",
+            "timeflip_paused 1",
+            "timeflip_locked 0",
+            "timeflip_connected 1",
+            "timeflip_reconnects_total 1",
+            "timeflip_flips_total{facet=\"2\",side=\"\"} 1",
+            "timeflip_gatt_errors_total{kind=\"timeout\"} 1",
+            "timeflip_script_failures_total 0",
+        ] {
+            assert!(
+                text.lines().any(|line| line == sample),
+                "{sample} in {text}"
+            );
+        }
+        assert!(!text.contains("timeflip_clock_drift_seconds"));
+    }
+
+    #[test]
+    fn escaped_side_names() {
+        assert_eq!(escape("a\\b \"c\"\nd"), r#"a\\b \"c\"\nd"#);
+
+        let mut config = Config::default();
+        config.sides[0].name = Some("Deep \"work\"\nmode".into());
+        let metrics = Metrics::default();
+        metrics.event(&facet(1));
+
+        let text = metrics.render(&FakeControl::new(config));
+        check_exposition(&text);
+        assert!(text.contains(r#"timeflip_flips_total{facet="1",side="Deep \"work\"\nmode"} 1"#));
+    }
+
+    #[test]
+    fn flips() {
+        let metrics = Metrics::default();
+        metrics.update(|s| s.facet = Some(1));
+        for index in [1, 1, 2, 2, 3, 2] {
+            metrics.event(&facet(index));
+        }
+        let flips = metrics.state.lock().unwrap().flips.clone();
+        assert_eq!(flips, BTreeMap::from([(2, 2), (3, 1)]));
+    }
+}
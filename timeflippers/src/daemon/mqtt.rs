@@ -0,0 +1,489 @@
+//! Bridge between the TimeFlip2 and an MQTT broker.
+//!
+//! The state is published below the configured base topic:
+//!
+//! | Topic                 | Payload                               |
+//! |-----------------------|---------------------------------------|
+//! | `<base>/availability` | `online` or `offline`                 |
+//! | `<base>/facet`        | index of the facet facing up          |
+//! | `<base>/side`         | name of the facet facing up           |
+//! | `<base>/paused`       | `ON` or `OFF`                         |
+//! | `<base>/locked`       | `ON` or `OFF`                         |
+//! | `<base>/battery`      | battery level in percent              |
+//! | `<base>/event`        | each event with its `seq` and `received` time as JSON |
+//! | `<base>/entry`        | each finished history entry as JSON   |
+//!
+//! `<base>/pause/set` and `<base>/lock/set` accept `ON` and `OFF`, `<base>/brightness/set`
+//! accepts a percentage.
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use rumqttc::{AsyncClient, Event as MqttEvent, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::{select, sync::mpsc, time::sleep};
+
+use super::{Command, Control, Daemon, Error, Update};
+use crate::{
+    config::MqttConfig,
+    timeflip::{Envelope, Event, SystemStatus},
+    types::{Facet, Percent},
+};
+
+/// Time to wait before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Packets from the broker relevant to the bridge.
+#[derive(Debug)]
+enum Incoming {
+    Connected,
+    Command { topic: String, payload: Bytes },
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+struct Bridge<C> {
+    control: Arc<C>,
+    config: MqttConfig,
+    client: AsyncClient,
+}
+
+impl<C: Control> Bridge<C> {
+    async fn publish(&self, name: &str, payload: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.client
+            .publish(self.config.topic(name), QoS::AtLeastOnce, true, payload)
+            .await?;
+        Ok(())
+    }
+
+    async fn publish_facet(&self, facet: Facet) -> Result<(), Error> {
+        self.publish("facet", facet.index().to_string()).await?;
+        self.publish("side", self.control.side_name(facet).unwrap_or_default())
+            .await
+    }
+
+    async fn publish_status(&self, status: SystemStatus) -> Result<(), Error> {
+        self.publish("paused", on_off(status.pause_mode)).await?;
+        self.publish("locked", on_off(status.lock_mode)).await
+    }
+
+    /// Publish `value` as JSON to the non-retained topic `name`.
+    async fn publish_json(&self, name: &str, value: &impl serde::Serialize) -> Result<(), Error> {
+        let json = serde_json::to_vec(value).expect("value is serializable");
+        self.client
+            .publish(self.config.topic(name), QoS::AtLeastOnce, false, json)
+            .await?;
+        Ok(())
+    }
+
+    /// Announce the TimeFlip2 to Home Assistant.
+    async fn publish_discovery(&self, prefix: &str) -> Result<(), Error> {
+        let id = &self.config.client_id;
+        let device = json!({
+            "identifiers": [id],
+            "name": "TimeFlip2",
+            "manufacturer": "TimeFlip",
+        });
+        let entities = [
+            (
+                "sensor",
+                "facet",
+                json!({ "name": "Facet", "state_topic": self.config.topic("facet") }),
+            ),
+            (
+                "sensor",
+                "side",
+                json!({ "name": "Side", "state_topic": self.config.topic("side") }),
+            ),
+            (
+                "sensor",
+                "battery",
+                json!({
+                    "name": "Battery",
+                    "state_topic": self.config.topic("battery"),
+                    "device_class": "battery",
+                    "state_class": "measurement",
+                    "unit_of_measurement": "%",
+                }),
+            ),
+            (
+                "switch",
+                "pause",
+                json!({
+                    "name": "Pause",
+                    "state_topic": self.config.topic("paused"),
+                    "command_topic": self.config.topic("pause/set"),
+                }),
+            ),
+            (
+                "switch",
+                "lock",
+                json!({
+                    "name": "Lock",
+                    "state_topic": self.config.topic("locked"),
+                    "command_topic": self.config.topic("lock/set"),
+                }),
+            ),
+            (
+                "number",
+                "brightness",
+                json!({
+                    "name": "Brightness",
+                    "command_topic": self.config.topic("brightness/set"),
+                    "min": 0,
+                    "max": 100,
+                    "unit_of_measurement": "%",
+                }),
+            ),
+        ];
+
+        for (component, object, mut payload) in entities {
+            payload["unique_id"] = json!(format!("{id}_{object}"));
+            payload["availability_topic"] = json!(self.config.topic("availability"));
+            payload["device"] = device.clone();
+            self.client
+                .publish(
+                    format!("{prefix}/{component}/{id}/{object}/config"),
+                    QoS::AtLeastOnce,
+                    true,
+                    payload.to_string(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn connected(&self) -> Result<(), Error> {
+        for command in ["pause/set", "lock/set", "brightness/set"] {
+            self.client
+                .subscribe(self.config.topic(command), QoS::AtLeastOnce)
+                .await?;
+        }
+        if let Some(prefix) = &self.config.discovery {
+            self.publish_discovery(prefix).await?;
+        }
+        self.publish("availability", "online").await?;
+
+        let state = self.control.state().await?;
+        self.publish("battery", state.battery.get().to_string())
+            .await?;
+        self.publish_facet(state.facet).await?;
+        self.publish("paused", on_off(state.paused)).await?;
+        self.publish("locked", on_off(state.locked)).await
+    }
+
+    async fn command(&self, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let payload = String::from_utf8_lossy(payload);
+        let payload = payload.trim();
+        let command = topic
+            .strip_prefix(&self.config.topic)
+            .and_then(|t| t.strip_prefix('/'))
+            .unwrap_or_default();
+
+        let command = match (command, payload) {
+            ("pause/set", "ON") => Command::Pause,
+            ("pause/set", "OFF") => Command::Unpause,
+            ("lock/set", "ON") => Command::Lock,
+            ("lock/set", "OFF") => Command::Unlock,
+            ("brightness/set", value) => {
+                match value.parse().ok().and_then(|v| Percent::new(v).ok()) {
+                    Some(value) => Command::Brightness(value),
+                    None => {
+                        log::warn!("invalid brightness {value:?} on {topic}");
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {
+                log::warn!("ignoring unknown MQTT command {payload:?} on {topic}");
+                return Ok(());
+            }
+        };
+        // The daemon relays the changed status.
+        self.control.execute(command).await?;
+        Ok(())
+    }
+
+    async fn event(&self, envelope: Envelope) -> Result<(), Error> {
+        self.publish_json("event", &envelope).await?;
+        match envelope.event {
+            Event::BatteryLevel(battery) => {
+                self.publish("battery", battery.get().to_string()).await
+            }
+            Event::Facet(facet) => self.publish_facet(facet).await,
+            Event::DoubleTap { pause, .. } => self.publish("paused", on_off(pause)).await,
+            Event::Disconnected => self.publish("availability", "offline").await,
+            Event::Event(_) | Event::SyncState(_) | Event::History(_) => Ok(()),
+        }
+    }
+
+    /// Relay `updates` to the broker and execute the commands `incoming` from it, until both
+    /// end.
+    async fn run(
+        &self,
+        mut updates: impl Stream<Item = Update> + Unpin,
+        mut incoming: impl Stream<Item = Incoming> + Unpin,
+    ) {
+        loop {
+            let res = select! {
+                Some(incoming) = incoming.next() => match incoming {
+                    Incoming::Connected => self.connected().await,
+                    Incoming::Command { topic, payload } => self.command(&topic, &payload).await,
+                },
+                Some(update) = updates.next() => match update {
+                    Update::Event(event) => self.event(event).await,
+                    Update::Entry(entry) => self.publish_json("entry", &entry).await,
+                    Update::Status(status) => self.publish_status(status).await,
+                },
+                else => break,
+            };
+            if let Err(e) = res {
+                log::warn!("MQTT bridge: {e}");
+                if let Error::TimeFlip(e) = &e {
+                    self.control.record_error(e);
+                }
+            }
+        }
+    }
+}
+
+/// Connect to the broker, returning a client and the packets received from it.
+fn connect(config: &MqttConfig) -> (AsyncClient, BoxStream<'static, Incoming>) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        config.topic("availability"),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+
+    // The event loop must be polled while publishing, so it runs separately.
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            let incoming = match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    log::info!("connected to MQTT broker");
+                    Incoming::Connected
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => Incoming::Command {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                },
+                Ok(_) => continue,
+                Err(e) => {
+                    log::warn!("MQTT connection failed: {e}");
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            if tx.send(incoming).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let incoming = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|incoming| (incoming, rx))
+    });
+    (client, incoming.boxed())
+}
+
+/// Publish the TimeFlip2's state to the broker and execute commands received from it.
+pub(super) async fn bridge(daemon: Arc<Daemon>, config: MqttConfig) -> Result<(), Error> {
+    let (client, incoming) = connect(&config);
+    let updates = daemon.updates("MQTT bridge");
+    let bridge = Bridge {
+        control: daemon,
+        config,
+        client,
+    };
+    bridge.run(updates, incoming).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::fake::{envelope, status, FakeControl},
+    };
+    use rumqttc::Request;
+    use tokio::time::timeout;
+
+    fn config(host: &str, port: u16) -> MqttConfig {
+        MqttConfig {
+            host: host.into(),
+            port,
+            client_id: "timeflippers-test".into(),
+            username: None,
+            password: None,
+            topic: "timeflippers-test".into(),
+            discovery: Some("homeassistant".into()),
+        }
+    }
+
+    fn command(topic: &str, payload: &'static str) -> Incoming {
+        Incoming::Command {
+            topic: format!("timeflippers-test/{topic}"),
+            payload: Bytes::from_static(payload.as_bytes()),
+        }
+    }
+
+    #[tokio::test]
+    async fn relay_and_commands() {
+        let (tx, rx) = flume::unbounded();
+        let control = Arc::new(FakeControl::new(Config::default()));
+        let bridge = Bridge {
+            control: control.clone(),
+            config: config("localhost", 1883),
+            client: AsyncClient::from_senders(tx),
+        };
+
+        // Events and commands are independent, the bridge handles them in any order.
+        let updates = stream::iter([
+            Update::Event(envelope(1, Event::Facet(Facet::new(2).unwrap()))),
+            Update::Event(envelope(2, Event::BatteryLevel(Percent::new(40).unwrap()))),
+            Update::Status(status(true, false)),
+        ]);
+        let incoming = stream::iter([
+            Incoming::Connected,
+            command("pause/set", "ON"),
+            command("brightness/set", "150"),
+            command("lock/set", "maybe"),
+        ]);
+        bridge.run(updates, incoming).await;
+
+        let mut subscribed = vec![];
+        let mut published = vec![];
+        for request in rx.drain() {
+            match request {
+                Request::Subscribe(subscribe) => {
+                    subscribed.extend(subscribe.filters.into_iter().map(|f| f.path))
+                }
+                Request::Publish(publish) => published.push((
+                    publish.topic,
+                    String::from_utf8_lossy(&publish.payload).into_owned(),
+                )),
+                _ => {}
+            }
+        }
+        let published = |topic: &str, payload: &str| {
+            published.contains(&(topic.to_string(), payload.to_string()))
+        };
+
+        assert_eq!(subscribed.len(), 3);
+        assert!(published("timeflippers-test/availability", "online"));
+        assert!(published("timeflippers-test/facet", "1"));
+        assert!(published("timeflippers-test/facet", "2"));
+        assert!(published(
+            "timeflippers-test/event",
+            &serde_json::to_string(&envelope(1, Event::Facet(Facet::new(2).unwrap()))).unwrap()
+        ));
+        assert!(published("timeflippers-test/battery", "80"));
+        assert!(published("timeflippers-test/battery", "40"));
+        assert!(published("timeflippers-test/paused", "ON"));
+        assert!(published(
+            "homeassistant/switch/timeflippers-test/pause/config",
+            &json!({
+                "name": "Pause",
+                "state_topic": "timeflippers-test/paused",
+                "command_topic": "timeflippers-test/pause/set",
+                "unique_id": "timeflippers-test_pause",
+                "availability_topic": "timeflippers-test/availability",
+                "device": {
+                    "identifiers": ["timeflippers-test"],
+                    "name": "TimeFlip2",
+                    "manufacturer": "TimeFlip",
+                },
+            })
+            .to_string()
+        ));
+        assert_eq!(control.commands(), vec![Command::Pause]);
+    }
+
+    /// Run against a real broker, e.g. `mosquitto -p 1883`, given by `MQTT_BROKER=host:port`.
+    #[tokio::test]
+    #[ignore = "needs an MQTT broker"]
+    async fn broker() {
+        let broker = std::env::var("MQTT_BROKER").unwrap_or("localhost:1883".into());
+        let (host, port) = broker.split_once(':').expect("MQTT_BROKER is host:port");
+        let config = config(host, port.parse().unwrap());
+
+        let control = Arc::new(FakeControl::new(Config::default()));
+        let (client, incoming) = connect(&config);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let updates = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|update| (update, rx))
+        });
+        let bridge = Bridge {
+            control: control.clone(),
+            config,
+            client,
+        };
+        tokio::spawn(async move { bridge.run(updates.boxed(), incoming).await });
+
+        let mut options =
+            MqttOptions::new("timeflippers-test-observer", host, port.parse().unwrap());
+        options.set_keep_alive(Duration::from_secs(5));
+        let (observer, mut eventloop) = AsyncClient::new(options, 16);
+        observer
+            .subscribe("timeflippers-test/#", QoS::AtLeastOnce)
+            .await
+            .unwrap();
+
+        // Wait for a published value, polling the observer's event loop meanwhile.
+        let mut expect = async |topic: &str, payload: &str| {
+            timeout(Duration::from_secs(10), async {
+                loop {
+                    if let MqttEvent::Incoming(Packet::Publish(publish)) =
+                        eventloop.poll().await.unwrap()
+                    {
+                        if publish.topic == format!("timeflippers-test/{topic}")
+                            && publish.payload == payload.as_bytes()
+                        {
+                            return;
+                        }
+                    }
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("{topic} was not set to {payload}"));
+        };
+
+        expect("availability", "online").await;
+        tx.send(Update::Event(envelope(
+            1,
+            Event::Facet(Facet::new(5).unwrap()),
+        )))
+        .unwrap();
+        expect("facet", "5").await;
+
+        observer
+            .publish("timeflippers-test/pause/set", QoS::AtLeastOnce, false, "ON")
+            .await
+            .unwrap();
+        tx.send(Update::Status(status(true, false))).unwrap();
+        expect("paused", "ON").await;
+        timeout(Duration::from_secs(10), async {
+            while control.commands().is_empty() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("pause command was not received");
+        assert_eq!(control.commands(), vec![Command::Pause]);
+    }
+}
@@ -0,0 +1,333 @@
+//! Desktop notifications sent through `org.freedesktop.Notifications`.
+
+use chrono::Utc;
+use dbus::{
+    arg::{PropMap, Variant},
+    channel::Channel,
+    nonblock::{Proxy, SyncConnection},
+};
+use futures::{Stream, StreamExt};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    select,
+    time::{interval, MissedTickBehavior},
+};
+
+use super::{receive, Control, Daemon, Error};
+use crate::{
+    config::{NotificationEvent, NotificationsConfig},
+    pomodoro::PomodoroTracker,
+    timeflip::Event,
+    types::Facet,
+};
+
+const APP_NAME: &str = "timeflippers";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Urgency levels of the notification specification.
+#[derive(Clone, Copy)]
+enum Urgency {
+    Normal = 1,
+    Critical = 2,
+}
+
+struct Notifier<C> {
+    control: Arc<C>,
+    config: NotificationsConfig,
+    connection: Arc<SyncConnection>,
+    /// ID of the last facet or pause notification, replaced by the next one.
+    replaces: u32,
+    pomodoros: PomodoroTracker,
+    low_battery: bool,
+}
+
+impl<C: Control> Notifier<C> {
+    fn new(control: Arc<C>, config: NotificationsConfig, connection: Arc<SyncConnection>) -> Self {
+        Notifier {
+            pomodoros: PomodoroTracker::new(control.config()),
+            control,
+            config,
+            connection,
+            replaces: 0,
+            low_battery: false,
+        }
+    }
+
+    async fn send(&self, summary: &str, body: &str, urgency: Urgency) -> Result<u32, dbus::Error> {
+        self.send_replacing(0, summary, body, urgency).await
+    }
+
+    async fn send_replacing(
+        &self,
+        replaces: u32,
+        summary: &str,
+        body: &str,
+        urgency: Urgency,
+    ) -> Result<u32, dbus::Error> {
+        let proxy = Proxy::new(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            DBUS_TIMEOUT,
+            self.connection.clone(),
+        );
+        let mut hints = PropMap::new();
+        hints.insert("urgency".into(), Variant(Box::new(urgency as u8)));
+        let (id,): (u32,) = proxy
+            .method_call(
+                "org.freedesktop.Notifications",
+                "Notify",
+                (
+                    APP_NAME,
+                    replaces,
+                    "",
+                    summary,
+                    body,
+                    Vec::<String>::new(),
+                    hints,
+                    -1i32,
+                ),
+            )
+            .await?;
+        Ok(id)
+    }
+
+    fn enabled(&self, event: NotificationEvent) -> bool {
+        self.config.events.contains(&event)
+    }
+
+    fn side_name(&self, facet: &Facet) -> String {
+        self.control
+            .side_name(facet.clone())
+            .map(String::from)
+            .unwrap_or_else(|| format!("Side {}", facet.index()))
+    }
+
+    /// Start a pomodoro on `facet`, continuing the time already counted by the TimeFlip2.
+    async fn start_pomodoro(&mut self, facet: Facet) {
+        let elapsed = if self.pomodoros.length(&facet).is_some() {
+            match self.control.elapsed(facet.clone()).await {
+                Ok(elapsed) => elapsed,
+                Err(e) => {
+                    log::warn!("cannot read the time counted on {facet}: {e}");
+                    self.control.record_error(&e);
+                    Duration::ZERO
+                }
+            }
+        } else {
+            Duration::ZERO
+        };
+        self.pomodoros.start(facet, elapsed, Utc::now());
+    }
+
+    async fn event(&mut self, event: Event) -> Result<(), Error> {
+        match event {
+            Event::Facet(facet) => {
+                if self.enabled(NotificationEvent::Facet) {
+                    let summary = self.side_name(&facet);
+                    self.replaces = self
+                        .send_replacing(self.replaces, &summary, "is now up", Urgency::Normal)
+                        .await?;
+                }
+                self.start_pomodoro(facet).await;
+            }
+            Event::DoubleTap { facet, pause } => {
+                if self.enabled(NotificationEvent::Pause) {
+                    let summary = self.side_name(&facet);
+                    let body = if pause { "paused" } else { "resumed" };
+                    self.replaces = self
+                        .send_replacing(self.replaces, &summary, body, Urgency::Normal)
+                        .await?;
+                }
+                if pause {
+                    self.pomodoros.stop(Utc::now());
+                } else {
+                    self.start_pomodoro(facet).await;
+                }
+            }
+            Event::BatteryLevel(battery) => {
+                let low = battery.get() < self.control.config().daemon.low_battery();
+                if low && !self.low_battery && self.enabled(NotificationEvent::LowBattery) {
+                    let body = format!("Battery level is at {battery}");
+                    self.send("TimeFlip battery low", &body, Urgency::Critical)
+                        .await?;
+                }
+                self.low_battery = low;
+            }
+            Event::Disconnected => {
+                if self.enabled(NotificationEvent::Disconnect) {
+                    self.send("TimeFlip disconnected", "", Urgency::Critical)
+                        .await?;
+                }
+            }
+            Event::Event(_) | Event::SyncState(_) | Event::History(_) => {}
+        }
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<(), Error> {
+        if let Some(record) = self.pomodoros.tick(Utc::now()) {
+            if self.enabled(NotificationEvent::Pomodoro) {
+                let body = format!(
+                    "{} minutes on {}",
+                    record.length.as_secs() / 60,
+                    self.side_name(&record.facet)
+                );
+                self.send("Pomodoro completed", &body, Urgency::Normal)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Show notifications for `events` until they end.
+    async fn run(&mut self, mut events: impl Stream<Item = Event> + Unpin) {
+        let mut tick = interval(Duration::from_secs(1));
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let result = select! {
+                _ = tick.tick() => self.tick().await,
+                event = events.next() => match event {
+                    Some(event) => self.event(event).await,
+                    None => break,
+                },
+            };
+            if let Err(e) = result {
+                log::warn!("cannot send notification: {e}");
+            }
+        }
+    }
+}
+
+/// Connect to the D-Bus at `address`, or the session bus.
+fn connect(address: Option<&str>) -> Result<Arc<SyncConnection>, Error> {
+    let (resource, connection) = match address {
+        Some(address) => {
+            let mut channel = Channel::open_private(address)?;
+            channel.register()?;
+            dbus_tokio::connection::from_channel::<SyncConnection>(channel)?
+        }
+        None => dbus_tokio::connection::new_session_sync()?,
+    };
+    tokio::spawn(async move {
+        let e = resource.await;
+        log::error!("lost connection to D-Bus: {e}");
+    });
+    Ok(connection)
+}
+
+/// Show desktop notifications for the TimeFlip2's events.
+pub(super) async fn run(daemon: Arc<Daemon>, config: NotificationsConfig) -> Result<(), Error> {
+    let connection = connect(config.address.as_deref())?;
+    let events = receive(daemon.subscribe(), "notifications", "events").map(|e| e.event);
+    let mut notifier = Notifier::new(daemon, config, connection);
+    let state = notifier.control.state().await?;
+    if !state.paused {
+        notifier.start_pomodoro(state.facet).await;
+    }
+    notifier.run(events).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::fake::{Bus, FakeControl},
+        types::Percent,
+    };
+    use dbus::{
+        channel::{MatchingReceiver, Sender},
+        message::MatchRule,
+    };
+    use futures::stream;
+    use std::sync::Mutex;
+
+    /// A notification received by the stand-in: replaced id, summary, body and urgency.
+    type Received = (u32, String, String, u8);
+
+    /// Stand in for the notification daemon, recording the notifications.
+    async fn notification_daemon(address: &str) -> Arc<Mutex<Vec<Received>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let connection = connect(Some(address)).unwrap();
+        let recorded = received.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                let mut args = message.iter_init();
+                let _app: String = args.read().unwrap();
+                let replaces: u32 = args.read().unwrap();
+                let _icon: String = args.read().unwrap();
+                let summary: String = args.read().unwrap();
+                let body: String = args.read().unwrap();
+                let _actions: Vec<String> = args.read().unwrap();
+                let hints: PropMap = args.read().unwrap();
+                let urgency = hints["urgency"].0.as_u64().unwrap() as u8;
+
+                let mut received = recorded.lock().unwrap();
+                received.push((replaces, summary, body, urgency));
+                let id = received.len() as u32;
+                let _ = connection.send(message.method_return().append1(id));
+                true
+            }),
+        );
+        connection
+            .request_name("org.freedesktop.Notifications", false, true, false)
+            .await
+            .unwrap();
+        received
+    }
+
+    #[tokio::test]
+    async fn session_bus() {
+        let Some(bus) = Bus::start("notifications") else {
+            return;
+        };
+        let received = notification_daemon(&bus.address).await;
+
+        let mut sides = Config::default();
+        sides.sides[1].name = Some("Writing".into());
+        let control = Arc::new(FakeControl::new(sides));
+        let config = NotificationsConfig {
+            address: Some(bus.address.clone()),
+            events: vec![
+                NotificationEvent::Facet,
+                NotificationEvent::Pause,
+                NotificationEvent::LowBattery,
+            ],
+        };
+        let connection = connect(config.address.as_deref()).unwrap();
+        let mut notifier = Notifier::new(control, config, connection);
+
+        let facet = |i| Facet::new(i).unwrap();
+        let battery = |v| Event::BatteryLevel(Percent::new(v).unwrap());
+        notifier
+            .run(stream::iter([
+                Event::Facet(facet(2)),
+                Event::DoubleTap {
+                    facet: facet(2),
+                    pause: true,
+                },
+                Event::Facet(facet(3)),
+                battery(10),
+                battery(5),
+                Event::Disconnected,
+            ]))
+            .await;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                (0, "Writing".into(), "is now up".into(), 1),
+                (1, "Writing".into(), "paused".into(), 1),
+                (2, "Side 3".into(), "is now up".into(), 1),
+                (
+                    0,
+                    "TimeFlip battery low".into(),
+                    "Battery level is at 10%".into(),
+                    2
+                ),
+            ]
+        );
+    }
+}
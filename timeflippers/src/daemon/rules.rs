@@ -0,0 +1,372 @@
+//! Rules evaluated against the TimeFlip2's events and the clock.
+
+use chrono::{Local, NaiveTime};
+use futures::StreamExt;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    select,
+    time::{interval, Instant, MissedTickBehavior},
+};
+
+use super::{Command, Control, Daemon, Error, Update};
+use crate::{
+    config::{Rule, RuleAction},
+    timeflip::{self, Event},
+    types::Facet,
+};
+
+/// Interval in which time based conditions are checked.
+const TICK: Duration = Duration::from_secs(30);
+
+/// State of the TimeFlip2 the rules' conditions are evaluated against.
+struct State {
+    facet: Facet,
+    /// Time the facet was active before the current pause.
+    active: Duration,
+    /// Time the facet was last flipped up or unpaused, unless paused.
+    active_since: Option<Instant>,
+    paused: bool,
+    battery: Option<u8>,
+}
+
+impl State {
+    fn active(&self) -> Duration {
+        self.active
+            + self
+                .active_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    fn update(&mut self, event: &Event) {
+        match event {
+            Event::Facet(facet) if *facet != self.facet => {
+                self.facet = facet.clone();
+                self.active = Duration::ZERO;
+                self.active_since = (!self.paused).then(Instant::now);
+            }
+            Event::DoubleTap { pause, .. } => self.set_paused(*pause),
+            Event::BatteryLevel(battery) => self.battery = Some(battery.get()),
+            _ => {}
+        }
+    }
+
+    fn set_paused(&mut self, pause: bool) {
+        if pause == self.paused {
+            return;
+        }
+        self.paused = pause;
+        if pause {
+            self.active = self.active();
+            self.active_since = None;
+        } else {
+            self.active_since = Some(Instant::now());
+        }
+    }
+
+    fn holds(&self, rule: &Rule, now: NaiveTime) -> bool {
+        rule.facet.as_ref().is_none_or(|facet| *facet == self.facet)
+            && rule
+                .active_for
+                .as_ref()
+                .is_none_or(|minutes| self.active() > minutes.duration())
+            && rule.inside.is_none_or(|window| window.contains(now))
+            && rule.outside.is_none_or(|window| !window.contains(now))
+            && rule
+                .battery_below
+                .as_ref()
+                .is_none_or(|limit| self.battery.is_some_and(|level| level < limit.get()))
+            && rule.paused.is_none_or(|paused| paused == self.paused)
+    }
+}
+
+/// The rule's name, or its position in the configuration.
+fn name(rule: &Rule, index: usize) -> String {
+    rule.name
+        .clone()
+        .unwrap_or_else(|| format!("#{}", index + 1))
+}
+
+struct Engine<C> {
+    control: Arc<C>,
+    state: State,
+    /// Whether each rule's conditions held on the last evaluation.
+    held: Vec<bool>,
+}
+
+impl<C: Control> Engine<C> {
+    /// Start with the TimeFlip2's current state, counting the time the active task ran so far.
+    async fn new(control: Arc<C>) -> Result<Self, timeflip::Error> {
+        let state = control.state().await?;
+        let active = match control.elapsed(state.facet.clone()).await {
+            Ok(elapsed) => elapsed,
+            Err(e) => {
+                log::warn!("cannot read the time since the activity started: {e}");
+                control.record_error(&e);
+                Duration::ZERO
+            }
+        };
+        Ok(Engine {
+            held: vec![false; control.config().rules.len()],
+            state: State {
+                facet: state.facet,
+                active,
+                active_since: (!state.paused).then(Instant::now),
+                paused: state.paused,
+                battery: Some(state.battery.get()),
+            },
+            control,
+        })
+    }
+
+    fn update(&mut self, update: &Update) {
+        match update {
+            Update::Event(envelope) => self.state.update(&envelope.event),
+            Update::Status(status) => self.state.set_paused(status.pause_mode),
+            Update::Entry(_) => {}
+        }
+    }
+
+    async fn take(&mut self, index: usize) -> Result<(), timeflip::Error> {
+        let rule = &self.control.config().rules[index];
+        let name = name(rule, index);
+        if self.control.config().daemon.dry_run {
+            log::info!("rule {name} would {} (dry run)", rule.action);
+            return Ok(());
+        }
+
+        log::info!("rule {name}: {}", rule.action);
+        let command = match &rule.action {
+            RuleAction::Pause => Command::Pause,
+            RuleAction::Unpause => Command::Unpause,
+            RuleAction::Lock => Command::Lock,
+            RuleAction::Unlock => Command::Unlock,
+            RuleAction::Brightness(value) => Command::Brightness(value.clone()),
+            RuleAction::Color(color) => {
+                let facet = rule.facet.clone().unwrap_or(self.state.facet.clone());
+                Command::Color(facet, color.clone())
+            }
+        };
+        self.control.execute(command).await?;
+        match &rule.action {
+            RuleAction::Pause => self.state.set_paused(true),
+            RuleAction::Unpause => self.state.set_paused(false),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Take the actions of the rules whose conditions started to hold at the local time `now`.
+    async fn evaluate(&mut self, now: NaiveTime) {
+        for index in 0..self.held.len() {
+            let control = self.control.clone();
+            let rule = &control.config().rules[index];
+            let holds = self.state.holds(rule, now);
+            if holds && !self.held[index] {
+                if let Err(e) = self.take(index).await {
+                    log::warn!("cannot apply rule {}: {e}", name(rule, index));
+                    control.record_error(&e);
+                }
+            }
+            self.held[index] = holds;
+        }
+    }
+}
+
+/// Evaluate the configured rules until the TimeFlip2 disconnects.
+pub(super) async fn run(daemon: Arc<Daemon>) -> Result<(), Error> {
+    let mut updates = daemon.updates("rules");
+    let mut engine = Engine::new(daemon).await?;
+
+    let mut tick = interval(TICK);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        select! {
+            _ = tick.tick() => {}
+            update = updates.next() => match update {
+                Some(update) => engine.update(&update),
+                None => break,
+            },
+        }
+        engine.evaluate(Local::now().time()).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::fake::{envelope, FakeControl},
+        types::{Minutes, Percent},
+    };
+    use tokio::time::advance;
+
+    fn rule(action: RuleAction) -> Rule {
+        Rule {
+            name: None,
+            facet: None,
+            active_for: None,
+            inside: None,
+            outside: None,
+            battery_below: None,
+            paused: None,
+            action,
+        }
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    async fn engine(rules: Vec<Rule>, f: impl FnOnce(&mut Config)) -> Engine<FakeControl> {
+        let mut config = Config {
+            rules,
+            ..Config::default()
+        };
+        f(&mut config);
+        Engine::new(Arc::new(FakeControl::new(config)))
+            .await
+            .unwrap()
+    }
+
+    fn flip(engine: &mut Engine<FakeControl>, index: usize) {
+        let event = Event::Facet(Facet::new(index).unwrap());
+        engine.update(&Update::Event(envelope(0, event)));
+    }
+
+    #[tokio::test]
+    async fn held_and_retrigger() {
+        let mut engine = engine(
+            vec![Rule {
+                facet: Some(Facet::new(2).unwrap()),
+                ..rule(RuleAction::Lock)
+            }],
+            |_| {},
+        )
+        .await;
+        let noon = time("12:00");
+
+        engine.evaluate(noon).await;
+        assert!(engine.control.commands().is_empty());
+
+        flip(&mut engine, 2);
+        engine.evaluate(noon).await;
+        engine.evaluate(noon).await;
+        assert_eq!(engine.control.commands(), [Command::Lock]);
+
+        flip(&mut engine, 1);
+        engine.evaluate(noon).await;
+        flip(&mut engine, 2);
+        engine.evaluate(noon).await;
+        assert_eq!(engine.control.commands(), [Command::Lock, Command::Lock]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn active_for() {
+        let config = Config {
+            rules: vec![Rule {
+                active_for: Some(Minutes(30)),
+                ..rule(RuleAction::Pause)
+            }],
+            ..Config::default()
+        };
+        let control = Arc::new(FakeControl::new(config));
+        control.set_elapsed(Duration::from_secs(25 * 60));
+        let mut engine = Engine::new(control.clone()).await.unwrap();
+        let noon = time("12:00");
+
+        engine.evaluate(noon).await;
+        assert!(control.commands().is_empty());
+
+        advance(Duration::from_secs(6 * 60)).await;
+        engine.evaluate(noon).await;
+        assert_eq!(control.commands(), [Command::Pause]);
+        assert!(engine.state.paused);
+
+        // Paused time does not count, flipping starts over.
+        flip(&mut engine, 2);
+        engine.update(&Update::Event(envelope(
+            0,
+            Event::DoubleTap {
+                facet: Facet::new(2).unwrap(),
+                pause: false,
+            },
+        )));
+        advance(Duration::from_secs(29 * 60)).await;
+        engine.evaluate(noon).await;
+        assert_eq!(control.commands(), [Command::Pause]);
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let mut engine = engine(vec![rule(RuleAction::Pause)], |config| {
+            config.daemon.dry_run = true
+        })
+        .await;
+
+        engine.evaluate(time("12:00")).await;
+        assert!(engine.control.commands().is_empty());
+        assert!(!engine.state.paused);
+    }
+
+    #[tokio::test]
+    async fn time_windows() {
+        let mut engine = engine(
+            vec![
+                Rule {
+                    inside: Some("08:00-18:00".parse().unwrap()),
+                    ..rule(RuleAction::Lock)
+                },
+                Rule {
+                    outside: Some("06:00-22:00".parse().unwrap()),
+                    ..rule(RuleAction::Unlock)
+                },
+            ],
+            |_| {},
+        )
+        .await;
+
+        engine.evaluate(time("07:00")).await;
+        assert!(engine.control.commands().is_empty());
+        engine.evaluate(time("08:00")).await;
+        engine.evaluate(time("17:59")).await;
+        assert_eq!(engine.control.commands(), [Command::Lock]);
+        engine.evaluate(time("18:00")).await;
+        engine.evaluate(time("23:00")).await;
+        assert_eq!(engine.control.commands(), [Command::Lock, Command::Unlock]);
+    }
+
+    #[tokio::test]
+    async fn battery_below() {
+        let mut engine = engine(
+            vec![Rule {
+                battery_below: Some(Percent::new(20).unwrap()),
+                ..rule(RuleAction::Brightness(Percent::new(10).unwrap()))
+            }],
+            |_| {},
+        )
+        .await;
+        let battery = |level| {
+            Update::Event(envelope(
+                0,
+                Event::BatteryLevel(Percent::new(level).unwrap()),
+            ))
+        };
+        let noon = time("12:00");
+
+        engine.evaluate(noon).await;
+        engine.update(&battery(20));
+        engine.evaluate(noon).await;
+        assert!(engine.control.commands().is_empty());
+
+        engine.update(&battery(19));
+        engine.evaluate(noon).await;
+        engine.update(&battery(15));
+        engine.evaluate(noon).await;
+        assert_eq!(
+            engine.control.commands(),
+            [Command::Brightness(Percent::new(10).unwrap())]
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Rhai scripts run on the TimeFlip2's events.
+//!
+//! Each `*.rhai` file in the scripts directory may define the functions `on_event(event)`,
+//! called with each [Event](crate::timeflip::Event) as map with `type` and `data` next to the
+//! [Envelope](crate::timeflip::Envelope)'s `seq`, `received` and `clock_offset`, and
+//! `on_entry(entry)`, called with each finished history [Entry](crate::timeflip::Entry). Scripts can use the following functions:
+//!
+//! - `facet()`, `battery()` and `side_name(facet)` to query the TimeFlip2
+//! - `pause()`, `unpause()`, `lock()`, `unlock()`, `brightness(percent)` and
+//!   `color(facet, red, green, blue)` to control it
+//! - `history()` to read the entries of the daemon's history file
+//!
+//! Scripts are reloaded when changed. A failing or panicking script only logs an error, it does
+//! not affect other scripts or the daemon. Scripts cannot import modules and are aborted when
+//! they exceed the limits below.
+
+use futures::{future::BoxFuture, FutureExt};
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, Dynamic, Engine, EvalAltResult, Scope, AST,
+};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    fs,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    runtime::Handle,
+    select,
+    sync::broadcast::error::RecvError,
+    task::spawn_blocking,
+    time::{interval, MissedTickBehavior},
+};
+
+use super::{Command, Control, Daemon, Error};
+use crate::{
+    history,
+    timeflip::{self, TimeFlip},
+    types::{Color, Facet, Percent},
+};
+
+/// Interval in which the scripts directory is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+/// Number of operations after which a script is aborted, e.g. in an endless loop.
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Maximum depth of nested function calls.
+const MAX_CALL_LEVELS: usize = 32;
+/// Maximum depth of nested expressions, globally and in functions.
+const MAX_EXPR_DEPTH: usize = 64;
+/// Maximum length of strings in bytes.
+const MAX_STRING_SIZE: usize = 64 * 1024;
+/// Maximum number of elements in arrays and object maps.
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The TimeFlip2 API available to scripts.
+#[derive(Clone)]
+struct Api {
+    daemon: Arc<Daemon>,
+    handle: Handle,
+}
+
+impl Api {
+    fn call<T>(
+        &self,
+        f: impl for<'a> FnOnce(&'a TimeFlip) -> BoxFuture<'a, Result<T, timeflip::Error>>,
+    ) -> ScriptResult<T> {
+        self.handle
+            .block_on(async {
+                let device = self.daemon.device().await;
+                f(&device).await
+            })
+            .map_err(|e| {
+                self.daemon.record_error(&e);
+                e.to_string().into()
+            })
+    }
+
+    fn execute(&self, script: &str, command: Command) -> ScriptResult<()> {
+        if self.daemon.config().daemon.dry_run {
+            log::info!("script {script} would {command} (dry run)");
+            return Ok(());
+        }
+        log::info!("script {script}: {command}");
+        self.handle
+            .block_on(self.daemon.execute(command))
+            .map_err(|e| {
+                self.daemon.record_error(&e);
+                e.to_string().into()
+            })
+    }
+
+    fn history(&self) -> ScriptResult<Array> {
+        let Some(file) = &self.daemon.config().daemon.history else {
+            return Ok(Array::new());
+        };
+        let entries = self
+            .handle
+            .block_on(history::load(file))
+            .map_err(|e| e.to_string())?;
+        entries.iter().map(rhai::serde::to_dynamic).collect()
+    }
+}
+
+fn facet(index: i64) -> ScriptResult<Facet> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| Facet::new(i).ok())
+        .ok_or_else(|| format!("invalid facet {index}").into())
+}
+
+fn percent(value: i64) -> ScriptResult<Percent> {
+    usize::try_from(value)
+        .ok()
+        .and_then(|v| Percent::new(v).ok())
+        .ok_or_else(|| format!("invalid percentage {value}").into())
+}
+
+fn color(red: i64, green: i64, blue: i64) -> ScriptResult<Color> {
+    let channel = |v: i64| u16::try_from(v).map_err(|_| format!("invalid color channel {v}"));
+    Ok(Color::from_rgb(
+        channel(red)?,
+        channel(green)?,
+        channel(blue)?,
+    ))
+}
+
+/// Disable module imports and limit the resources a script may use.
+fn sandbox(engine: &mut Engine) {
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE);
+}
+
+/// The message of a caught panic.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Construct a sandboxed engine for the script `name`.
+fn engine(api: &Api, name: &str) -> Engine {
+    let mut engine = Engine::new();
+    sandbox(&mut engine);
+
+    let script = name.to_string();
+    engine.on_print(move |s| log::info!("script {script}: {s}"));
+    let script = name.to_string();
+    engine.on_debug(move |s, _, _| log::debug!("script {script}: {s}"));
+
+    let a = api.clone();
+    engine.register_fn("facet", move || {
+        a.call(|t| t.facet().boxed()).map(|f| i64::from(f.index()))
+    });
+    let a = api.clone();
+    engine.register_fn("battery", move || {
+        a.call(|t| t.battery_level().boxed())
+            .map(|b| i64::from(b.get()))
+    });
+    let a = api.clone();
+    engine.register_fn("side_name", move |index: i64| -> ScriptResult<Dynamic> {
+        Ok(a.daemon
+            .side_name(facet(index)?)
+            .map_or(Dynamic::UNIT, |name| Dynamic::from(name.to_string())))
+    });
+    let a = api.clone();
+    engine.register_fn("history", move || a.history());
+
+    for (function, command) in [
+        ("pause", Command::Pause),
+        ("unpause", Command::Unpause),
+        ("lock", Command::Lock),
+        ("unlock", Command::Unlock),
+    ] {
+        let (a, script) = (api.clone(), name.to_string());
+        engine.register_fn(function, move || a.execute(&script, command.clone()));
+    }
+    let (a, script) = (api.clone(), name.to_string());
+    engine.register_fn("brightness", move |value: i64| {
+        a.execute(&script, Command::Brightness(percent(value)?))
+    });
+    let (a, script) = (api.clone(), name.to_string());
+    engine.register_fn(
+        "color",
+        move |index: i64, red: i64, green: i64, blue: i64| {
+            let command = Command::Color(facet(index)?, color(red, green, blue)?);
+            a.execute(&script, command)
+        },
+    );
+
+    engine
+}
+
+/// A loaded script.
+struct Script {
+    name: String,
+    modified: SystemTime,
+    engine: Engine,
+    /// The compiled script, unless it failed to compile.
+    ast: Option<AST>,
+}
+
+impl Script {
+    fn load(api: &Api, path: &Path, modified: SystemTime) -> Script {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let engine = engine(api, &name);
+
+        let ast = match engine.compile_file(path.into()) {
+            Ok(ast) => match catch_unwind(AssertUnwindSafe(|| engine.run_ast(&ast))) {
+                Ok(Ok(())) => {
+                    log::info!("loaded script {name}");
+                    Some(ast)
+                }
+                Ok(Err(e)) => {
+                    log::error!("script {name} failed: {e}");
+                    None
+                }
+                Err(panic) => {
+                    log::error!("script {name} panicked: {}", panic_message(&*panic));
+                    None
+                }
+            },
+            Err(e) => {
+                log::error!("cannot load script {name}: {e}");
+                None
+            }
+        };
+
+        Script {
+            name,
+            modified,
+            engine,
+            ast,
+        }
+    }
+
+    /// Call the function `name` with `arg`, if the script defines it.
+    ///
+    /// Returns `false` if the function failed or panicked.
+    fn call(&self, name: &str, arg: &Dynamic) -> bool {
+        let Some(ast) = &self.ast else {
+            return true;
+        };
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == 1)
+        {
+            return true;
+        }
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), ast, name, (arg.clone(),))
+        }));
+        match result {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                log::error!("script {} failed in {name}: {e}", self.name);
+                false
+            }
+            Err(panic) => {
+                log::error!(
+                    "script {} panicked in {name}: {}",
+                    self.name,
+                    panic_message(&*panic)
+                );
+                false
+            }
+        }
+    }
+}
+
+/// The scripts in a directory by path.
+type Scripts = BTreeMap<PathBuf, Script>;
+
+/// Load new and changed scripts in `dir`, drop removed ones.
+///
+/// Returns the number of scripts that failed to load.
+fn reload(api: &Api, dir: &Path, scripts: &mut Scripts) -> usize {
+    let files = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect::<BTreeMap<_, _>>(),
+        Err(e) => {
+            log::error!("cannot read scripts directory {}: {e}", dir.display());
+            return 0;
+        }
+    };
+
+    scripts.retain(|path, script| {
+        let keep = files.contains_key(path);
+        if !keep {
+            log::info!("unloaded script {}", script.name);
+        }
+        keep
+    });
+    let mut failed = 0;
+    for (path, modified) in files {
+        if scripts.get(&path).is_none_or(|s| s.modified != modified) {
+            let script = Script::load(api, &path, modified);
+            failed += usize::from(script.ast.is_none());
+            scripts.insert(path, script);
+        }
+    }
+    failed
+}
+
+/// Run the scripts in `dir` on the TimeFlip2's events until it disconnects.
+pub(super) async fn run(daemon: Arc<Daemon>, dir: PathBuf) -> Result<(), Error> {
+    let mut events = daemon.subscribe();
+    let mut entries = daemon.subscribe_entries();
+    let api = Api {
+        daemon,
+        handle: Handle::current(),
+    };
+
+    let mut scripts = Scripts::new();
+    let mut check = interval(RELOAD_INTERVAL);
+    check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        // Scripts call back into the runtime, so they must run on a blocking thread.
+        let call: Box<dyn FnOnce(&mut Scripts) -> usize + Send> = select! {
+            _ = check.tick() => {
+                let (api, dir) = (api.clone(), dir.clone());
+                Box::new(move |scripts| reload(&api, &dir, scripts))
+            }
+            event = events.recv() => match event {
+                Ok(event) => Box::new(move |scripts| call_all(scripts, "on_event", &event)),
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("scripts missed {n} events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            entry = entries.recv() => match entry {
+                Ok(entry) => Box::new(move |scripts| call_all(scripts, "on_entry", &entry)),
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("scripts missed {n} history entries");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        let result = spawn_blocking(move || {
+            let failed = call(&mut scripts);
+            (scripts, failed)
+        })
+        .await;
+        match result {
+            Ok((loaded, failed)) => {
+                scripts = loaded;
+                (0..failed).for_each(|_| api.daemon.metrics.script_failure());
+            }
+            Err(e) => {
+                // The scripts were lost with the task, load them again right away.
+                log::error!("scripts failed: {e}");
+                api.daemon.metrics.script_failure();
+                scripts = Scripts::new();
+                check.reset_immediately();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Call the function `name` of each script with `value`.
+///
+/// Returns the number of scripts that failed.
+fn call_all<T: serde::Serialize>(scripts: &Scripts, name: &str, value: &T) -> usize {
+    match rhai::serde::to_dynamic(value) {
+        Ok(arg) => scripts
+            .values()
+            .filter(|script| !script.call(name, &arg))
+            .count(),
+        Err(e) => {
+            log::error!("cannot pass {name} argument to scripts: {e}");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandboxed() -> Engine {
+        let mut engine = Engine::new();
+        sandbox(&mut engine);
+        engine
+    }
+
+    #[test]
+    fn imports_and_limits() {
+        let engine = sandboxed();
+        let dir = std::env::temp_dir().join(format!("timeflip-scripts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("module.rhai"), "export const SECRET = 42;").unwrap();
+        let import = format!("import \"{}/module\" as m; m::SECRET", dir.display());
+        assert!(engine.eval::<i64>(&import).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(engine.run("loop {}").is_err());
+        assert!(engine.run("fn f(n) { f(n + 1) } f(0)").is_err());
+        assert!(engine.run("let s = \"x\"; loop { s += s }").is_err());
+        assert_eq!(engine.eval::<i64>("1 + 2").unwrap(), 3);
+    }
+
+    fn script(source: &str) -> Script {
+        let mut engine = sandboxed();
+        engine.register_fn("boom", || -> i64 { panic!("boom") });
+        let ast = engine.compile(source).unwrap();
+        Script {
+            name: "test.rhai".into(),
+            modified: SystemTime::UNIX_EPOCH,
+            engine,
+            ast: Some(ast),
+        }
+    }
+
+    #[test]
+    fn panicking_callback() {
+        let arg = Dynamic::from(1_i64);
+        assert!(!script("fn on_event(e) { boom() }").call("on_event", &arg));
+        assert!(!script("fn on_event(e) { throw \"failed\" }").call("on_event", &arg));
+        assert!(script("fn on_event(e) { e + 1 }").call("on_event", &arg));
+        assert!(script("fn on_entry(e) { boom() }").call("on_event", &arg));
+
+        let scripts = Scripts::from([
+            ("a.rhai".into(), script("fn on_event(e) { boom() }")),
+            ("b.rhai".into(), script("fn on_event(e) { e }")),
+        ]);
+        assert_eq!(call_all(&scripts, "on_event", &1), 1);
+    }
+}
@@ -0,0 +1,362 @@
+//! The `io.timeflippers.TimeFlip` service on the session bus.
+//!
+//! The object `/io/timeflippers/TimeFlip` has the properties `CurrentFacet`, `SideName`,
+//! `Paused`, `Locked` and `Battery`, changes are signalled with `PropertiesChanged`. The
+//! methods `Lock`, `Unlock`, `Pause`, `Unpause`, `SetTime(unix_seconds)` and `Sync` control
+//! the TimeFlip2.
+
+use chrono::{TimeZone, Utc};
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, SignalArgs},
+    nonblock::SyncConnection,
+    Path,
+};
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+use futures::{Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+
+use super::{bus_lost, Command, Control, Daemon, Error, Update};
+use crate::{timeflip::Event, types::Facet};
+
+const BUS_NAME: &str = "io.timeflippers.TimeFlip";
+const INTERFACE: &str = "io.timeflippers.TimeFlip";
+const OBJECT_PATH: &str = "/io/timeflippers/TimeFlip";
+
+/// The values of the service's properties.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Properties {
+    facet: u8,
+    side: String,
+    paused: bool,
+    locked: bool,
+    battery: u8,
+}
+
+impl Properties {
+    /// The properties differing from `old`.
+    fn changed(&self, old: &Properties) -> PropMap {
+        let mut changed = PropMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| {
+            changed.insert(name.into(), Variant(value));
+        };
+        if self.facet != old.facet {
+            insert("CurrentFacet", Box::new(self.facet));
+        }
+        if self.side != old.side {
+            insert("SideName", Box::new(self.side.clone()));
+        }
+        if self.paused != old.paused {
+            insert("Paused", Box::new(self.paused));
+        }
+        if self.locked != old.locked {
+            insert("Locked", Box::new(self.locked));
+        }
+        if self.battery != old.battery {
+            insert("Battery", Box::new(self.battery));
+        }
+        changed
+    }
+}
+
+/// Data of the exported object.
+struct Service<C: Control> {
+    control: Arc<C>,
+    connection: Arc<SyncConnection>,
+    properties: Arc<Mutex<Properties>>,
+}
+
+impl<C: Control> Clone for Service<C> {
+    fn clone(&self) -> Self {
+        Service {
+            control: self.control.clone(),
+            connection: self.connection.clone(),
+            properties: self.properties.clone(),
+        }
+    }
+}
+
+impl<C: Control> Service<C> {
+    fn properties(&self) -> Properties {
+        self.properties.lock().expect("not poisoned").clone()
+    }
+
+    /// Update the properties, signalling the changed ones.
+    fn update(&self, f: impl FnOnce(&mut Properties)) {
+        let changed = {
+            let mut properties = self.properties.lock().expect("not poisoned");
+            let old = properties.clone();
+            f(&mut properties);
+            properties.changed(&old)
+        };
+        if changed.is_empty() {
+            return;
+        }
+
+        let signal = PropertiesPropertiesChanged {
+            interface_name: INTERFACE.into(),
+            changed_properties: changed,
+            invalidated_properties: vec![],
+        };
+        let message = signal.to_emit_message(&Path::from(OBJECT_PATH));
+        if self.connection.send(message).is_err() {
+            log::warn!("cannot signal changed properties on D-Bus");
+        }
+    }
+
+    fn set_facet(&self, facet: &Facet) {
+        let side = self
+            .control
+            .side_name(facet.clone())
+            .unwrap_or_default()
+            .to_string();
+        self.update(|p| {
+            p.facet = facet.index();
+            p.side = side;
+        });
+    }
+
+    /// Execute `command`, the changed properties are signalled once the daemon relays them.
+    async fn execute(self, command: Command) -> Result<(), MethodErr> {
+        self.control.execute(command).await.map_err(|e| {
+            self.control.record_error(&e);
+            MethodErr::failed(&e)
+        })
+    }
+
+    /// Update the properties changed by `update`.
+    fn relay(&self, update: Update) {
+        match update {
+            Update::Event(envelope) => match envelope.event {
+                Event::Facet(facet) => self.set_facet(&facet),
+                Event::DoubleTap { pause, .. } => self.update(|p| p.paused = pause),
+                Event::BatteryLevel(battery) => self.update(|p| p.battery = battery.get()),
+                _ => {}
+            },
+            Update::Status(status) => self.update(|p| {
+                p.paused = status.pause_mode;
+                p.locked = status.lock_mode;
+            }),
+            Update::Entry(_) => {}
+        }
+    }
+}
+
+/// Register a method without arguments executing `command`.
+fn command<C: Control>(b: &mut IfaceBuilder<Service<C>>, name: &'static str, command: Command) {
+    b.method_with_cr_async(name, (), (), move |mut ctx, cr, (): ()| {
+        let service = cr.data_mut::<Service<C>>(ctx.path()).cloned();
+        let command = command.clone();
+        async move {
+            let result = match service {
+                Some(service) => service.execute(command).await,
+                None => Err(MethodErr::no_path(ctx.path())),
+            };
+            ctx.reply(result)
+        }
+    });
+}
+
+/// Publish the service, failing when the connection to the session bus is lost.
+pub(super) async fn serve(daemon: Arc<Daemon>) -> Result<(), Error> {
+    let (resource, connection) = dbus_tokio::connection::new_session_sync()?;
+    let lost = tokio::spawn(resource);
+    let updates = daemon.updates("D-Bus service");
+
+    tokio::select! {
+        e = lost => Err(bus_lost("session bus", e)),
+        result = publish(daemon, connection, updates) => result,
+    }
+}
+
+/// Export the service on `connection` and keep its properties up to date with `updates`.
+async fn publish<C: Control>(
+    control: Arc<C>,
+    connection: Arc<SyncConnection>,
+    mut updates: impl Stream<Item = Update> + Unpin,
+) -> Result<(), Error> {
+    let service = Service {
+        control: control.clone(),
+        connection: connection.clone(),
+        properties: Arc::default(),
+    };
+    {
+        let state = control.state().await?;
+        *service.properties.lock().expect("not poisoned") = Properties {
+            side: control
+                .side_name(state.facet.clone())
+                .unwrap_or_default()
+                .into(),
+            facet: state.facet.index(),
+            paused: state.paused,
+            locked: state.locked,
+            battery: state.battery.get(),
+        };
+    }
+
+    let mut cr = Crossroads::new();
+    cr.set_async_support(Some((
+        connection.clone(),
+        Box::new(|future| {
+            tokio::spawn(future);
+        }),
+    )));
+    let iface = cr.register(INTERFACE, |b: &mut IfaceBuilder<Service<C>>| {
+        b.property("CurrentFacet")
+            .get(|_, s| Ok(s.properties().facet))
+            .emits_changed_true();
+        b.property("SideName")
+            .get(|_, s| Ok(s.properties().side))
+            .emits_changed_true();
+        b.property("Paused")
+            .get(|_, s| Ok(s.properties().paused))
+            .emits_changed_true();
+        b.property("Locked")
+            .get(|_, s| Ok(s.properties().locked))
+            .emits_changed_true();
+        b.property("Battery")
+            .get(|_, s| Ok(s.properties().battery))
+            .emits_changed_true();
+
+        command(b, "Lock", Command::Lock);
+        command(b, "Unlock", Command::Unlock);
+        command(b, "Pause", Command::Pause);
+        command(b, "Unpause", Command::Unpause);
+        command(b, "Sync", Command::Sync);
+        b.method_with_cr_async(
+            "SetTime",
+            ("unix_seconds",),
+            (),
+            |mut ctx, cr, (seconds,): (i64,)| {
+                let service = cr.data_mut::<Service<C>>(ctx.path()).cloned();
+                async move {
+                    let result = match (service, Utc.timestamp_opt(seconds, 0).single()) {
+                        (Some(service), Some(time)) => {
+                            service.execute(Command::SetTime(time)).await
+                        }
+                        (None, _) => Err(MethodErr::no_path(ctx.path())),
+                        (_, None) => Err(MethodErr::invalid_arg(&seconds)),
+                    };
+                    ctx.reply(result)
+                }
+            },
+        );
+    });
+    cr.insert(OBJECT_PATH, &[iface], service.clone());
+
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = cr.handle_message(message, connection);
+            true
+        }),
+    );
+    connection
+        .request_name(BUS_NAME, false, true, false)
+        .await?;
+    log::info!("published {BUS_NAME} on the session bus");
+
+    while let Some(update) = updates.next().await {
+        service.relay(update);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::fake::{envelope, status, Bus, FakeControl},
+        types::Percent,
+    };
+    use dbus::{
+        arg::{Arg, Get},
+        nonblock::{stdintf::org_freedesktop_dbus::Properties as _, Proxy},
+    };
+    use futures::channel::mpsc;
+    use std::time::Duration;
+    use tokio::time::{sleep, timeout};
+
+    type Client = Proxy<'static, Arc<SyncConnection>>;
+
+    async fn get<T: for<'b> Get<'b> + Arg + 'static>(client: &Client, name: &str) -> T {
+        client.get(INTERFACE, name).await.unwrap()
+    }
+
+    async fn call(client: &Client, method: &str) -> Result<(), dbus::Error> {
+        client.method_call(INTERFACE, method, ()).await
+    }
+
+    #[tokio::test]
+    async fn properties_and_methods() {
+        let Some(bus) = Bus::start("session-bus") else {
+            return;
+        };
+        let mut config = Config::default();
+        config.sides[0].name = Some("Work".into());
+        let control = Arc::new(FakeControl::new(config));
+        let (updates, rx) = mpsc::unbounded();
+        tokio::spawn(publish(control.clone(), bus.connect(), rx));
+
+        let client = Proxy::new(BUS_NAME, OBJECT_PATH, Duration::from_secs(5), bus.connect());
+        timeout(Duration::from_secs(10), async {
+            while client.get::<u8>(INTERFACE, "CurrentFacet").await.is_err() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("service is published");
+
+        assert_eq!(get::<u8>(&client, "CurrentFacet").await, 1);
+        assert_eq!(get::<String>(&client, "SideName").await, "Work");
+        assert!(!get::<bool>(&client, "Paused").await);
+        assert!(!get::<bool>(&client, "Locked").await);
+        assert_eq!(get::<u8>(&client, "Battery").await, 80);
+
+        let facet = Event::Facet(Facet::new(2).unwrap());
+        let battery = Event::BatteryLevel(Percent::new(40).unwrap());
+        updates
+            .unbounded_send(Update::Event(envelope(1, facet)))
+            .unwrap();
+        updates
+            .unbounded_send(Update::Event(envelope(2, battery)))
+            .unwrap();
+        updates
+            .unbounded_send(Update::Status(status(true, true)))
+            .unwrap();
+        // Updates are relayed in order, once the last one is visible the others are as well.
+        timeout(Duration::from_secs(10), async {
+            while !get::<bool>(&client, "Locked").await {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("status is relayed");
+        assert_eq!(get::<u8>(&client, "CurrentFacet").await, 2);
+        assert_eq!(get::<String>(&client, "SideName").await, "");
+        assert!(get::<bool>(&client, "Paused").await);
+        assert_eq!(get::<u8>(&client, "Battery").await, 40);
+
+        for method in ["Lock", "Unlock", "Pause", "Unpause", "Sync"] {
+            call(&client, method).await.unwrap();
+        }
+        let set_time =
+            |seconds: i64| client.method_call::<(), _, _, _>(INTERFACE, "SetTime", (seconds,));
+        set_time(1_700_000_000).await.unwrap();
+        assert!(set_time(i64::MAX).await.is_err());
+        assert_eq!(
+            control.commands(),
+            vec![
+                Command::Lock,
+                Command::Unlock,
+                Command::Pause,
+                Command::Unpause,
+                Command::Sync,
+                Command::SetTime(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+            ]
+        );
+    }
+}
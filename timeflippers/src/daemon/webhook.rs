@@ -0,0 +1,544 @@
+//! HTTP webhooks called on the TimeFlip2's events.
+//!
+//! Requests are queued per webhook and delivered in order. Failed deliveries are retried with
+//! exponential backoff and the queue is saved to a file, so requests survive restarts and
+//! periods without network.
+
+use chrono::{DateTime, Utc};
+use futures::stream::{Stream, StreamExt};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs,
+    sync::{Mutex, Notify},
+    task::JoinSet,
+    time::sleep,
+};
+
+use super::{Control, Daemon, Error, Update};
+use crate::{
+    config::{Webhook, WebhookEvent, WebhooksConfig},
+    timeflip::{Entry, Envelope, Event},
+};
+
+/// Delay before the first retry, doubled on each further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum delay between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+/// Time after which a request is considered failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request waiting for delivery.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Delivery {
+    event: String,
+    body: String,
+}
+
+enum DeliveryError {
+    /// The request failed temporarily and is retried.
+    Failed(String),
+    /// The endpoint refused the request, retrying will not help.
+    Rejected(StatusCode),
+}
+
+/// Undelivered requests by webhook, see [identity()].
+struct Queue {
+    file: Option<PathBuf>,
+    pending: Mutex<HashMap<String, VecDeque<Delivery>>>,
+}
+
+impl Queue {
+    /// Load the queue, dropping the requests of webhooks not in `hooks`.
+    async fn load(file: Option<PathBuf>, hooks: &[String]) -> Queue {
+        let mut pending: HashMap<String, VecDeque<Delivery>> = match &file {
+            Some(file) => match fs::read_to_string(file).await {
+                Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                    log::error!("ignoring invalid webhook queue {}: {e}", file.display());
+                    HashMap::new()
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+                Err(e) => {
+                    log::error!("cannot read webhook queue {}: {e}", file.display());
+                    HashMap::new()
+                }
+            },
+            None => HashMap::new(),
+        };
+
+        let orphaned: Vec<String> = pending
+            .keys()
+            .filter(|id| !hooks.contains(id))
+            .cloned()
+            .collect();
+        for id in &orphaned {
+            let dropped = pending.remove(id).map_or(0, |queue| queue.len());
+            log::warn!("dropping {dropped} requests queued for removed webhook {id}");
+        }
+
+        let queue = Queue {
+            file,
+            pending: Mutex::new(pending),
+        };
+        if !orphaned.is_empty() {
+            queue.save(&*queue.pending.lock().await).await;
+        }
+        queue
+    }
+
+    async fn save(&self, pending: &HashMap<String, VecDeque<Delivery>>) {
+        if let Some(file) = &self.file {
+            let json = serde_json::to_vec(pending).expect("deliveries are serializable");
+            if let Err(e) = fs::write(file, json).await {
+                log::error!("cannot update webhook queue {}: {e}", file.display());
+            }
+        }
+    }
+
+    async fn push(&self, id: &str, delivery: Delivery) {
+        let mut pending = self.pending.lock().await;
+        pending.entry(id.into()).or_default().push_back(delivery);
+        self.save(&pending).await;
+    }
+
+    async fn front(&self, id: &str) -> Option<Delivery> {
+        let pending = self.pending.lock().await;
+        pending.get(id).and_then(|q| q.front()).cloned()
+    }
+
+    async fn pop(&self, id: &str) {
+        let mut pending = self.pending.lock().await;
+        if let Some(queue) = pending.get_mut(id) {
+            queue.pop_front();
+            if queue.is_empty() {
+                pending.remove(id);
+            }
+        }
+        self.save(&pending).await;
+    }
+}
+
+fn hex(prefix: &str, bytes: &[u8]) -> String {
+    bytes.iter().fold(String::from(prefix), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// The key of the webhook's requests in the [Queue].
+///
+/// Webhooks posting to the same URL are told apart by their other settings, unless named.
+fn identity(hook: &Webhook) -> String {
+    if let Some(name) = &hook.name {
+        return name.clone();
+    }
+    let mut digest = Sha256::new();
+    let events: Vec<&str> = hook.events.iter().map(WebhookEvent::name).collect();
+    for part in [
+        hook.url.as_str(),
+        &events.join(","),
+        hook.body.as_deref().unwrap_or_default(),
+        hook.secret.as_deref().unwrap_or_default(),
+        &hook.signature_header,
+    ] {
+        digest.update(part.as_bytes());
+        digest.update([0]);
+    }
+    hex("sha256:", &digest.finalize()[..8])
+}
+
+/// Signature of `body` as `sha256=<hex digest>`.
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body.as_bytes());
+    hex("sha256=", &mac.finalize().into_bytes())
+}
+
+async fn deliver(
+    client: &Client,
+    hook: &Webhook,
+    delivery: &Delivery,
+) -> Result<(), DeliveryError> {
+    let mut request = client
+        .post(&hook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-TimeFlip-Event", &delivery.event)
+        .body(delivery.body.clone());
+    if let Some(secret) = &hook.secret {
+        request = request.header(&hook.signature_header, signature(secret, &delivery.body));
+    }
+
+    match request.send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response)
+            if response.status().is_client_error()
+                && response.status() != StatusCode::REQUEST_TIMEOUT
+                && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+        {
+            Err(DeliveryError::Rejected(response.status()))
+        }
+        Ok(response) => Err(DeliveryError::Failed(response.status().to_string())),
+        Err(e) => Err(DeliveryError::Failed(e.to_string())),
+    }
+}
+
+/// Deliver the requests queued for `hook` one after another.
+async fn deliver_queued(client: Client, queue: Arc<Queue>, hook: Webhook, queued: Arc<Notify>) {
+    let id = identity(&hook);
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let Some(delivery) = queue.front(&id).await else {
+            queued.notified().await;
+            continue;
+        };
+
+        match deliver(&client, &hook, &delivery).await {
+            Ok(()) => {
+                log::debug!("delivered {} event to {}", delivery.event, hook.url);
+                queue.pop(&id).await;
+                backoff = INITIAL_BACKOFF;
+            }
+            Err(DeliveryError::Rejected(status)) => {
+                log::error!(
+                    "{} rejected {} event with {status}, dropping it",
+                    hook.url,
+                    delivery.event
+                );
+                queue.pop(&id).await;
+            }
+            Err(DeliveryError::Failed(e)) => {
+                log::warn!(
+                    "cannot deliver {} event to {}: {e}, retrying in {}s",
+                    delivery.event,
+                    hook.url,
+                    backoff.as_secs()
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Render the body of a request for `hook`.
+fn render(hook: &Webhook, data: &Value) -> Option<String> {
+    match &hook.body {
+        Some(template) => match minijinja::Environment::new().render_str(template, data) {
+            Ok(body) => Some(body),
+            Err(e) => {
+                log::error!("cannot render body for {}: {e}", hook.url);
+                None
+            }
+        },
+        None => Some(data.to_string()),
+    }
+}
+
+struct Dispatcher<C> {
+    control: Arc<C>,
+    queue: Arc<Queue>,
+    /// The webhooks with their identity and the notification of their worker.
+    hooks: Vec<(Webhook, String, Arc<Notify>)>,
+}
+
+impl<C: Control> Dispatcher<C> {
+    async fn fire(&self, event: WebhookEvent, time: DateTime<Utc>, mut data: Value) {
+        data["event"] = json!(event.name());
+        data["time"] = json!(time);
+        for (hook, id, queued) in &self.hooks {
+            if !hook.events.contains(&event) {
+                continue;
+            }
+            if let Some(body) = render(hook, &data) {
+                let delivery = Delivery {
+                    event: event.name().into(),
+                    body,
+                };
+                self.queue.push(id, delivery).await;
+                queued.notify_one();
+            }
+        }
+    }
+
+    async fn event(&self, envelope: Envelope, low_battery: &mut bool) {
+        let (time, seq) = (envelope.received, envelope.seq);
+        match envelope.event {
+            Event::Facet(facet) => {
+                let data =
+                    json!({ "seq": seq, "facet": facet, "side": self.control.side_name(facet) });
+                self.fire(WebhookEvent::Facet, time, data).await;
+            }
+            Event::DoubleTap { facet, pause } => {
+                let event = if pause {
+                    WebhookEvent::Pause
+                } else {
+                    WebhookEvent::Unpause
+                };
+                let data =
+                    json!({ "seq": seq, "facet": facet, "side": self.control.side_name(facet) });
+                self.fire(event, time, data).await;
+            }
+            Event::BatteryLevel(battery) => {
+                let low = battery.get() < self.control.config().daemon.low_battery();
+                if low && !*low_battery {
+                    let data = json!({ "seq": seq, "battery": battery });
+                    self.fire(WebhookEvent::LowBattery, time, data).await;
+                }
+                *low_battery = low;
+            }
+            Event::Disconnected => {
+                let data = json!({ "seq": seq });
+                self.fire(WebhookEvent::Disconnect, time, data).await
+            }
+            Event::Event(_) | Event::SyncState(_) | Event::History(_) => {}
+        }
+    }
+
+    async fn entry(&self, entry: Entry) {
+        let side = self.control.side_name(entry.facet.clone());
+        let data = json!({ "side": side, "entry": entry });
+        self.fire(WebhookEvent::Entry, Utc::now(), data).await;
+    }
+
+    /// Queue requests for `updates` until they end.
+    async fn run(&self, mut updates: impl Stream<Item = Update> + Unpin) {
+        let mut low_battery = false;
+        while let Some(update) = updates.next().await {
+            match update {
+                Update::Event(event) => self.event(event, &mut low_battery).await,
+                Update::Entry(entry) => self.entry(entry).await,
+                Update::Status(_) => {}
+            }
+        }
+    }
+}
+
+/// Start a worker delivering the queued requests of each webhook.
+fn start_workers(
+    client: &Client,
+    queue: &Arc<Queue>,
+    hooks: Vec<Webhook>,
+    workers: &mut JoinSet<()>,
+) -> Vec<(Webhook, String, Arc<Notify>)> {
+    hooks
+        .into_iter()
+        .map(|hook| {
+            let queued = Arc::new(Notify::new());
+            workers.spawn(deliver_queued(
+                client.clone(),
+                queue.clone(),
+                hook.clone(),
+                queued.clone(),
+            ));
+            let id = identity(&hook);
+            (hook, id, queued)
+        })
+        .collect()
+}
+
+/// Queue requests for the TimeFlip2's events and deliver them to the webhooks.
+pub(super) async fn dispatch(daemon: Arc<Daemon>, config: WebhooksConfig) -> Result<(), Error> {
+    let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+    let ids: Vec<String> = config.hooks.iter().map(identity).collect();
+    let queue = Arc::new(Queue::load(config.queue, &ids).await);
+
+    let mut workers = JoinSet::new();
+    let hooks = start_workers(&client, &queue, config.hooks, &mut workers);
+    let updates = daemon.updates("webhooks");
+    let dispatcher = Dispatcher {
+        control: daemon,
+        queue,
+        hooks,
+    };
+    dispatcher.run(updates).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        daemon::fake::{envelope, FakeControl},
+        types::Facet,
+    };
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+    use futures::stream;
+    use tokio::{net::TcpListener, time::timeout};
+
+    /// Requests received by the stand-in endpoint, which fails the first `failures` requests.
+    #[derive(Default)]
+    struct Endpoint {
+        failures: usize,
+        received: Vec<(String, Option<String>, Value)>,
+    }
+
+    async fn receive(
+        State(endpoint): State<Arc<std::sync::Mutex<Endpoint>>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let mut endpoint = endpoint.lock().unwrap();
+        if endpoint.failures > 0 {
+            endpoint.failures -= 1;
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        let signature = header("X-TimeFlip-Signature");
+        assert_eq!(signature, Some(signature_of(&body)));
+        endpoint.received.push((
+            header("X-TimeFlip-Event").unwrap(),
+            signature,
+            serde_json::from_str(&body).unwrap(),
+        ));
+        StatusCode::NO_CONTENT
+    }
+
+    fn signature_of(body: &str) -> String {
+        signature("secret", body)
+    }
+
+    async fn serve(endpoint: Arc<std::sync::Mutex<Endpoint>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/hook", post(receive))
+            .with_state(endpoint);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{addr}/hook")
+    }
+
+    fn hook(url: &str) -> Webhook {
+        Webhook {
+            name: None,
+            url: url.into(),
+            events: vec![WebhookEvent::Facet, WebhookEvent::Disconnect],
+            body: None,
+            secret: Some("secret".into()),
+            signature_header: "X-TimeFlip-Signature".into(),
+        }
+    }
+
+    fn queue_file(name: &str) -> PathBuf {
+        let file =
+            std::env::temp_dir().join(format!("timeflippers-{name}-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        file
+    }
+
+    async fn read_queue(file: &PathBuf) -> HashMap<String, VecDeque<Delivery>> {
+        serde_json::from_slice(&fs::read(file).await.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn identities() {
+        let hook = hook("http://localhost/hook");
+        let other = Webhook {
+            events: vec![WebhookEvent::Entry],
+            ..hook.clone()
+        };
+        assert_eq!(identity(&hook), identity(&hook.clone()));
+        assert_ne!(identity(&hook), identity(&other));
+        assert_eq!(
+            identity(&Webhook {
+                name: Some("ci".into()),
+                ..hook
+            }),
+            "ci"
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_and_persist() {
+        let endpoint = Arc::new(std::sync::Mutex::new(Endpoint {
+            failures: 1,
+            ..Default::default()
+        }));
+        let hook = hook(&serve(endpoint.clone()).await);
+        let id = identity(&hook);
+        let file = queue_file("retry");
+
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap();
+        let queue = Arc::new(Queue::load(Some(file.clone()), std::slice::from_ref(&id)).await);
+        let mut workers = JoinSet::new();
+        let hooks = start_workers(&client, &queue, vec![hook], &mut workers);
+        let dispatcher = Dispatcher {
+            control: Arc::new(FakeControl::new(Config::default())),
+            queue,
+            hooks,
+        };
+        dispatcher
+            .run(stream::iter([
+                Update::Event(envelope(1, Event::Facet(Facet::new(2).unwrap()))),
+                Update::Event(envelope(
+                    2,
+                    Event::BatteryLevel(crate::Percent::new(50).unwrap()),
+                )),
+                Update::Event(envelope(3, Event::Disconnected)),
+            ]))
+            .await;
+
+        // The first delivery failed and is retried after a second, both are saved meanwhile.
+        let saved = read_queue(&file).await;
+        assert_eq!(saved.keys().collect::<Vec<_>>(), vec![&id]);
+        assert_eq!(saved[&id].len(), 2);
+
+        timeout(Duration::from_secs(10), async {
+            while endpoint.lock().unwrap().received.len() < 2 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("requests are delivered");
+
+        let received = endpoint.lock().unwrap().received.clone();
+        assert_eq!(received[0].0, "facet");
+        assert_eq!(received[0].2["facet"], json!(2));
+        assert_eq!(received[0].2["side"], json!(null));
+        assert_eq!(received[0].2["seq"], json!(1));
+        assert_eq!(received[0].2["time"], json!("1970-01-01T00:00:01Z"));
+        assert_eq!(received[1].0, "disconnect");
+        assert!(read_queue(&file).await.is_empty());
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn drop_orphaned_queues() {
+        let file = queue_file("orphans");
+        let delivery = Delivery {
+            event: "facet".into(),
+            body: "{}".into(),
+        };
+        let saved = HashMap::from([
+            ("current".to_string(), VecDeque::from([delivery.clone()])),
+            ("removed".to_string(), VecDeque::from([delivery])),
+        ]);
+        fs::write(&file, serde_json::to_vec(&saved).unwrap())
+            .await
+            .unwrap();
+
+        let queue = Queue::load(Some(file.clone()), &["current".to_string()]).await;
+        assert!(queue.front("current").await.is_some());
+        assert!(queue.front("removed").await.is_none());
+        assert_eq!(
+            read_queue(&file).await.keys().collect::<Vec<_>>(),
+            vec!["current"]
+        );
+        let _ = std::fs::remove_file(&file);
+    }
+}
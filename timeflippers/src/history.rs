@@ -0,0 +1,72 @@
+//! Keeping a local copy of TimeFlip2's history.
+#![deny(missing_docs)]
+
+use std::{io, path::Path};
+use thiserror::Error;
+use tokio::fs;
+
+use crate::timeflip::{self, Entry, TimeFlip};
+
+/// Error updating the history.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    TimeFlip(#[from] timeflip::Error),
+    #[error("cannot read history file: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid history file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Read the entries saved in `file`, sorted by ID.
+///
+/// A missing file is treated as empty history.
+pub async fn load(file: &Path) -> Result<Vec<Entry>, Error> {
+    match fs::read_to_string(file).await {
+        Ok(s) => {
+            let mut entries: Vec<Entry> = serde_json::from_str(&s)?;
+            entries.sort_by_key(|e| e.id);
+            Ok(entries)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read new entries from the TimeFlip2, merged with the ones saved in `file`.
+///
+/// Reading starts with entry `start_with`, or with the latest entry in `file`. The merged
+/// entries are written back to `file`.
+pub async fn update(
+    timeflip: &TimeFlip,
+    file: Option<&Path>,
+    start_with: Option<u32>,
+) -> Result<Vec<Entry>, Error> {
+    let mut entries = match file {
+        Some(file) => load(file).await?,
+        None => vec![],
+    };
+    let start_with = start_with
+        .or_else(|| entries.last().map(|e| e.id))
+        .unwrap_or(0);
+
+    let mut update = timeflip.read_history_since(start_with).await?;
+
+    let new_ids = update.iter().map(|e| e.id).collect::<Vec<_>>();
+    entries.retain(|entry| !new_ids.contains(&entry.id));
+    entries.append(&mut update);
+
+    if let Some(file) = file {
+        match serde_json::to_vec(&entries) {
+            Ok(json) => {
+                if let Err(e) = fs::write(file, json).await {
+                    log::error!("cannot update entries file {}: {e}", file.display());
+                }
+            }
+            Err(e) => log::error!("cannot update entries file {}: {e}", file.display()),
+        }
+    }
+
+    Ok(entries)
+}
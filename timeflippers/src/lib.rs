@@ -1,15 +1,26 @@
 pub use bluez_async::BluetoothSession;
 
+pub mod activity;
+pub mod budget;
+pub mod daemon;
+pub mod history;
+pub mod pomodoro;
 pub mod timeflip;
 pub use timeflip::TimeFlip;
 
 pub mod view;
 
 mod config;
-pub use config::Config;
+pub use config::{
+    BreakRule, Budget, BudgetPeriod, ComplianceConfig, Config, DaemonConfig, FacetRate,
+    HookConcurrency, HooksConfig, InvoiceConfig, InvoiceGrouping, MqttConfig, NotificationEvent,
+    NotificationsConfig, Rule, RuleAction, TargetsConfig, Webhook, WebhookEvent, WebhooksConfig,
+};
 
 mod types;
 pub use types::{
-    BlinkInterval, BlinkIntervalError, Color, Facet, FacetError, FacetTask, Minutes, Percent,
-    PercentError,
+    Amount, AmountError, BlinkInterval, BlinkIntervalError, Color, Facet, FacetError, FacetTask,
+    Hours, HoursError, Minutes, Percent, PercentError, Rounding, RoundingIncrement,
+    RoundingIncrementError, RoundingMode, RoundingScope, TaxRate, TaxRateError, TimeWindow,
+    TimeWindowError,
 };
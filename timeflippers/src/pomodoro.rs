@@ -0,0 +1,229 @@
+//! Following pomodoro timers of facets configured with [FacetTask::Pomodoro].
+#![deny(missing_docs)]
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    types::{Facet, FacetTask},
+};
+
+/// A finished pomodoro, either completed or interrupted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PomodoroRecord {
+    /// The facet the pomodoro ran on.
+    pub facet: Facet,
+    /// The time the pomodoro started.
+    pub started: DateTime<Utc>,
+    /// The configured length of the pomodoro.
+    pub length: Duration,
+    /// Whether the pomodoro ran until the end.
+    pub completed: bool,
+}
+
+/// A running pomodoro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pomodoro {
+    /// The facet the pomodoro runs on.
+    pub facet: Facet,
+    /// The time the pomodoro started.
+    pub started: DateTime<Utc>,
+    /// The configured length of the pomodoro.
+    pub length: Duration,
+}
+
+impl Pomodoro {
+    /// The time left at `now`.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Duration {
+        let elapsed = (now - self.started).to_std().unwrap_or_default();
+        self.length.saturating_sub(elapsed)
+    }
+
+    fn finish(self, completed: bool) -> PomodoroRecord {
+        PomodoroRecord {
+            facet: self.facet,
+            started: self.started,
+            length: self.length,
+            completed,
+        }
+    }
+}
+
+/// Follows the pomodoro timers of the facets configured with [FacetTask::Pomodoro].
+#[derive(Debug)]
+pub struct PomodoroTracker {
+    tasks: Vec<FacetTask>,
+    current: Option<Pomodoro>,
+}
+
+impl PomodoroTracker {
+    /// Construct a tracker for the facet tasks in `config`.
+    pub fn new(config: &Config) -> Self {
+        PomodoroTracker {
+            tasks: config.sides.iter().map(|side| side.task.clone()).collect(),
+            current: None,
+        }
+    }
+
+    /// The length of the facet's pomodoro, if it has one.
+    pub fn length(&self, facet: &Facet) -> Option<Duration> {
+        match self.tasks.get(facet.index_zero()) {
+            Some(FacetTask::Pomodoro(seconds)) => Some(Duration::from_secs(u64::from(*seconds))),
+            Some(FacetTask::Simple) | None => None,
+        }
+    }
+
+    /// The running pomodoro.
+    pub fn current(&self) -> Option<&Pomodoro> {
+        self.current.as_ref()
+    }
+
+    /// The facet became active, with its timer running for `elapsed` already.
+    ///
+    /// `elapsed` is reported by TimeFlip2 as [FacetSettings::seconds_since_start]. Returns
+    /// the pomodoro interrupted by the flip, if any.
+    ///
+    /// [FacetSettings::seconds_since_start]: crate::timeflip::FacetSettings::seconds_since_start
+    pub fn start(
+        &mut self,
+        facet: Facet,
+        elapsed: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<PomodoroRecord> {
+        if matches!(&self.current, Some(pomodoro) if pomodoro.facet == facet) {
+            return None;
+        }
+
+        let interrupted = self.stop(now);
+        self.current = self
+            .length(&facet)
+            .filter(|length| elapsed < *length)
+            .map(|length| Pomodoro {
+                facet,
+                started: now
+                    - chrono::Duration::from_std(elapsed)
+                        .unwrap_or_else(|_| chrono::Duration::zero()),
+                length,
+            });
+        interrupted
+    }
+
+    /// Tracking stopped, e.g. by a flip to a facet without pomodoro or pause mode.
+    ///
+    /// Returns the interrupted pomodoro, if any.
+    pub fn stop(&mut self, now: DateTime<Utc>) -> Option<PomodoroRecord> {
+        let pomodoro = self.current.take()?;
+        let completed = pomodoro.remaining(now).is_zero();
+        Some(pomodoro.finish(completed))
+    }
+
+    /// Check the running pomodoro at `now`, returning it once it is completed.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Option<PomodoroRecord> {
+        match &self.current {
+            Some(pomodoro) if pomodoro.remaining(now).is_zero() => {
+                self.current.take().map(|pomodoro| pomodoro.finish(true))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 3, 8, 0, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    fn facet(index: usize) -> Facet {
+        Facet::new(index).unwrap()
+    }
+
+    fn tracker() -> PomodoroTracker {
+        let mut config = Config::default();
+        config.sides[0].task = FacetTask::Pomodoro(25 * 60);
+        config.sides[1].task = FacetTask::Pomodoro(5 * 60);
+        PomodoroTracker::new(&config)
+    }
+
+    #[test]
+    fn length() {
+        let tracker = tracker();
+        assert_eq!(tracker.length(&facet(1)), Some(25 * MINUTE));
+        assert_eq!(tracker.length(&facet(3)), None);
+    }
+
+    #[test]
+    fn complete() {
+        let mut tracker = tracker();
+        assert_eq!(tracker.start(facet(1), 10 * MINUTE, time(0)), None);
+        assert_eq!(tracker.current().unwrap().started, time(-10));
+        assert_eq!(tracker.current().unwrap().remaining(time(5)), 10 * MINUTE);
+        // Flipping the same facet up again does not restart the pomodoro.
+        assert_eq!(tracker.start(facet(1), Duration::ZERO, time(5)), None);
+
+        assert_eq!(tracker.tick(time(14)), None);
+        assert_eq!(
+            tracker.tick(time(15)),
+            Some(PomodoroRecord {
+                facet: facet(1),
+                started: time(-10),
+                length: 25 * MINUTE,
+                completed: true,
+            })
+        );
+        assert_eq!(tracker.current(), None);
+        assert_eq!(tracker.tick(time(16)), None);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut tracker = tracker();
+        tracker.start(facet(1), Duration::ZERO, time(0));
+        let interrupted = tracker.start(facet(3), Duration::ZERO, time(10)).unwrap();
+        assert!(!interrupted.completed);
+        assert_eq!(tracker.current(), None);
+
+        tracker.start(facet(2), Duration::ZERO, time(10));
+        assert!(!tracker.stop(time(14)).unwrap().completed);
+        assert_eq!(tracker.stop(time(15)), None);
+    }
+
+    #[test]
+    fn expired_timer_is_not_followed() {
+        let mut tracker = tracker();
+        tracker.start(facet(2), 5 * MINUTE, time(0));
+        assert_eq!(tracker.current(), None);
+    }
+
+    #[test]
+    fn alternating_work_and_breaks() {
+        // Four pomodoros, each followed by a break on another facet.
+        let mut tracker = tracker();
+        let mut records = vec![];
+        for round in 0..4 {
+            let start = round * 30;
+            records.extend(tracker.start(facet(1), Duration::ZERO, time(start)));
+            records.extend(tracker.tick(time(start + 25)));
+            records.extend(tracker.start(facet(2), Duration::ZERO, time(start + 25)));
+            records.extend(tracker.tick(time(start + 30)));
+        }
+
+        assert_eq!(records.len(), 8);
+        assert!(records.iter().all(|record| record.completed));
+        assert_eq!(
+            records
+                .iter()
+                .filter(|record| record.facet == facet(1))
+                .map(|record| record.started)
+                .collect::<Vec<_>>(),
+            vec![time(0), time(30), time(60), time(90)]
+        );
+    }
+}
@@ -2,14 +2,22 @@
 #![deny(missing_docs)]
 
 use bluez_async::{
-    BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicInfo,
-    DeviceInfo,
+    BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicInfo, DeviceId, DeviceInfo,
 };
 use bytes::BufMut;
 use chrono::{DateTime, Utc};
-use futures::stream::{BoxStream, StreamExt};
-use std::{convert::Infallible, string::FromUtf8Error};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
 use thiserror::Error;
+use tokio::sync::mpsc;
 
 use crate::{
     config::Config,
@@ -17,7 +25,15 @@ use crate::{
 };
 
 mod gatt;
-pub use gatt::{Entry, Event, FacetSettings, SyncState, SyncType, SystemStatus};
+pub use gatt::{
+    DeviceInformation, Entry, Event, FacetSettings, LogEvent, SyncState, SyncType, SystemStatus,
+};
+
+pub mod journal;
+use journal::Journal;
+
+mod protocol;
+pub use protocol::{EntryLayout, Profile};
 
 /// Error for communication with TimeFlip2.
 #[allow(missing_docs)]
@@ -27,6 +43,12 @@ pub enum Error {
     ReadTooShort(usize, usize),
     #[error("invalid command in result: {0}")]
     InvalidCommand(u8),
+    #[error("command 0x{command:02X} is not supported by {profile} firmware {firmware}")]
+    Unsupported {
+        command: u8,
+        profile: &'static str,
+        firmware: String,
+    },
     #[error("command execution failed")]
     CommandExecutionFailed,
     #[error("{0}")]
@@ -59,12 +81,62 @@ pub enum Error {
     SyncError(SyncType),
 }
 
+impl Error {
+    /// Short name of the kind of error, e.g. for metrics.
+    pub fn kind(&self) -> &'static str {
+        use Error::*;
+
+        match self {
+            Bluetooth(_) => "bluetooth",
+            NoDevice => "no_device",
+            InvalidCommand(_) | CommandExecutionFailed => "command",
+            Unsupported { .. } => "unsupported",
+            AccelerometerError | FlashError => "device",
+            SyncError(_) => "sync",
+            ReadTooShort(..)
+            | GetTime(_)
+            | Utf8Error(_)
+            | InvalidBatteryLevel(_)
+            | InvalidFacet(_)
+            | InvalidFacetSettings(_)
+            | InvalidCharacteristicData(_)
+            | InvalidHistoryEntry(_)
+            | InvalidSyncState(_)
+            | InvalidSystemStatus(_) => "invalid_data",
+        }
+    }
+}
+
 impl From<Infallible> for Error {
     fn from(_: Infallible) -> Self {
         unreachable!("infallible")
     }
 }
 
+/// An [Event] together with the time it was received.
+///
+/// Serialized with the event's fields next to the envelope's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Envelope {
+    /// Number of the event, increasing with every event received from the TimeFlip2.
+    pub seq: u64,
+    /// The time the host received the event from bluez.
+    pub received: DateTime<Utc>,
+    /// Seconds the TimeFlip2's clock is ahead of the host's clock, if known.
+    pub clock_offset: Option<i64>,
+    /// The event itself.
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Envelope {
+    /// The time the event was received according to the TimeFlip2's clock.
+    pub fn device_time(&self) -> Option<DateTime<Utc>> {
+        self.clock_offset
+            .map(|offset| self.received + chrono::Duration::seconds(offset))
+    }
+}
+
 /// Handles to TimeFlip2's characteristics.
 ///
 /// We need the CharacteristicInfo, which is bound to the bluez device, for accessing the dice's
@@ -83,7 +155,7 @@ struct CharacteristicHandles {
 }
 
 /// Representation of a TimeFlip2 dice connected via Bluetooth.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TimeFlip {
     /// Handle to the dbus session communicating with bluez.
     session: BluetoothSession,
@@ -93,6 +165,16 @@ pub struct TimeFlip {
     characteristics: CharacteristicHandles,
     /// Password to write to the TimeFlip2's password characteristic when connecting.
     password: [u8; 6],
+    /// Firmware revision reported by the Device Information service.
+    firmware: Option<String>,
+    /// Protocol details of the TimeFlip2's firmware.
+    profile: &'static Profile,
+    /// Whether [Event::History] events have been subscribed to.
+    history_subscribed: Arc<AtomicBool>,
+    /// Sequence number of the next [Envelope].
+    sequence: Arc<AtomicU64>,
+    /// Journal recording the notifications of event streams.
+    journal: Option<Journal>,
 }
 
 impl TimeFlip {
@@ -113,9 +195,7 @@ impl TimeFlip {
                 dev.name.as_deref().unwrap_or("<unknown>"),
                 dev.mac_address
             );
-            dev.services
-                .iter()
-                .any(|service| *service == time_flip_service_id)
+            dev.services.contains(&time_flip_service_id)
         }) {
             device
         } else {
@@ -150,6 +230,19 @@ impl TimeFlip {
 
         use gatt::Characteristic::*;
         let id = device.id.clone();
+        let firmware = match read_text(session, &id, FirmwareRevision).await {
+            Ok(firmware) => firmware,
+            Err(e) => {
+                log::warn!("cannot read firmware revision: {e}");
+                None
+            }
+        };
+        let profile = Profile::for_firmware(firmware.as_deref());
+        log::debug!(
+            "firmware {}, using protocol profile {}",
+            firmware.as_deref().unwrap_or("<unknown>"),
+            profile.name
+        );
         let timeflip = TimeFlip {
             session: session.clone(),
             device,
@@ -165,6 +258,11 @@ impl TimeFlip {
                 history: History.get_info(session, &id).await?,
             },
             password: [0x30; 6],
+            firmware,
+            profile,
+            history_subscribed: Arc::new(AtomicBool::new(false)),
+            sequence: Arc::new(AtomicU64::new(0)),
+            journal: None,
         };
 
         timeflip.write_password().await?;
@@ -172,6 +270,12 @@ impl TimeFlip {
         Ok(timeflip)
     }
 
+    /// Connect to the TimeFlip2 again after it has disconnected.
+    pub async fn reconnect(&self) -> Result<(), Error> {
+        self.session.connect(&self.device.id).await?;
+        self.write_password().await
+    }
+
     /// Disconnect the bluetooth device.
     pub async fn disconnect(&self) -> Result<(), Error> {
         Ok(self.session.disconnect(&self.device.id).await?)
@@ -199,6 +303,28 @@ impl TimeFlip {
         }
     }
 
+    /// Read the TimeFlip2's Device Information service.
+    ///
+    /// Characteristics the device does not expose are reported as `None`.
+    pub async fn device_info(&self) -> Result<DeviceInformation, Error> {
+        use gatt::Characteristic::*;
+
+        let read = |characteristic| read_text(&self.session, &self.device.id, characteristic);
+        Ok(DeviceInformation {
+            manufacturer: read(ManufacturerName).await?,
+            model_number: read(ModelNumber).await?,
+            serial_number: read(SerialNumber).await?,
+            hardware_revision: read(HardwareRevision).await?,
+            firmware_revision: read(FirmwareRevision).await?,
+            software_revision: read(SoftwareRevision).await?,
+        })
+    }
+
+    /// The protocol profile selected from the TimeFlip2's firmware revision.
+    pub fn profile(&self) -> &'static Profile {
+        self.profile
+    }
+
     /// Subscribe for [Event::BatteryLevel] events.
     pub async fn subscribe_battery_level(&self) -> Result<(), Error> {
         self.session
@@ -208,13 +334,13 @@ impl TimeFlip {
     }
 
     /// Read the (informational) last event of the TimeFlip2.
-    pub async fn last_event(&self) -> Result<String, Error> {
+    pub async fn last_event(&self) -> Result<LogEvent, Error> {
         let data = self
             .session
             .read_characteristic_value(&self.characteristics.event.id)
             .await?;
 
-        String::from_utf8(data).map_err(Into::into)
+        Ok(LogEvent::parse(&String::from_utf8(data)?))
     }
 
     /// Subscribe for [Event::Event] events.
@@ -246,6 +372,26 @@ impl TimeFlip {
             .map_err(Into::into)
     }
 
+    /// Subscribe for [Event::SyncState] events.
+    pub async fn subscribe_sync_state(&self) -> Result<(), Error> {
+        self.session
+            .start_notify(&self.characteristics.system_state.id)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Subscribe for [Event::History] events.
+    ///
+    /// TimeFlip2 sends history entries only when they are requested, e.g. by
+    /// [TimeFlip::read_history_since()], which keeps the subscription in place.
+    pub async fn subscribe_history(&self) -> Result<(), Error> {
+        self.session
+            .start_notify(&self.characteristics.history.id)
+            .await?;
+        self.history_subscribed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// Subscribe for [Event::DoubleTap] events.
     pub async fn subscribe_double_tap(&self) -> Result<(), Error> {
         self.session
@@ -264,6 +410,7 @@ impl TimeFlip {
         T: gatt::CommandResult,
         Error: From<T::Error>,
     {
+        self.profile.check(&command, self.firmware.as_deref())?;
         self.session
             .write_characteristic_value(&self.characteristics.command.id, command.to_vec())
             .await?;
@@ -271,7 +418,10 @@ impl TimeFlip {
             .session
             .read_characteristic_value(&self.characteristics.command.id)
             .await?;
-        if cmd_execution.len() < 2 || cmd_execution[0] != command.id() || cmd_execution[1] != 2 {
+        if cmd_execution.len() < 2
+            || cmd_execution[0] != command.id()
+            || cmd_execution[1] != self.profile.status_ok
+        {
             return Err(Error::CommandExecutionFailed);
         }
 
@@ -287,6 +437,12 @@ impl TimeFlip {
         self.command::<DateTime<Utc>>(gatt::Command::GetTime).await
     }
 
+    /// Get the seconds the TimeFlip2's clock is ahead of the host's clock.
+    pub async fn clock_offset(&self) -> Result<i64, Error> {
+        let time = self.time().await?;
+        Ok((time - Utc::now()).num_seconds())
+    }
+
     /// Set the time (in UTC) saved on TimeFlip2.
     pub async fn set_time(&self, time: DateTime<Utc>) -> Result<(), Error> {
         self.command::<()>(gatt::Command::Time(time)).await
@@ -454,7 +610,7 @@ impl TimeFlip {
             .read_characteristic_value(&self.characteristics.history.id)
             .await?;
 
-        Ok(Entry::from_data(&data)?)
+        Ok(Entry::from_data(&data, self.profile.entry_layout)?)
     }
 
     /// Read the last histroy entry.
@@ -470,10 +626,7 @@ impl TimeFlip {
         self.session
             .start_notify(&self.characteristics.history.id)
             .await?;
-        let mut stream = self
-            .session
-            .characteristic_event_stream(&self.characteristics.history.id)
-            .await?;
+        let mut stream = self.decoded_events().await?;
 
         let mut read_command = Vec::with_capacity(5);
         read_command.put_u8(0x02);
@@ -483,61 +636,105 @@ impl TimeFlip {
             .await?;
 
         let mut entries = vec![];
-        while let Some(event) = stream.next().await {
+        while let Some((_, event)) = stream.next().await {
             match event {
-                BluetoothEvent::Characteristic {
-                    id,
-                    event: CharacteristicEvent::Value { value },
-                } => {
-                    if id != self.characteristics.history.id {
-                        return Err(Error::InvalidCharacteristicData(format!(
-                            "wrong ID in bluetooth event {:?}",
-                            id
-                        )));
-                    }
-                    match Entry::from_data(&value) {
-                        Ok(entry) => {
-                            log::debug!("new entry: {entry}");
-                            entries.push(entry);
-                        }
-                        Err(gatt::EntryError::EndOfHistory) => break,
-                        Err(e) => log::error!("skipping unparsable history event: {e}"),
-                    }
+                Ok(Event::History(entry)) => {
+                    log::debug!("new entry: {entry}");
+                    entries.push(entry);
                 }
-                _ => {
-                    return Err(Error::InvalidCharacteristicData(format!(
-                        "invalid bluetooth event {:?}",
-                        event
-                    )))
+                Ok(_) => {}
+                Err(gatt::EventError::History(gatt::EntryError::EndOfHistory)) => break,
+                Err(gatt::EventError::History(e)) => {
+                    log::error!("skipping unparsable history event: {e}")
                 }
+                Err(e) => log::debug!("ignoring event while reading the history: {e}"),
             }
         }
 
-        self.session
-            .stop_notify(&self.characteristics.history.id)
-            .await?;
+        if !self.history_subscribed.load(Ordering::Relaxed) {
+            self.session
+                .stop_notify(&self.characteristics.history.id)
+                .await?;
+        }
 
         Ok(entries)
     }
 
-    /// Get a stream of events from TimeFlip2.
-    pub async fn event_stream(&self) -> Result<BoxStream<'_, Event>, Error> {
+    /// Record the notifications of event streams opened from now on to `journal`.
+    pub fn record(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Receive the TimeFlip2's notifications with the time they were received from bluez.
+    ///
+    /// A task takes the notifications off the bus as they arrive, so the times do not depend
+    /// on how promptly the stream is polled.
+    async fn bluetooth_events(
+        &self,
+    ) -> Result<BoxStream<'static, (DateTime<Utc>, BluetoothEvent)>, Error> {
+        let mut events = self
+            .session
+            .device_event_stream(&self.device.id)
+            .await?
+            .boxed();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if tx.send((Utc::now(), event)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })
+        .boxed())
+    }
+
+    /// Decode the TimeFlip2's notifications, recording them to the journal if there is one.
+    async fn decoded_events(
+        &self,
+    ) -> Result<BoxStream<'static, (DateTime<Utc>, Result<Event, gatt::EventError>)>, Error> {
         let handles = gatt::EventHandles {
             device_id: self.device.id.clone(),
             battery_level: self.characteristics.battery_level.id.clone(),
             last_event: self.characteristics.event.id.clone(),
             facet: self.characteristics.facet.id.clone(),
             double_tap: self.characteristics.double_tap.id.clone(),
+            system_state: self.characteristics.system_state.id.clone(),
+            history: self.characteristics.history.id.clone(),
+            entry_layout: self.profile.entry_layout,
         };
+        let journal = self.journal.clone();
+        if let Some(journal) = &journal {
+            journal.start(&handles);
+        }
 
         Ok(self
-            .session
-            .device_event_stream(&self.device.id)
+            .bluetooth_events()
             .await?
-            .map(move |bt_event| gatt::Event::from_bluetooth_event(bt_event, &handles))
-            .filter_map(|res| async move {
+            .map(move |(time, bt_event)| match &journal {
+                Some(journal) => {
+                    let event = gatt::Event::from_bluetooth_event(bt_event.clone(), &handles);
+                    journal.event(time, &bt_event, event.as_ref().ok());
+                    (time, event)
+                }
+                None => (time, gatt::Event::from_bluetooth_event(bt_event, &handles)),
+            })
+            .boxed())
+    }
+
+    /// Get a stream of events from TimeFlip2 with the time they were received.
+    async fn timed_event_stream(
+        &self,
+    ) -> Result<BoxStream<'static, (DateTime<Utc>, Event)>, Error> {
+        Ok(self
+            .decoded_events()
+            .await?
+            .filter_map(|(time, res)| async move {
                 match res {
-                    Ok(event) => Some(event),
+                    Ok(event) => Some((time, event)),
+                    Err(gatt::EventError::History(gatt::EntryError::EndOfHistory)) => None,
                     Err(e) => {
                         log::warn!("failed to decode event in stream: {e}");
                         None
@@ -546,4 +743,50 @@ impl TimeFlip {
             })
             .boxed())
     }
+
+    /// Get a stream of events from TimeFlip2.
+    pub async fn event_stream(&self) -> Result<BoxStream<'_, Event>, Error> {
+        Ok(self
+            .timed_event_stream()
+            .await?
+            .map(|(_, event)| event)
+            .boxed())
+    }
+
+    /// Get a stream of events from TimeFlip2, each wrapped in an [Envelope].
+    ///
+    /// Sequence numbers continue across streams of the same [TimeFlip]. Pass the result of
+    /// [TimeFlip::clock_offset()] to annotate the events with the device's clock.
+    pub async fn envelope_stream(
+        &self,
+        clock_offset: Option<i64>,
+    ) -> Result<BoxStream<'_, Envelope>, Error> {
+        let sequence = self.sequence.clone();
+        Ok(self
+            .timed_event_stream()
+            .await?
+            .map(move |(received, event)| Envelope {
+                seq: sequence.fetch_add(1, Ordering::Relaxed),
+                received,
+                clock_offset,
+                event,
+            })
+            .boxed())
+    }
+}
+
+/// Read a text characteristic which is not required to exist on every TimeFlip2.
+async fn read_text(
+    session: &BluetoothSession,
+    device: &DeviceId,
+    characteristic: gatt::Characteristic,
+) -> Result<Option<String>, Error> {
+    let info = match characteristic.get_info(session, device).await {
+        Ok(info) => info,
+        Err(BluetoothError::UuidNotFound { .. }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let data = session.read_characteristic_value(&info.id).await?;
+    let text = String::from_utf8(data)?;
+    Ok(Some(text.trim_end_matches('\0').trim().to_string()))
 }
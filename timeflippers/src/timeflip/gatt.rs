@@ -19,6 +19,8 @@ pub enum Service {
     Battery,
     /// TimeFlip service.
     TimeFlip,
+    /// GATT Device Information service.
+    DeviceInformation,
 }
 
 impl Service {
@@ -31,6 +33,7 @@ impl Service {
             TimeFlip => "F1196F50-71A4-11E6-BDF4-0800200C9A66"
                 .parse()
                 .expect("is a UUID"),
+            DeviceInformation => uuid_from_u16(0x180A),
         }
     }
 }
@@ -82,6 +85,30 @@ pub enum Characteristic {
     ///
     /// Supports Write, Read and Notify.
     History,
+    /// Name of the TimeFlip2's manufacturer.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    ManufacturerName,
+    /// Model number of the TimeFlip2.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    ModelNumber,
+    /// Serial number of the TimeFlip2.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    SerialNumber,
+    /// Hardware revision of the TimeFlip2.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    HardwareRevision,
+    /// Firmware revision of the TimeFlip2.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    FirmwareRevision,
+    /// Software revision of the TimeFlip2.
+    ///
+    /// Saved as UTF-8 text. Supports Read only.
+    SoftwareRevision,
 }
 
 impl Characteristic {
@@ -93,6 +120,8 @@ impl Characteristic {
             BatteryLevel => Service::Battery,
             Event | Facet | CommandResult | Command | DoubleTap | SystemState | Password
             | History => Service::TimeFlip,
+            ManufacturerName | ModelNumber | SerialNumber | HardwareRevision | FirmwareRevision
+            | SoftwareRevision => Service::DeviceInformation,
         }
     }
 
@@ -126,6 +155,12 @@ impl Characteristic {
             History => "F1196F58-71A4-11E6-BDF4-0800200C9A66"
                 .parse()
                 .expect("is a UUID"),
+            ManufacturerName => uuid_from_u16(0x2A29),
+            ModelNumber => uuid_from_u16(0x2A24),
+            SerialNumber => uuid_from_u16(0x2A25),
+            HardwareRevision => uuid_from_u16(0x2A27),
+            FirmwareRevision => uuid_from_u16(0x2A26),
+            SoftwareRevision => uuid_from_u16(0x2A28),
         }
     }
 
@@ -305,7 +340,7 @@ pub enum SystemStatusError {
 }
 
 /// The system status of TimeFlip2.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SystemStatus {
     /// Whether the TimeFlip2 is in lock mode.
     pub lock_mode: bool,
@@ -357,7 +392,7 @@ pub enum FacetSettingsError {
 }
 
 /// Settings of a facet of the TimeFlip2
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct FacetSettings {
     /// The facet.
     pub facet: super::Facet,
@@ -412,7 +447,7 @@ pub enum SyncStateError {
 }
 
 /// Indicates that some type of synchronization is required.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SyncType {
     /// The device is synchronized.
     Synchronized,
@@ -433,7 +468,7 @@ pub enum SyncType {
 }
 
 /// Synchronization state used to keep the application and the TimeFlip2 up-to-date.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SyncState {
     /// The synchronization state.
     pub sync: SyncType,
@@ -492,7 +527,7 @@ pub enum EntryError {
 }
 
 /// An entry from TimeFlip2's history.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Entry {
     /// ID of the entry.
     pub id: u32,
@@ -508,7 +543,14 @@ pub struct Entry {
 
 impl Entry {
     /// Construct a [Entry] from the data read from [Characteristic::History].
-    pub fn from_data(mut data: &[u8]) -> Result<Entry, EntryError> {
+    pub fn from_data(data: &[u8], layout: super::EntryLayout) -> Result<Entry, EntryError> {
+        match layout {
+            super::EntryLayout::Standard => Self::from_standard(data),
+        }
+    }
+
+    /// Decode the [super::EntryLayout::Standard] layout.
+    fn from_standard(mut data: &[u8]) -> Result<Entry, EntryError> {
         if data.len() < 17 {
             return Err(EntryError::TooShort);
         }
@@ -558,6 +600,87 @@ impl fmt::Display for Entry {
     }
 }
 
+/// Contents of TimeFlip2's Device Information service.
+///
+/// Each field is `None` if the device does not expose the characteristic.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceInformation {
+    /// See [Characteristic::ManufacturerName].
+    pub manufacturer: Option<String>,
+    /// See [Characteristic::ModelNumber].
+    pub model_number: Option<String>,
+    /// See [Characteristic::SerialNumber].
+    pub serial_number: Option<String>,
+    /// See [Characteristic::HardwareRevision].
+    pub hardware_revision: Option<String>,
+    /// See [Characteristic::FirmwareRevision].
+    pub firmware_revision: Option<String>,
+    /// See [Characteristic::SoftwareRevision].
+    pub software_revision: Option<String>,
+}
+
+impl fmt::Display for DeviceInformation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("Manufacturer", &self.manufacturer),
+            ("Model number", &self.model_number),
+            ("Serial number", &self.serial_number),
+            ("Hardware revision", &self.hardware_revision),
+            ("Firmware revision", &self.firmware_revision),
+            ("Software revision", &self.software_revision),
+        ];
+        for (i, (name, value)) in fields.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{name}: {}", value.as_deref().unwrap_or("<unknown>"))?;
+        }
+        Ok(())
+    }
+}
+
+/// A message from TimeFlip2's event log, read from [Characteristic::Event].
+///
+/// The firmware writes short ASCII messages. Only messages observed on real devices are
+/// decoded, anything else is kept as [LogEvent::Raw].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum LogEvent {
+    /// The password written after connecting was accepted.
+    PasswordOk,
+    /// A message which is not known.
+    Raw(String),
+}
+
+impl LogEvent {
+    const PASSWORD_OK: &'static str = "password OK";
+
+    /// Decode a message of the event log.
+    pub fn parse(text: &str) -> Self {
+        match text.trim_end_matches('\0').trim() {
+            Self::PASSWORD_OK => LogEvent::PasswordOk,
+            text => LogEvent::Raw(text.into()),
+        }
+    }
+
+    /// The message as written by the firmware.
+    pub fn text(&self) -> &str {
+        match self {
+            LogEvent::PasswordOk => Self::PASSWORD_OK,
+            LogEvent::Raw(text) => text,
+        }
+    }
+}
+
+impl fmt::Display for LogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogEvent::PasswordOk => write!(f, "password accepted"),
+            LogEvent::Raw(text) => write!(f, "{text}"),
+        }
+    }
+}
+
 /// Error while decoding bluetooth event
 #[derive(Debug, Error)]
 pub enum EventError {
@@ -579,27 +702,35 @@ pub enum EventError {
     Facet(#[from] super::FacetError),
     #[error("invalid facet in double tap event: {0}")]
     DoubleTap(super::FacetError),
+    #[error("{0}")]
+    SyncState(#[from] SyncStateError),
+    #[error("{0}")]
+    History(#[from] EntryError),
 }
 
 /// Bluez handles for identifying Bluetooth events.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EventHandles {
     pub device_id: DeviceId,
     pub battery_level: CharacteristicId,
     pub last_event: CharacteristicId,
     pub facet: CharacteristicId,
     pub double_tap: CharacteristicId,
+    pub system_state: CharacteristicId,
+    pub history: CharacteristicId,
+    pub entry_layout: super::EntryLayout,
 }
 
 /// Events for subscribed properties of the TimeFlip2.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum Event {
     /// Device has disconnected.
     Disconnected,
     /// Battery level has changed.
     BatteryLevel(super::Percent),
-    /// Status message has changed.
-    Event(String),
+    /// A message has been written to the event log.
+    Event(LogEvent),
     /// The facet has changed.
     Facet(super::Facet),
     /// Double Tap / Pause detected.
@@ -612,6 +743,10 @@ pub enum Event {
         /// Whether pause mode has been entered or left.
         pause: bool,
     },
+    /// The synchronization state has changed, e.g. after the TimeFlip2 has been reset.
+    SyncState(SyncState),
+    /// A history entry has been sent.
+    History(Entry),
 }
 
 impl Event {
@@ -636,7 +771,7 @@ impl Event {
                     log::debug!("Eventlog event");
                     String::from_utf8(value)
                         .map_err(Into::into)
-                        .map(Event::Event)
+                        .map(|text| Event::Event(LogEvent::parse(&text)))
                 } else if id == handles.facet {
                     log::debug!("Facet event");
                     value
@@ -659,6 +794,16 @@ impl Event {
                                 .map(|facet| Event::DoubleTap { facet, pause })
                                 .map_err(EventError::DoubleTap)
                         })
+                } else if id == handles.system_state {
+                    log::debug!("SystemState event");
+                    SyncState::from_data(&value)
+                        .map_err(Into::into)
+                        .map(Event::SyncState)
+                } else if id == handles.history {
+                    log::debug!("History event");
+                    Entry::from_data(&value, handles.entry_layout)
+                        .map_err(Into::into)
+                        .map(Event::History)
                 } else {
                     Err(EventError::UnexpectedCharacteristic(id))
                 }
@@ -685,3 +830,28 @@ impl Event {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_events() {
+        // As read with bluetoothctl after writing the password, see the README.
+        let captured = [
+            0x70, 0x61, 0x73, 0x73, 0x77, 0x6f, 0x72, 0x64, 0x20, 0x4f, 0x4b,
+        ];
+        let text = String::from_utf8(captured.to_vec()).unwrap();
+        assert_eq!(LogEvent::parse(&text), LogEvent::PasswordOk);
+        assert_eq!(LogEvent::parse("password OK\0\0\0"), LogEvent::PasswordOk);
+        assert_eq!(LogEvent::PasswordOk.text(), "password OK");
+        assert_eq!(LogEvent::PasswordOk.to_string(), "password accepted");
+
+        for text in ["password ok", "password OK?", "pause", "facet 3", ""] {
+            let event = LogEvent::parse(text);
+            assert_eq!(event, LogEvent::Raw(text.into()));
+            assert_eq!(event.text(), text);
+            assert_eq!(event.to_string(), text);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Recording the TimeFlip2's notifications to a file and reading them back.
+#![deny(missing_docs)]
+
+use bluez_async::{BluetoothEvent, CharacteristicEvent, CharacteristicId, DeviceEvent, DeviceId};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tokio::fs;
+
+use super::{gatt, Envelope, Event};
+
+/// Error reading or writing a [Journal].
+#[allow(missing_docs)]
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("cannot access journal {0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("invalid journal record in line {0}: {1}")]
+    InvalidRecord(usize, serde_json::Error),
+    #[error("journal record in line {0} precedes the first start record")]
+    MissingStart(usize),
+}
+
+/// A notification as received from bluez.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawEvent {
+    /// A new value of a characteristic.
+    Value {
+        id: CharacteristicId,
+        value: Vec<u8>,
+    },
+    /// The device has connected or disconnected.
+    Connected { id: DeviceId, connected: bool },
+    /// Any other event, which is only kept for reference.
+    Other { debug: String },
+}
+
+impl From<&BluetoothEvent> for RawEvent {
+    fn from(event: &BluetoothEvent) -> Self {
+        match event {
+            BluetoothEvent::Characteristic {
+                id,
+                event: CharacteristicEvent::Value { value },
+            } => RawEvent::Value {
+                id: id.clone(),
+                value: value.clone(),
+            },
+            BluetoothEvent::Device {
+                id,
+                event: DeviceEvent::Connected { connected },
+            } => RawEvent::Connected {
+                id: id.clone(),
+                connected: *connected,
+            },
+            event => RawEvent::Other {
+                debug: format!("{event:?}"),
+            },
+        }
+    }
+}
+
+impl RawEvent {
+    fn to_bluetooth_event(&self) -> Option<BluetoothEvent> {
+        match self {
+            RawEvent::Value { id, value } => Some(BluetoothEvent::Characteristic {
+                id: id.clone(),
+                event: CharacteristicEvent::Value {
+                    value: value.clone(),
+                },
+            }),
+            RawEvent::Connected { id, connected } => Some(BluetoothEvent::Device {
+                id: id.clone(),
+                event: DeviceEvent::Connected {
+                    connected: *connected,
+                },
+            }),
+            RawEvent::Other { .. } => None,
+        }
+    }
+}
+
+/// A line of the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    /// An event stream has been opened, the handles are needed to decode the events.
+    Start {
+        time: DateTime<Utc>,
+        handles: gatt::EventHandles,
+    },
+    /// A notification has been received.
+    Event {
+        time: DateTime<Utc>,
+        raw: RawEvent,
+        /// The event decoded at the time of recording, for reference.
+        event: Option<Event>,
+    },
+}
+
+/// An append-only file of JSON lines recording the TimeFlip2's notifications.
+///
+/// Attach it with [super::TimeFlip::record()] and read it back with [read()].
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl Journal {
+    /// Open the journal at `path` for appending, creating it if necessary.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, JournalError> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| JournalError::Io(path.clone(), e))?;
+        Ok(Journal {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    fn append(&self, record: &Record) {
+        let mut line = serde_json::to_vec(record).expect("records are serializable");
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("not poisoned");
+        if let Err(e) = file.write_all(&line) {
+            log::warn!("cannot append to journal {}: {e}", self.path.display());
+        }
+    }
+
+    pub(super) fn start(&self, handles: &gatt::EventHandles) {
+        self.append(&Record::Start {
+            time: Utc::now(),
+            handles: handles.clone(),
+        });
+    }
+
+    pub(super) fn event(&self, time: DateTime<Utc>, raw: &BluetoothEvent, event: Option<&Event>) {
+        self.append(&Record::Event {
+            time,
+            raw: raw.into(),
+            event: event.cloned(),
+        });
+    }
+}
+
+/// Read a journal and decode its notifications again.
+///
+/// Each event is wrapped in an [Envelope] with the time it was recorded. Notifications which
+/// cannot be decoded are skipped, like [super::TimeFlip::event_stream()] does.
+pub async fn read(path: impl AsRef<Path>) -> Result<Vec<Envelope>, JournalError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .await
+        .map_err(|e| JournalError::Io(path.to_owned(), e))?;
+
+    let mut handles = None;
+    let mut envelopes = vec![];
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(line).map_err(|e| JournalError::InvalidRecord(i + 1, e))?;
+        match record {
+            Record::Start { handles: h, .. } => handles = Some(h),
+            Record::Event { time, raw, .. } => {
+                let handles = handles.as_ref().ok_or(JournalError::MissingStart(i + 1))?;
+                let Some(bt_event) = raw.to_bluetooth_event() else {
+                    continue;
+                };
+                match Event::from_bluetooth_event(bt_event, handles) {
+                    Ok(event) => envelopes.push(Envelope {
+                        seq: envelopes.len() as u64,
+                        received: time,
+                        clock_offset: None,
+                        event,
+                    }),
+                    Err(gatt::EventError::History(gatt::EntryError::EndOfHistory)) => {}
+                    Err(e) => log::warn!("failed to decode event in line {}: {e}", i + 1),
+                }
+            }
+        }
+    }
+    Ok(envelopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{timeflip::EntryLayout, types::Facet};
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn device() -> DeviceId {
+        serde_json::from_value(json!({ "object_path": "/org/bluez/hci0/dev_00_11_22_33_44_55" }))
+            .unwrap()
+    }
+
+    fn characteristic(n: usize) -> CharacteristicId {
+        serde_json::from_value(json!({
+            "object_path": format!("/org/bluez/hci0/dev_00_11_22_33_44_55/service0001/char{n:04}")
+        }))
+        .unwrap()
+    }
+
+    fn handles() -> gatt::EventHandles {
+        gatt::EventHandles {
+            device_id: device(),
+            battery_level: characteristic(1),
+            last_event: characteristic(2),
+            facet: characteristic(3),
+            double_tap: characteristic(4),
+            system_state: characteristic(5),
+            history: characteristic(6),
+            entry_layout: EntryLayout::Standard,
+        }
+    }
+
+    fn value(id: CharacteristicId, value: u8) -> BluetoothEvent {
+        BluetoothEvent::Characteristic {
+            id,
+            event: CharacteristicEvent::Value { value: vec![value] },
+        }
+    }
+
+    fn journal_file(name: &str) -> PathBuf {
+        let file = std::env::temp_dir().join(format!(
+            "timeflippers-journal-{name}-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&file);
+        file
+    }
+
+    #[test]
+    fn raw_events() {
+        let handles = handles();
+        let disconnected = BluetoothEvent::Device {
+            id: device(),
+            event: DeviceEvent::Connected { connected: false },
+        };
+        for event in [value(handles.facet.clone(), 3), disconnected] {
+            let raw = RawEvent::from(&event);
+            let json = serde_json::to_string(&raw).unwrap();
+            let read: RawEvent = serde_json::from_str(&json).unwrap();
+            assert_eq!(read.to_bluetooth_event(), Some(event));
+        }
+
+        let rssi = BluetoothEvent::Device {
+            id: device(),
+            event: DeviceEvent::Rssi { rssi: -50 },
+        };
+        assert!(matches!(RawEvent::from(&rssi), RawEvent::Other { .. }));
+        assert_eq!(RawEvent::from(&rssi).to_bluetooth_event(), None);
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let file = journal_file("round-trip");
+        let handles = handles();
+        let time = |secs| Utc.timestamp_opt(secs, 0).unwrap();
+        let facet = Event::Facet(Facet::new(3).unwrap());
+        let double_tap = Event::DoubleTap {
+            facet: Facet::new(3).unwrap(),
+            pause: true,
+        };
+
+        let journal = Journal::open(&file).unwrap();
+        journal.start(&handles);
+        journal.event(time(10), &value(handles.facet.clone(), 3), Some(&facet));
+        let rssi = BluetoothEvent::Device {
+            id: device(),
+            event: DeviceEvent::Rssi { rssi: -50 },
+        };
+        journal.event(time(11), &rssi, None);
+        let too_short = BluetoothEvent::Characteristic {
+            id: handles.facet.clone(),
+            event: CharacteristicEvent::Value { value: vec![] },
+        };
+        journal.event(time(12), &too_short, None);
+        journal.event(
+            time(20),
+            &value(handles.double_tap.clone(), 0x83),
+            Some(&double_tap),
+        );
+        let disconnected = BluetoothEvent::Device {
+            id: device(),
+            event: DeviceEvent::Connected { connected: false },
+        };
+        journal.event(time(30), &disconnected, Some(&Event::Disconnected));
+
+        let envelope = |seq, secs, event| Envelope {
+            seq,
+            received: time(secs),
+            clock_offset: None,
+            event,
+        };
+        assert_eq!(
+            read(&file).await.unwrap(),
+            vec![
+                envelope(0, 10, facet),
+                envelope(1, 20, double_tap),
+                envelope(2, 30, Event::Disconnected),
+            ]
+        );
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn missing_start() {
+        let file = journal_file("missing-start");
+        let journal = Journal::open(&file).unwrap();
+        journal.event(Utc::now(), &value(characteristic(3), 1), None);
+        assert!(matches!(
+            read(&file).await,
+            Err(JournalError::MissingStart(1))
+        ));
+        let _ = std::fs::remove_file(&file);
+    }
+}
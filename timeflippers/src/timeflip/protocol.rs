@@ -0,0 +1,164 @@
+//! Differences in the TimeFlip2 protocol between firmware revisions.
+#![deny(missing_docs)]
+
+use serde::{Deserialize, Serialize};
+
+use super::{gatt, Error};
+
+/// Layout of a record read from the History characteristic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum EntryLayout {
+    /// 17 bytes: 4 byte ID, 1 byte facet with the pause flag in its highest bit, 8 byte
+    /// start time in seconds since the epoch and 4 byte duration in seconds.
+    Standard,
+}
+
+/// Quirks and capabilities of a range of TimeFlip2 firmware revisions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Profile {
+    /// Human readable name of the profile.
+    pub name: &'static str,
+    /// First firmware revision (major, minor) the profile applies to.
+    pub since: (u16, u16),
+    /// Status reported by the Command characteristic after a command succeeded.
+    ///
+    /// The vendor's protocol documentation is wrong about this value, at least for the
+    /// password: the cube reports 0x02 on success.
+    pub status_ok: u8,
+    /// Layout of history records.
+    pub entry_layout: EntryLayout,
+    /// IDs of the commands the firmware executes.
+    pub commands: &'static [u8],
+}
+
+/// Known profiles, ordered by the first firmware revision they apply to.
+///
+/// Only one protocol is known so far. Add a profile here once a firmware revision is found
+/// to change the protocol.
+const PROFILES: &[Profile] = &[Profile {
+    name: "TimeFlip2",
+    since: (0, 0),
+    status_ok: 0x02,
+    entry_layout: EntryLayout::Standard,
+    commands: &[
+        0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x13, 0x14,
+    ],
+}];
+
+impl Profile {
+    /// Select the profile for the firmware revision read from the Device Information service.
+    ///
+    /// Falls back to the oldest known profile if the revision is unknown or unparsable.
+    pub fn for_firmware(revision: Option<&str>) -> &'static Profile {
+        let Some(version) = revision.and_then(parse_revision) else {
+            log::warn!(
+                "cannot determine protocol from firmware revision {revision:?}, assuming {}",
+                PROFILES[0].name
+            );
+            return &PROFILES[0];
+        };
+
+        PROFILES
+            .iter()
+            .rev()
+            .find(|profile| profile.since <= version)
+            .unwrap_or(&PROFILES[0])
+    }
+
+    /// Whether the firmware executes the command with the given ID.
+    pub fn supports(&self, command: u8) -> bool {
+        self.commands.contains(&command)
+    }
+
+    /// Fail with [Error::Unsupported] if the firmware does not execute `command`.
+    pub fn check(&self, command: &gatt::Command, firmware: Option<&str>) -> Result<(), Error> {
+        if self.supports(command.id()) {
+            return Ok(());
+        }
+        Err(Error::Unsupported {
+            command: command.id(),
+            profile: self.name,
+            firmware: firmware.unwrap_or("<unknown>").to_string(),
+        })
+    }
+}
+
+/// Parse revisions like "2.1", "v3.04" or "3.1.0 (build 7)" into (major, minor).
+fn parse_revision(revision: &str) -> Option<(u16, u16)> {
+    let revision = revision.trim().trim_start_matches(['v', 'V']);
+    let version: String = revision
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Facet;
+
+    #[test]
+    fn parse_revisions() {
+        assert_eq!(parse_revision("2.1"), Some((2, 1)));
+        assert_eq!(parse_revision("v3.04"), Some((3, 4)));
+        assert_eq!(parse_revision("V3.04"), Some((3, 4)));
+        assert_eq!(parse_revision(" 3.1.0 (build 7)"), Some((3, 1)));
+        assert_eq!(parse_revision("4"), Some((4, 0)));
+        assert_eq!(parse_revision("4."), Some((4, 0)));
+        assert_eq!(parse_revision(""), None);
+        assert_eq!(parse_revision("beta"), None);
+        assert_eq!(parse_revision("99999.1"), None);
+    }
+
+    #[test]
+    fn profiles() {
+        assert_eq!(Profile::for_firmware(None), &PROFILES[0]);
+        assert_eq!(Profile::for_firmware(Some("unknown")), &PROFILES[0]);
+        assert_eq!(Profile::for_firmware(Some("0.1")), &PROFILES[0]);
+        assert_eq!(
+            Profile::for_firmware(Some("99.0")),
+            PROFILES.last().unwrap()
+        );
+        assert!(PROFILES.windows(2).all(|p| p[0].since < p[1].since));
+    }
+
+    #[test]
+    fn unsupported_commands() {
+        let current = Profile::for_firmware(Some("3.1"));
+        assert!(current
+            .check(&gatt::Command::ReadStatus, Some("3.1"))
+            .is_ok());
+
+        // A hypothetical firmware without task parameters.
+        let old = Profile {
+            name: "old",
+            commands: &[0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x10, 0x11],
+            ..current.clone()
+        };
+        let command = gatt::Command::GetTaskParameter(Facet::new(1).unwrap());
+        let err = old.check(&command, Some("1.0")).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Unsupported {
+                command: 0x14,
+                profile: "old",
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "command 0x14 is not supported by old firmware 1.0"
+        );
+        assert_eq!(err.kind(), "unsupported");
+        assert!(old
+            .check(&command, None)
+            .unwrap_err()
+            .to_string()
+            .ends_with("<unknown>"));
+    }
+}
@@ -1,8 +1,9 @@
+use chrono::NaiveTime;
 use serde::{
     de::{self, Error},
     ser, Deserialize, Serialize,
 };
-use std::{default::Default, fmt};
+use std::{default::Default, fmt, str::FromStr, time::Duration};
 use thiserror::Error;
 
 /// Error constructing a [Percent] object.
@@ -47,6 +48,15 @@ impl TryFrom<usize> for Percent {
     }
 }
 
+impl Serialize for Percent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        u8::serialize(&self.0, serializer)
+    }
+}
+
 impl<'de> de::Deserialize<'de> for Percent {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -61,12 +71,28 @@ impl<'de> de::Deserialize<'de> for Percent {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Minutes(pub u16);
 
+impl Minutes {
+    /// Get the minutes as [Duration].
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(u64::from(self.0) * 60)
+    }
+}
+
 impl fmt::Display for Minutes {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} minutes", self.0)
     }
 }
 
+impl Serialize for Minutes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        u16::serialize(&self.0, serializer)
+    }
+}
+
 impl<'de> de::Deserialize<'de> for Minutes {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -78,7 +104,7 @@ impl<'de> de::Deserialize<'de> for Minutes {
 }
 
 /// Representation of the color of the LED
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Color {
     red: u16,
     green: u16,
@@ -113,7 +139,7 @@ pub enum FacetError {
 }
 
 /// The side of a TimeFlip2.
-#[derive(Debug, Clone, Ord, PartialEq, PartialOrd, Eq)]
+#[derive(Debug, Clone, Hash, Ord, PartialEq, PartialOrd, Eq)]
 pub struct Facet(u8);
 
 impl Facet {
@@ -167,7 +193,7 @@ impl<'de> de::Deserialize<'de> for Facet {
 }
 
 /// Task assigned to a facet.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FacetTask {
     /// Simple counting up timer.
     Simple,
@@ -229,3 +255,449 @@ impl<'de> de::Deserialize<'de> for BlinkInterval {
         BlinkInterval::new(v).map_err(D::Error::custom)
     }
 }
+
+/// Error constructing an [Amount] object.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum AmountError {
+    #[error("{0} is not a valid amount")]
+    Invalid(f64),
+}
+
+/// A monetary amount, stored in cents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount(i64);
+
+impl Amount {
+    /// Construct an [Amount] from a decimal value, e.g. `85.5`.
+    pub fn new(value: f64) -> Result<Self, AmountError> {
+        let cents = (value * 100.0).round();
+        if cents.is_finite() && cents.abs() < i64::MAX as f64 {
+            Ok(Amount(cents as i64))
+        } else {
+            Err(AmountError::Invalid(value))
+        }
+    }
+
+    /// Construct an [Amount] from cents.
+    pub fn from_cents(cents: i64) -> Self {
+        Amount(cents)
+    }
+
+    /// Get the amount in cents.
+    pub fn cents(&self) -> i64 {
+        self.0
+    }
+
+    /// The amount to bill for `duration` at this hourly rate, rounded to the nearest cent.
+    pub fn for_duration(&self, duration: &Duration) -> Amount {
+        let seconds = i128::from(duration.as_secs());
+        Amount::from_rounded(i128::from(self.0) * seconds, 3600)
+    }
+
+    /// Divide `cents` by `divisor`, rounding halves away from zero like a credit note would.
+    fn from_rounded(cents: i128, divisor: i128) -> Amount {
+        let half = divisor / 2;
+        let cents = if cents < 0 {
+            (cents - half) / divisor
+        } else {
+            (cents + half) / divisor
+        };
+        Amount(cents.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+    }
+}
+
+impl std::ops::Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl std::iter::Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::default(), |a, b| a + b)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        f.pad(&format!("{sign}{}.{:02}", cents / 100, cents % 100))
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let v = f64::deserialize(deserializer)?;
+        Amount::new(v).map_err(D::Error::custom)
+    }
+}
+
+/// Error constructing a [TaxRate] object.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum TaxRateError {
+    #[error("{0} out of range (0-100%)")]
+    OutOfRange(f64),
+}
+
+/// A tax rate, stored in hundredths of a percent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaxRate(u16);
+
+impl TaxRate {
+    /// Construct a [TaxRate] from a percentage, e.g. `19.0` or `7.7`.
+    pub fn new(percent: f64) -> Result<Self, TaxRateError> {
+        if (0.0..=100.0).contains(&percent) {
+            Ok(TaxRate((percent * 100.0).round() as u16))
+        } else {
+            Err(TaxRateError::OutOfRange(percent))
+        }
+    }
+
+    /// The tax for a net `amount`, rounded to the nearest cent.
+    pub fn apply(&self, amount: Amount) -> Amount {
+        Amount::from_rounded(i128::from(amount.cents()) * i128::from(self.0), 10000)
+    }
+}
+
+impl fmt::Display for TaxRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = format!("{}", f64::from(self.0) / 100.0);
+        f.pad(&format!("{s}%"))
+    }
+}
+
+impl<'de> de::Deserialize<'de> for TaxRate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let v = f64::deserialize(deserializer)?;
+        TaxRate::new(v).map_err(D::Error::custom)
+    }
+}
+
+/// Error constructing a [RoundingIncrement] object.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum RoundingIncrementError {
+    #[error("unsupported increment of {0} minutes (6, 15 or 30 minutes)")]
+    Unsupported(usize),
+}
+
+/// Increment in minutes durations are rounded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundingIncrement(u8);
+
+impl RoundingIncrement {
+    /// Construct a new [RoundingIncrement] object.
+    ///
+    /// Supported increments are 6, 15 and 30 minutes.
+    pub fn new(minutes: usize) -> Result<Self, RoundingIncrementError> {
+        match minutes {
+            6 | 15 | 30 => Ok(RoundingIncrement(u8::try_from(minutes).expect("in range"))),
+            _ => Err(RoundingIncrementError::Unsupported(minutes)),
+        }
+    }
+
+    /// Get the increment in minutes.
+    pub fn minutes(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for RoundingIncrement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} minutes", self.0)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for RoundingIncrement {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let v = usize::deserialize(deserializer)?;
+        RoundingIncrement::new(v).map_err(D::Error::custom)
+    }
+}
+
+/// Direction in which durations are rounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    /// Round up to the next increment.
+    #[default]
+    Up,
+    /// Round down to the previous increment.
+    Down,
+    /// Round to the nearest increment.
+    Nearest,
+}
+
+/// The unit tracked time is rounded in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingScope {
+    /// Round each history entry.
+    #[default]
+    Entry,
+    /// Round each session, i.e., consecutive entries of a side without a gap.
+    Session,
+    /// Round the total of each side per day.
+    Day,
+}
+
+/// Rounding policy for tracked durations.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rounding {
+    /// The increment to round to.
+    pub increment: RoundingIncrement,
+    /// The direction to round in.
+    #[serde(default)]
+    pub mode: RoundingMode,
+    /// The unit which is rounded.
+    #[serde(default)]
+    pub scope: RoundingScope,
+    /// The minimum billable duration of each unit.
+    pub minimum: Option<Minutes>,
+}
+
+impl Rounding {
+    /// Round the duration of a single unit.
+    pub fn apply(&self, duration: Duration) -> Duration {
+        let increment = u64::from(self.increment.minutes()) * 60;
+        let seconds = duration.as_secs();
+        let down = seconds - seconds % increment;
+        let rounded = match self.mode {
+            RoundingMode::Down => down,
+            RoundingMode::Up if down == seconds => down,
+            RoundingMode::Up => down + increment,
+            RoundingMode::Nearest if seconds - down >= increment.div_ceil(2) => down + increment,
+            RoundingMode::Nearest => down,
+        };
+
+        let minimum = self.minimum.as_ref().map(Minutes::duration);
+        Duration::from_secs(rounded).max(minimum.unwrap_or_default())
+    }
+}
+
+/// Error constructing an [Hours] object.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum HoursError {
+    #[error("{0} out of range (0-168 hours)")]
+    OutOfRange(f64),
+}
+
+/// A working time given in (fractional) hours, stored in minutes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hours(u32);
+
+impl Hours {
+    /// Construct an [Hours] object, e.g. from `7.5` hours.
+    pub fn new(hours: f64) -> Result<Self, HoursError> {
+        if (0.0..=168.0).contains(&hours) {
+            Ok(Hours((hours * 60.0).round() as u32))
+        } else {
+            Err(HoursError::OutOfRange(hours))
+        }
+    }
+
+    /// Get the working time in minutes.
+    pub fn minutes(&self) -> u32 {
+        self.0
+    }
+
+    /// Get the working time as [Duration].
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(u64::from(self.0) * 60)
+    }
+}
+
+impl fmt::Display for Hours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:02} hours", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Hours {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let v = f64::deserialize(deserializer)?;
+        Hours::new(v).map_err(D::Error::custom)
+    }
+}
+
+/// Error parsing a [TimeWindow].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum TimeWindowError {
+    #[error("invalid time window {0:?}, expected e.g. \"08:00-18:00\"")]
+    Invalid(String),
+}
+
+/// A daily time window, e.g. `08:00-18:00`.
+///
+/// Windows ending before they start span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Construct a [TimeWindow] from `start` (inclusive) to `end` (exclusive).
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        TimeWindow { start, end }
+    }
+
+    /// Whether `time` lies within the window.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = TimeWindowError;
+
+    fn from_str(s: &str) -> Result<Self, TimeWindowError> {
+        let invalid = || TimeWindowError::Invalid(s.into());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let parse = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(TimeWindow::new(parse(start)?, parse(end)?))
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl<'de> de::Deserialize<'de> for TimeWindow {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let v = String::deserialize(deserializer)?;
+        v.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn amount() {
+        assert_eq!(Amount::new(85.5).unwrap().cents(), 8550);
+        assert_eq!(Amount::new(-0.004).unwrap(), Amount::default());
+        assert!(Amount::new(f64::NAN).is_err());
+        assert_eq!(Amount::from_cents(0).to_string(), "0.00");
+        assert_eq!(Amount::from_cents(-5).to_string(), "-0.05");
+        assert_eq!(Amount::from_cents(123456).to_string(), "1234.56");
+    }
+
+    #[test]
+    fn amount_for_duration() {
+        let rate = Amount::new(100.0).unwrap();
+        assert_eq!(rate.for_duration(&Duration::ZERO), Amount::default());
+        assert_eq!(rate.for_duration(&(HOUR / 2)).cents(), 5000);
+        // 100.00 for 18 seconds is 0.50.
+        assert_eq!(rate.for_duration(&Duration::from_secs(18)).cents(), 50);
+        assert_eq!(rate.for_duration(&Duration::from_secs(17)).cents(), 47);
+
+        let credit = Amount::new(-1.0).unwrap();
+        assert_eq!(credit.for_duration(&HOUR).cents(), -100);
+        assert_eq!(credit.for_duration(&(HOUR / 2)).cents(), -50);
+        // -1.00 for 18 minutes is -0.30, for 27 seconds -0.0075.
+        assert_eq!(
+            credit.for_duration(&Duration::from_secs(18 * 60)).cents(),
+            -30
+        );
+        assert_eq!(credit.for_duration(&Duration::from_secs(27)).cents(), -1);
+        assert_eq!(credit.for_duration(&Duration::ZERO), Amount::default());
+    }
+
+    #[test]
+    fn tax_rate() {
+        let rate = TaxRate::new(19.0).unwrap();
+        assert_eq!(rate.apply(Amount::default()), Amount::default());
+        assert_eq!(rate.apply(Amount::from_cents(1050)).cents(), 200);
+        assert_eq!(rate.apply(Amount::from_cents(-1050)).cents(), -200);
+        assert_eq!(TaxRate::new(7.7).unwrap().to_string(), "7.7%");
+        assert!(TaxRate::new(-1.0).is_err());
+        assert!(TaxRate::new(100.5).is_err());
+    }
+
+    fn rounding(minutes: usize, mode: RoundingMode) -> Rounding {
+        Rounding {
+            increment: RoundingIncrement::new(minutes).unwrap(),
+            mode,
+            scope: RoundingScope::Entry,
+            minimum: None,
+        }
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn rounding_increment() {
+        assert!(RoundingIncrement::new(0).is_err());
+        assert!(RoundingIncrement::new(10).is_err());
+        assert_eq!(RoundingIncrement::new(6).unwrap().minutes(), 6);
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let up = rounding(15, RoundingMode::Up);
+        let down = rounding(15, RoundingMode::Down);
+        let nearest = rounding(15, RoundingMode::Nearest);
+
+        for rounding in [&up, &down, &nearest] {
+            assert_eq!(rounding.apply(Duration::ZERO), Duration::ZERO);
+            assert_eq!(rounding.apply(minutes(30)), minutes(30));
+        }
+        assert_eq!(up.apply(Duration::from_secs(1)), minutes(15));
+        assert_eq!(down.apply(minutes(29)), minutes(15));
+        assert_eq!(
+            nearest.apply(Duration::from_secs(7 * 60 + 29)),
+            Duration::ZERO
+        );
+        assert_eq!(nearest.apply(Duration::from_secs(7 * 60 + 30)), minutes(15));
+    }
+
+    #[test]
+    fn rounding_minimum() {
+        let rounding = Rounding {
+            minimum: Some(Minutes(30)),
+            ..rounding(6, RoundingMode::Up)
+        };
+        assert_eq!(rounding.apply(Duration::ZERO), minutes(30));
+        assert_eq!(rounding.apply(minutes(7)), minutes(30));
+        assert_eq!(rounding.apply(minutes(31)), minutes(36));
+    }
+}
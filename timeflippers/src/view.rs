@@ -1,8 +1,22 @@
-use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
 use std::{collections::HashMap, fmt, time::Duration};
 
-use crate::config::Config;
+use crate::config::{ComplianceConfig, Config, InvoiceConfig, TargetsConfig};
 use crate::timeflip::Entry;
+use crate::types::{Rounding, RoundingScope};
+
+mod balance;
+pub use balance::{Balance, DayBalance, DaysOff, DaysOffError};
+
+mod compliance;
+pub use compliance::{Compliance, DayCompliance, Violation};
+
+mod invoice;
+pub use invoice::{Invoice, InvoiceError, InvoiceFormat, LineItem};
+
+mod pomodoro;
+pub use pomodoro::PomodoroReport;
 
 mod table;
 use table::{Position, TableHeader};
@@ -20,27 +34,105 @@ impl<'a> fmt::Display for DurationView<'a> {
     }
 }
 
+/// Gap between two entries of a side up to which they belong to the same session.
+///
+/// TimeFlip2 does not record flips shorter than this.
+const SESSION_GAP: Duration = Duration::from_secs(5);
+
+/// Tracked time of non-paused entries, as is and after rounding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub raw: Duration,
+    pub rounded: Duration,
+}
+
+impl Totals {
+    /// Sum up the non-paused entries, rounding each unit given by the rounding scope.
+    pub fn new(entries: &[&Entry], rounding: Option<&Rounding>) -> Self {
+        let mut entries: Vec<&Entry> = entries.iter().copied().filter(|e| !e.pause).collect();
+        entries.sort_by_key(|e| (e.facet.clone(), e.time));
+
+        let raw = entries
+            .iter()
+            .fold(Duration::ZERO, |sum, e| sum.saturating_add(e.duration));
+        let Some(rounding) = rounding else {
+            return Totals { raw, rounded: raw };
+        };
+
+        let timezone = Local::now().timezone();
+        let mut units: Vec<Duration> = vec![];
+        let mut previous: Option<&Entry> = None;
+        for entry in entries {
+            let same_unit = match (rounding.scope, previous) {
+                (RoundingScope::Entry, _) | (_, None) => false,
+                (RoundingScope::Session, Some(prev)) => {
+                    prev.facet == entry.facet
+                        && (entry.time - prev.time)
+                            .to_std()
+                            .is_ok_and(|gap| gap <= prev.duration.saturating_add(SESSION_GAP))
+                }
+                (RoundingScope::Day, Some(prev)) => {
+                    prev.facet == entry.facet
+                        && prev.time.with_timezone(&timezone).date_naive()
+                            == entry.time.with_timezone(&timezone).date_naive()
+                }
+            };
+
+            match units.last_mut() {
+                Some(unit) if same_unit => *unit = unit.saturating_add(entry.duration),
+                _ => units.push(entry.duration),
+            }
+            previous = Some(entry);
+        }
+
+        let rounded = units.into_iter().fold(Duration::ZERO, |sum, unit| {
+            sum.saturating_add(rounding.apply(unit))
+        });
+        Totals { raw, rounded }
+    }
+}
+
+impl std::ops::Add for Totals {
+    type Output = Totals;
+
+    fn add(self, rhs: Totals) -> Totals {
+        Totals {
+            raw: self.raw.saturating_add(rhs.raw),
+            rounded: self.rounded.saturating_add(rhs.rounded),
+        }
+    }
+}
+
+/// The configured names of the sides, indexed by [Facet::index_zero].
+///
+/// [Facet::index_zero]: crate::Facet::index_zero
+fn side_names(config: &Config) -> Vec<String> {
+    config
+        .sides
+        .iter()
+        .enumerate()
+        .map(|(i, side)| {
+            if let Some(name) = &side.name {
+                name.clone()
+            } else {
+                format!("Side {i}")
+            }
+        })
+        .collect()
+}
+
 pub struct History {
     entries: Vec<Entry>,
     names: Vec<String>,
+    rounding: Option<Rounding>,
 }
 
 impl History {
     pub fn new(entries: Vec<Entry>, config: Config) -> Self {
         History {
             entries,
-            names: config
-                .sides
-                .iter()
-                .enumerate()
-                .map(|(i, side)| {
-                    if let Some(name) = &side.name {
-                        name.clone()
-                    } else {
-                        format!("Side {i}")
-                    }
-                })
-                .collect(),
+            names: side_names(&config),
+            rounding: config.rounding,
         }
     }
 
@@ -48,6 +140,7 @@ impl History {
         HistoryFiltered {
             entries: self.entries.iter().collect(),
             names: &self.names,
+            rounding: self.rounding.as_ref(),
         }
     }
 
@@ -59,16 +152,95 @@ impl History {
                 .filter(|entry| !entry.pause && entry.time > date)
                 .collect(),
             names: &self.names,
+            rounding: self.rounding.as_ref(),
+        }
+    }
+}
+
+/// Range of local dates selecting history entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct DateRange {
+    /// Select entries started after the beginning of this day.
+    pub since: Option<NaiveDate>,
+    /// Select entries started before the end of this day.
+    pub until: Option<NaiveDate>,
+}
+
+/// The beginning of `date`, with the offset in effect on that day.
+fn local_midnight(date: NaiveDate) -> DateTime<Local> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        // Where DST starts at midnight, the day starts an hour later.
+        .or_else(|| {
+            Local
+                .from_local_datetime(&(midnight + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .expect("a day starts at most an hour after midnight")
+}
+
+/// The end of `date`, i.e. the beginning of the following day.
+fn local_end_of_day(date: NaiveDate) -> DateTime<Local> {
+    local_midnight(date.succ_opt().unwrap_or(date))
+}
+
+impl DateRange {
+    /// Select the entries in the range, skipping entries in pause mode if `since` is given.
+    pub fn filter<'a>(&self, history: &'a History) -> HistoryFiltered<'a> {
+        let filtered = if let Some(since) = self.since {
+            history.since(local_midnight(since).into())
+        } else {
+            history.all()
+        };
+
+        if let Some(until) = self.until {
+            filtered.until(local_end_of_day(until).into())
+        } else {
+            filtered
         }
     }
+
+    /// Like [DateRange::filter], but keep entries in pause mode.
+    pub fn range<'a>(&self, history: &'a History) -> HistoryFiltered<'a> {
+        let mut filtered = history.all();
+        if let Some(since) = self.since {
+            filtered = filtered.after(local_midnight(since).into());
+        }
+        if let Some(until) = self.until {
+            filtered = filtered.until(local_end_of_day(until).into());
+        }
+        filtered
+    }
 }
 
 pub struct HistoryFiltered<'a> {
     entries: Vec<&'a Entry>,
     names: &'a [String],
+    rounding: Option<&'a Rounding>,
 }
 
 impl<'a> HistoryFiltered<'a> {
+    /// The selected entries.
+    pub fn entries(&self) -> &[&'a Entry] {
+        &self.entries
+    }
+
+    /// Only keep entries started after `date`.
+    ///
+    /// Unlike [History::since], entries in pause mode are kept.
+    pub fn after(mut self, date: DateTime<Utc>) -> Self {
+        self.entries.retain(|entry| entry.time > date);
+        self
+    }
+
+    /// Only keep entries started before `date`.
+    pub fn until(mut self, date: DateTime<Utc>) -> Self {
+        self.entries.retain(|entry| entry.time < date);
+        self
+    }
+
     fn group_by_day(&self) -> Vec<(NaiveDate, Vec<&Entry>)> {
         let timezone = Local::now().timezone();
 
@@ -81,14 +253,20 @@ impl<'a> HistoryFiltered<'a> {
         }
 
         let mut sorted = groups.into_iter().collect::<Vec<_>>();
-        sorted.sort_by(|(date_a, _), (date_b, _)| date_a.cmp(date_b));
+        sorted.sort_by_key(|(date, _)| *date);
         sorted
     }
 
+    /// The tracked time of all entries.
+    pub fn totals(&self) -> Totals {
+        Totals::new(&self.entries, self.rounding)
+    }
+
     pub fn table(&'a self) -> HistoryTable<'a> {
         HistoryTable {
             groups: vec![(None, self.entries.clone())],
             names: self.names,
+            rounding: self.rounding,
         }
     }
 
@@ -102,6 +280,7 @@ impl<'a> HistoryFiltered<'a> {
         HistoryTable {
             groups,
             names: self.names,
+            rounding: self.rounding,
         }
     }
 
@@ -110,18 +289,46 @@ impl<'a> HistoryFiltered<'a> {
             .group_by_day()
             .into_iter()
             .map(|(date, entries)| {
-                let mut durations = HashMap::<String, Duration>::new();
-                for entry in entries.into_iter() {
-                    let sum = durations
+                // Paused entries are not tracked time, neither as is nor rounded.
+                let mut by_name = HashMap::<String, Vec<&Entry>>::new();
+                for entry in entries.into_iter().filter(|e| !e.pause) {
+                    by_name
                         .entry(self.names[entry.facet.index_zero()].clone())
-                        .or_default();
-                    *sum = sum.saturating_add(entry.duration);
+                        .or_default()
+                        .push(entry);
                 }
+                let totals = by_name
+                    .into_iter()
+                    .map(|(name, entries)| (name, Totals::new(&entries, self.rounding)))
+                    .collect();
 
-                (date, durations)
+                (date, totals)
             })
             .collect();
-        Summarized { groups }
+        Summarized {
+            groups,
+            rounded: self.rounding.is_some(),
+        }
+    }
+
+    /// Compare the worked time against the targets, up to and including `today`.
+    pub fn balance(
+        &self,
+        targets: &TargetsConfig,
+        days_off: &DaysOff,
+        today: NaiveDate,
+    ) -> Balance {
+        Balance::new(self, targets, days_off, today)
+    }
+
+    /// Check each day's working time and breaks against the rules.
+    pub fn compliance(&self, rules: &ComplianceConfig) -> Compliance {
+        Compliance::new(self, rules)
+    }
+
+    /// Bill the non-paused entries with invoice number `number`.
+    pub fn invoice(&self, config: &InvoiceConfig, number: u32) -> Invoice {
+        Invoice::new(self, config, number)
     }
 }
 
@@ -145,6 +352,12 @@ impl<'a> fmt::Display for HistoryFiltered<'a> {
             )?;
         }
 
+        let totals = self.totals();
+        write!(f, "{:>align_name$}: {}", "Total", DurationView(&totals.raw))?;
+        if self.rounding.is_some() {
+            write!(f, " (rounded {})", DurationView(&totals.rounded))?;
+        }
+
         Ok(())
     }
 }
@@ -189,6 +402,7 @@ where
 pub struct HistoryTable<'a> {
     groups: Vec<(Option<String>, Vec<&'a Entry>)>,
     names: &'a [String],
+    rounding: Option<&'a Rounding>,
 }
 
 impl<'a> fmt::Display for HistoryTable<'a> {
@@ -219,6 +433,7 @@ impl<'a> fmt::Display for HistoryTable<'a> {
                     group: name.as_deref(),
                     entries: &entries[..],
                     names: self.names,
+                    rounding: self.rounding,
                 }
             )?;
         }
@@ -240,6 +455,7 @@ struct GroupTable<'a> {
     group: Option<&'a str>,
     entries: &'a [&'a Entry],
     names: &'a [String],
+    rounding: Option<&'a Rounding>,
 }
 
 impl<'a> fmt::Display for GroupTable<'a> {
@@ -280,6 +496,24 @@ impl<'a> fmt::Display for GroupTable<'a> {
             )?;
         }
 
+        let totals = Totals::new(self.entries, self.rounding);
+        let mut rows = vec![("Total", totals.raw)];
+        if self.rounding.is_some() {
+            rows.push(("Rounded", totals.rounded));
+        }
+        for (label, duration) in rows {
+            writeln!(
+                f,
+                "│ {:<width_name$}│{:^width_started$}│{:>width_duration$} │",
+                label,
+                "",
+                DurationView(&duration),
+                width_name = width_name,
+                width_started = WIDTH_STARTED,
+                width_duration = WIDTH_DURATION,
+            )?;
+        }
+
         Ok(())
     }
 }
@@ -318,7 +552,36 @@ where
 }
 
 pub struct Summarized {
-    groups: Vec<(NaiveDate, HashMap<String, Duration>)>,
+    groups: Vec<(NaiveDate, HashMap<String, Totals>)>,
+    rounded: bool,
+}
+
+impl Summarized {
+    fn row(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: &str,
+        totals: &Totals,
+        width_name: usize,
+    ) -> fmt::Result {
+        const WIDTH_DURATION: usize = 10;
+        write!(f, "│ {:<width_name$}", name, width_name = width_name)?;
+        write!(
+            f,
+            "│{:>width_duration$}",
+            DurationView(&totals.raw),
+            width_duration = WIDTH_DURATION
+        )?;
+        if self.rounded {
+            write!(
+                f,
+                "│{:>width_duration$}",
+                DurationView(&totals.rounded),
+                width_duration = WIDTH_DURATION
+            )?;
+        }
+        writeln!(f, " │")
+    }
 }
 
 impl fmt::Display for Summarized {
@@ -332,21 +595,29 @@ impl fmt::Display for Summarized {
             .unwrap_or(15)
             + 1;
 
+        let mut columns = vec![(" Side ", width_name), (" Duration ", WIDTH_DURATION)];
+        if self.rounded {
+            columns.push((" Rounded ", WIDTH_DURATION));
+        }
+
         writeln!(
             f,
             "{}",
             TableHeader {
-                columns: vec![(" Side ", width_name), (" Duration ", WIDTH_DURATION)],
+                columns: columns.clone(),
                 position: Position::Top,
             },
         )?;
 
         for (time, durations) in self.groups.iter() {
+            let date = time.to_string();
+            let mut group_columns = vec![(date.as_str(), width_name)];
+            group_columns.extend(columns.iter().skip(1).map(|(_, width)| ("", *width)));
             writeln!(
                 f,
                 "{}",
                 TableHeader {
-                    columns: vec![(&time.to_string(), width_name), ("", WIDTH_DURATION)],
+                    columns: group_columns,
                     position: Position::Center,
                 },
             )?;
@@ -354,26 +625,125 @@ impl fmt::Display for Summarized {
             let mut facets: Vec<_> = durations.keys().collect();
             facets.sort_unstable();
 
+            let mut total = Totals::default();
             for facet in facets {
-                let duration = durations.get(facet).expect("key does exist");
-                writeln!(
-                    f,
-                    "│ {:<width_name$}│{:>width_duration$} │",
-                    facet,
-                    DurationView(duration),
-                    width_name = width_name,
-                    width_duration = WIDTH_DURATION,
-                )?;
+                let totals = durations.get(facet).expect("key does exist");
+                self.row(f, facet, totals, width_name)?;
+                total = total + *totals;
             }
+            self.row(f, "Total", &total, width_name)?;
         }
 
         write!(
             f,
             "{}",
             TableHeader {
-                columns: vec![("", width_name), ("", WIDTH_DURATION)],
+                columns: columns.iter().map(|(_, width)| ("", *width)).collect(),
                 position: Position::Bottom,
             },
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        types::{RoundingIncrement, RoundingMode},
+        Facet,
+    };
+
+    fn entry(facet: usize, start: &str, seconds: u64, pause: bool) -> Entry {
+        Entry {
+            id: 0,
+            facet: Facet::new(facet).unwrap(),
+            pause,
+            time: DateTime::parse_from_rfc3339(start).unwrap().into(),
+            duration: Duration::from_secs(seconds),
+        }
+    }
+
+    fn rounding(scope: RoundingScope) -> Rounding {
+        Rounding {
+            increment: RoundingIncrement::new(15).unwrap(),
+            mode: RoundingMode::Up,
+            scope,
+            minimum: None,
+        }
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn totals_skip_paused_entries() {
+        let entries = [
+            entry(1, "2024-06-03T08:00:00Z", 600, false),
+            entry(1, "2024-06-03T08:10:00Z", 600, true),
+        ];
+        let entries: Vec<&Entry> = entries.iter().collect();
+
+        assert_eq!(
+            Totals::new(&entries, None),
+            Totals {
+                raw: minutes(10),
+                rounded: minutes(10),
+            }
+        );
+        assert_eq!(
+            Totals::new(&[], Some(&rounding(RoundingScope::Entry))),
+            Totals::default()
+        );
+    }
+
+    #[test]
+    fn totals_rounding_scopes() {
+        let entries = [
+            // Between 10:00 and 12:00 UTC, it is the same day from UTC-10 to UTC+12.
+            entry(1, "2024-06-03T10:00:00Z", 300, false),
+            // Within the session gap of the previous entry.
+            entry(1, "2024-06-03T10:05:03Z", 300, false),
+            entry(2, "2024-06-03T10:10:03Z", 60, false),
+            entry(1, "2024-06-03T10:30:00Z", 0, false),
+            entry(1, "2024-06-03T11:00:00Z", 300, false),
+        ];
+        let entries: Vec<&Entry> = entries.iter().collect();
+        let totals = |scope| Totals::new(&entries, Some(&rounding(scope)));
+
+        assert_eq!(totals(RoundingScope::Entry).raw, minutes(16));
+        // 15 + 15 + 15 + 0 + 15 minutes.
+        assert_eq!(totals(RoundingScope::Entry).rounded, minutes(60));
+        // Facet 1: 15 (two entries) + 0 + 15, facet 2: 15 minutes.
+        assert_eq!(totals(RoundingScope::Session).rounded, minutes(45));
+        assert_eq!(totals(RoundingScope::Day).rounded, minutes(30));
+    }
+
+    #[test]
+    fn summarized_skips_paused_entries() {
+        let mut config = Config::default();
+        config.sides[0].name = Some("Work".into());
+        config.sides[1].name = Some("Break".into());
+        config.rounding = Some(rounding(RoundingScope::Entry));
+        let history = History::new(
+            vec![
+                entry(1, "2024-06-03T08:00:00Z", 600, false),
+                entry(1, "2024-06-03T08:10:00Z", 600, true),
+                entry(2, "2024-06-03T08:20:00Z", 900, true),
+            ],
+            config,
+        );
+
+        let summarized = history.all().summarized();
+        assert_eq!(summarized.groups.len(), 1);
+        let totals = &summarized.groups[0].1;
+        assert_eq!(
+            totals.get("Work"),
+            Some(&Totals {
+                raw: minutes(10),
+                rounded: minutes(15),
+            })
+        );
+        assert_eq!(totals.get("Break"), None);
+    }
+}
@@ -0,0 +1,474 @@
+use chrono::{Datelike, Local, NaiveDate};
+use std::{collections::BTreeMap, fmt, time::Duration};
+use thiserror::Error;
+
+use super::{DurationView, HistoryFiltered};
+use crate::config::TargetsConfig;
+use crate::view::table::{Position, TableHeader};
+
+/// Error parsing a list of [DaysOff].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum DaysOffError {
+    #[error("line {0}: invalid date: {1}")]
+    InvalidDate(usize, chrono::ParseError),
+    #[error("line {0}: range ends before it starts")]
+    InvalidRange(usize),
+}
+
+/// Days without a working time target, e.g. public holidays or vacation.
+#[derive(Debug, Clone, Default)]
+pub struct DaysOff {
+    days: BTreeMap<NaiveDate, String>,
+}
+
+impl DaysOff {
+    /// Parse a list of days off.
+    ///
+    /// Each line contains a date (`YYYY-MM-DD`) or a range of dates (`YYYY-MM-DD..YYYY-MM-DD`),
+    /// optionally followed by a description. Empty lines and lines starting with `#` are
+    /// ignored. Days without a description are described as `default_description`.
+    pub fn parse(s: &str, default_description: &str) -> Result<Self, DaysOffError> {
+        let mut days = BTreeMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (range, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let description = match description.trim() {
+                "" => default_description,
+                d => d,
+            };
+            let parse = |s: &str| {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                    .map_err(|e| DaysOffError::InvalidDate(i + 1, e))
+            };
+            let (first, last) = match range.split_once("..") {
+                Some((first, last)) => (parse(first)?, parse(last)?),
+                None => (parse(range)?, parse(range)?),
+            };
+            if last < first {
+                return Err(DaysOffError::InvalidRange(i + 1));
+            }
+
+            for day in first.iter_days().take_while(|day| *day <= last) {
+                days.insert(day, description.to_string());
+            }
+        }
+
+        Ok(DaysOff { days })
+    }
+
+    /// Add the days of `other`, keeping the descriptions already present.
+    pub fn extend(&mut self, other: DaysOff) {
+        for (day, description) in other.days {
+            self.days.entry(day).or_insert(description);
+        }
+    }
+
+    /// The description of a day, if it is a day off.
+    pub fn get(&self, date: &NaiveDate) -> Option<&str> {
+        self.days.get(date).map(String::as_str)
+    }
+}
+
+/// Duration which might be negative, given in seconds.
+struct SignedDurationView(i64);
+
+impl fmt::Display for SignedDurationView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "+" };
+        let duration = Duration::from_secs(self.0.unsigned_abs());
+        f.pad(&format!("{sign}{}", DurationView(&duration)))
+    }
+}
+
+fn signed(duration: &Duration) -> i64 {
+    i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// Target and worked time of a single day.
+#[derive(Debug, Clone)]
+pub struct DayBalance {
+    pub date: NaiveDate,
+    pub target: Duration,
+    pub worked: Duration,
+    /// Description of the day, if it is a day off.
+    pub off: Option<String>,
+}
+
+impl DayBalance {
+    /// Overtime in seconds, negative if less than the target was worked.
+    pub fn overtime(&self) -> i64 {
+        signed(&self.worked) - signed(&self.target)
+    }
+}
+
+/// Overtime account comparing the worked time against the [TargetsConfig].
+#[derive(Debug, Clone)]
+pub struct Balance {
+    days: Vec<DayBalance>,
+    today: NaiveDate,
+}
+
+impl Balance {
+    pub(super) fn new(
+        history: &HistoryFiltered<'_>,
+        targets: &TargetsConfig,
+        days_off: &DaysOff,
+        today: NaiveDate,
+    ) -> Self {
+        let timezone = Local::now().timezone();
+
+        let mut worked = BTreeMap::<NaiveDate, Duration>::new();
+        for entry in history
+            .entries
+            .iter()
+            .filter(|entry| !entry.pause && !targets.exclude.contains(&entry.facet))
+        {
+            let sum = worked
+                .entry(entry.time.with_timezone(&timezone).date_naive())
+                .or_default();
+            *sum = sum.saturating_add(entry.duration);
+        }
+
+        let start = targets
+            .start
+            .or_else(|| worked.keys().next().copied())
+            .unwrap_or(today);
+        let days = start
+            .iter_days()
+            .take_while(|date| *date <= today)
+            .map(|date| {
+                let off = days_off.get(&date).map(String::from);
+                DayBalance {
+                    date,
+                    target: if off.is_some() {
+                        Duration::ZERO
+                    } else {
+                        targets.daily(date)
+                    },
+                    worked: worked.get(&date).copied().unwrap_or_default(),
+                    off,
+                }
+            })
+            .collect();
+
+        Balance { days, today }
+    }
+
+    /// The days of the overtime account.
+    pub fn days(&self) -> &[DayBalance] {
+        &self.days
+    }
+
+    /// Overtime in seconds accumulated before today.
+    pub fn overtime(&self) -> i64 {
+        self.days
+            .iter()
+            .filter(|day| day.date < self.today)
+            .map(DayBalance::overtime)
+            .sum()
+    }
+
+    /// Today's balance, if today is part of the account.
+    pub fn today(&self) -> Option<&DayBalance> {
+        self.days.last().filter(|day| day.date == self.today)
+    }
+
+    /// Worked and target time of the current week up to and including today.
+    pub fn week_to_date(&self) -> (Duration, Duration) {
+        let week = self.today.iso_week();
+        self.days
+            .iter()
+            .filter(|day| day.date.iso_week() == week)
+            .fold((Duration::ZERO, Duration::ZERO), |(worked, target), day| {
+                (worked + day.worked, target + day.target)
+            })
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH_DURATION: usize = 10;
+        const WIDTH_BALANCE: usize = 11;
+        let width_date = self
+            .days
+            .iter()
+            .filter_map(|day| day.off.as_ref().map(|off| off.len() + 12))
+            .max()
+            .unwrap_or(0)
+            .max(12);
+
+        let columns = [
+            (" Date ", width_date),
+            (" Target ", WIDTH_DURATION),
+            (" Worked ", WIDTH_DURATION),
+            (" Overtime ", WIDTH_BALANCE),
+            (" Balance ", WIDTH_BALANCE),
+        ];
+        writeln!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.to_vec(),
+                position: Position::Top,
+            }
+        )?;
+
+        let mut balance = 0;
+        let mut week = None;
+        for day in &self.days {
+            if week != Some(day.date.iso_week()) {
+                week = Some(day.date.iso_week());
+                let name = format!(
+                    " {}-W{:02} ",
+                    day.date.iso_week().year(),
+                    day.date.iso_week().week()
+                );
+                let mut group = vec![(name.as_str(), width_date)];
+                group.extend(columns.iter().skip(1).map(|(_, width)| ("", *width)));
+                writeln!(
+                    f,
+                    "{}",
+                    TableHeader {
+                        columns: group,
+                        position: Position::Center,
+                    }
+                )?;
+            }
+
+            balance += day.overtime();
+            let date = match &day.off {
+                Some(off) => format!("{} {off}", day.date.format("%a %m-%d")),
+                None => day.date.format("%a %m-%d").to_string(),
+            };
+            writeln!(
+                f,
+                "│ {:<width_date$}│{:>WIDTH_DURATION$}│{:>WIDTH_DURATION$}│{:>WIDTH_BALANCE$}│{:>WIDTH_BALANCE$} │",
+                date,
+                DurationView(&day.target),
+                DurationView(&day.worked),
+                SignedDurationView(day.overtime()),
+                SignedDurationView(balance),
+            )?;
+        }
+
+        writeln!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.iter().map(|(_, width)| ("", *width)).collect(),
+                position: Position::Bottom,
+            }
+        )?;
+
+        if let Some(today) = self.today() {
+            let left = today.target.saturating_sub(today.worked);
+            writeln!(
+                f,
+                "Today:        {} of {} worked, {} left",
+                DurationView(&today.worked),
+                DurationView(&today.target),
+                DurationView(&left),
+            )?;
+        }
+        let (worked, target) = self.week_to_date();
+        let percent = if target.is_zero() {
+            100
+        } else {
+            worked.as_secs() * 100 / target.as_secs()
+        };
+        writeln!(
+            f,
+            "Week to date: {} of {} worked ({percent}%)",
+            DurationView(&worked),
+            DurationView(&target),
+        )?;
+        write!(
+            f,
+            "Balance:      {} (until yesterday)",
+            SignedDurationView(self.overtime())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{timeflip::Entry, types::Hours, Facet};
+    use chrono::{TimeZone, Weekday};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn entry(facet: usize, day: &str, hours: u64, pause: bool) -> Entry {
+        Entry {
+            id: 0,
+            facet: Facet::new(facet).unwrap(),
+            pause,
+            time: Local
+                .from_local_datetime(&date(day).and_hms_opt(9, 0, 0).unwrap())
+                .unwrap()
+                .into(),
+            duration: Duration::from_secs(hours * 3600),
+        }
+    }
+
+    fn targets() -> TargetsConfig {
+        TargetsConfig {
+            weekly: Some(Hours::new(40.0).unwrap()),
+            schedule: Default::default(),
+            exclude: vec![],
+            holidays: None,
+            vacation: None,
+            start: None,
+        }
+    }
+
+    fn balance(
+        entries: &[Entry],
+        targets: &TargetsConfig,
+        days_off: &DaysOff,
+        today: &str,
+    ) -> Balance {
+        let history = HistoryFiltered {
+            entries: entries.iter().collect(),
+            names: &[],
+            rounding: None,
+        };
+        Balance::new(&history, targets, days_off, date(today))
+    }
+
+    #[test]
+    fn parse_days_off() {
+        let days = DaysOff::parse(
+            "# holidays\n\n2024-12-24..2024-12-26 Christmas\n2025-01-01\n",
+            "Holiday",
+        )
+        .unwrap();
+
+        assert_eq!(days.get(&date("2024-12-23")), None);
+        assert_eq!(days.get(&date("2024-12-24")), Some("Christmas"));
+        assert_eq!(days.get(&date("2024-12-25")), Some("Christmas"));
+        assert_eq!(days.get(&date("2024-12-26")), Some("Christmas"));
+        assert_eq!(days.get(&date("2024-12-27")), None);
+        assert_eq!(days.get(&date("2025-01-01")), Some("Holiday"));
+    }
+
+    #[test]
+    fn parse_days_off_errors() {
+        assert!(matches!(
+            DaysOff::parse("2024-12-24\n2024-13-01", ""),
+            Err(DaysOffError::InvalidDate(2, _))
+        ));
+        assert!(matches!(
+            DaysOff::parse("2024-12-26..2024-12-24", ""),
+            Err(DaysOffError::InvalidRange(1))
+        ));
+    }
+
+    #[test]
+    fn extend_keeps_descriptions() {
+        let mut days = DaysOff::parse("2024-12-24 Christmas Eve", "").unwrap();
+        days.extend(DaysOff::parse("2024-12-24..2024-12-25", "Vacation").unwrap());
+
+        assert_eq!(days.get(&date("2024-12-24")), Some("Christmas Eve"));
+        assert_eq!(days.get(&date("2024-12-25")), Some("Vacation"));
+    }
+
+    #[test]
+    fn overtime_until_yesterday() {
+        // Monday to Wednesday, 8h per day.
+        let entries = [
+            entry(1, "2024-06-03", 9, false),
+            entry(1, "2024-06-04", 7, false),
+            entry(2, "2024-06-04", 2, true),
+            entry(1, "2024-06-05", 3, false),
+        ];
+        let balance = balance(&entries, &targets(), &DaysOff::default(), "2024-06-05");
+
+        assert_eq!(balance.days().len(), 3);
+        assert_eq!(balance.days()[0].overtime(), 3600);
+        assert_eq!(balance.days()[1].overtime(), -3600);
+        assert_eq!(balance.overtime(), 0);
+        assert_eq!(
+            balance.today().unwrap().worked,
+            Duration::from_secs(3 * 3600)
+        );
+        assert_eq!(
+            balance.week_to_date(),
+            (
+                Duration::from_secs(19 * 3600),
+                Duration::from_secs(24 * 3600)
+            )
+        );
+    }
+
+    #[test]
+    fn excluded_facets_are_not_worked() {
+        let mut targets = targets();
+        targets.exclude = vec![Facet::new(2).unwrap()];
+        let entries = [
+            entry(1, "2024-06-03", 8, false),
+            entry(2, "2024-06-03", 1, false),
+        ];
+        let balance = balance(&entries, &targets, &DaysOff::default(), "2024-06-04");
+
+        assert_eq!(balance.days()[0].worked, Duration::from_secs(8 * 3600));
+        assert_eq!(balance.overtime(), 0);
+    }
+
+    #[test]
+    fn days_off_on_weekends() {
+        // Friday to Monday, Saturday and Monday are days off.
+        let mut targets = targets();
+        targets.start = Some(date("2024-06-07"));
+        let days_off = DaysOff::parse("2024-06-08..2024-06-10 Vacation", "").unwrap();
+        let entries = [
+            entry(1, "2024-06-07", 8, false),
+            entry(1, "2024-06-08", 2, false),
+        ];
+        let balance = balance(&entries, &targets, &days_off, "2024-06-11");
+
+        let days = balance.days();
+        assert_eq!(days.len(), 5);
+        assert_eq!(days[1].date.weekday(), Weekday::Sat);
+        assert_eq!(days[1].off.as_deref(), Some("Vacation"));
+        assert_eq!(days[1].target, Duration::ZERO);
+        assert_eq!(days[2].target, Duration::ZERO);
+        assert_eq!(days[3].target, Duration::ZERO);
+        // Work on a day off is overtime.
+        assert_eq!(balance.overtime(), 2 * 3600);
+    }
+
+    #[test]
+    fn schedule_takes_precedence() {
+        let mut targets = targets();
+        targets.schedule = [
+            (Weekday::Fri, Hours::new(6.0).unwrap()),
+            (Weekday::Sat, Hours::new(4.0).unwrap()),
+        ]
+        .into();
+        let hours = |hours: u64| Duration::from_secs(hours * 3600);
+
+        // Days not in the schedule fall back to the weekly target.
+        assert_eq!(targets.daily(date("2024-06-06")), hours(8));
+        assert_eq!(targets.daily(date("2024-06-07")), hours(6));
+        assert_eq!(targets.daily(date("2024-06-08")), hours(4));
+        assert_eq!(targets.daily(date("2024-06-09")), Duration::ZERO);
+
+        targets.weekly = None;
+        assert_eq!(targets.daily(date("2024-06-06")), Duration::ZERO);
+        assert_eq!(targets.daily(date("2024-06-08")), hours(4));
+    }
+
+    #[test]
+    fn signed_duration() {
+        assert_eq!(SignedDurationView(-5400).to_string(), "-01:30:00");
+        assert_eq!(SignedDurationView(0).to_string(), "+00:00:00");
+    }
+}
@@ -0,0 +1,367 @@
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use super::{DurationView, HistoryFiltered};
+use crate::config::ComplianceConfig;
+use crate::timeflip::Entry;
+use crate::view::table::{Position, TableHeader};
+
+/// A working time rule broken on a day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Worked longer than allowed.
+    MaxDailyTime { worked: Duration, maximum: Duration },
+    /// Took less break than required for the working time.
+    InsufficientBreak { required: Duration, taken: Duration },
+    /// Started working too soon after the last working day ended.
+    InsufficientRest { rest: Duration, required: Duration },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Violation::*;
+        match self {
+            MaxDailyTime { worked, maximum } => write!(
+                f,
+                "worked {}, more than the maximum of {}",
+                DurationView(worked),
+                DurationView(maximum)
+            ),
+            InsufficientBreak { required, taken } => write!(
+                f,
+                "took {} of break, {} required",
+                DurationView(taken),
+                DurationView(required)
+            ),
+            InsufficientRest { rest, required } => write!(
+                f,
+                "rested {} since the last working day, {} required",
+                DurationView(rest),
+                DurationView(required)
+            ),
+        }
+    }
+}
+
+/// Working time, breaks and rule violations of a single day.
+#[derive(Debug, Clone)]
+pub struct DayCompliance {
+    pub date: NaiveDate,
+    pub worked: Duration,
+    /// Breaks counted towards the required break.
+    pub breaks: Duration,
+    /// Rest period since the end of the previous working day.
+    pub rest: Option<Duration>,
+    pub violations: Vec<Violation>,
+}
+
+/// Compliance of the working days with the [ComplianceConfig].
+#[derive(Debug, Clone)]
+pub struct Compliance {
+    days: Vec<DayCompliance>,
+}
+
+fn end(entry: &Entry) -> DateTime<Utc> {
+    entry.time
+        + chrono::Duration::from_std(entry.duration).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+impl Compliance {
+    pub(super) fn new(history: &HistoryFiltered<'_>, rules: &ComplianceConfig) -> Self {
+        let timezone = Local::now().timezone();
+
+        let mut by_day = BTreeMap::<NaiveDate, Vec<&Entry>>::new();
+        for entry in &history.entries {
+            by_day
+                .entry(entry.time.with_timezone(&timezone).date_naive())
+                .or_default()
+                .push(entry);
+        }
+
+        let is_break = |entry: &Entry| entry.pause || rules.break_facets.contains(&entry.facet);
+        let min_break = rules.min_break_length.duration();
+
+        let mut days = vec![];
+        let mut last_end: Option<DateTime<Utc>> = None;
+        for (date, mut entries) in by_day {
+            entries.sort_by_key(|entry| entry.time);
+            let work: Vec<&Entry> = entries.iter().copied().filter(|e| !is_break(e)).collect();
+            let (Some(first), Some(last)) = (
+                work.iter().map(|e| e.time).min(),
+                work.iter().map(|e| end(e)).max(),
+            ) else {
+                continue;
+            };
+
+            let worked = work
+                .iter()
+                .fold(Duration::ZERO, |sum, e| sum.saturating_add(e.duration));
+            let breaks = entries
+                .iter()
+                .filter(|e| is_break(e) && e.duration >= min_break)
+                .filter(|e| e.time >= first && end(e) <= last)
+                .fold(Duration::ZERO, |sum, e| sum.saturating_add(e.duration));
+            let rest = last_end.and_then(|last_end| (first - last_end).to_std().ok());
+
+            let mut violations = vec![];
+            if let Some(maximum) = rules.max_daily.map(|h| h.duration()) {
+                if worked > maximum {
+                    violations.push(Violation::MaxDailyTime { worked, maximum });
+                }
+            }
+            let required = rules.required_break(worked);
+            if breaks < required {
+                violations.push(Violation::InsufficientBreak {
+                    required,
+                    taken: breaks,
+                });
+            }
+            if let (Some(rest), Some(required)) = (rest, rules.rest_period.map(|h| h.duration())) {
+                if rest < required {
+                    violations.push(Violation::InsufficientRest { rest, required });
+                }
+            }
+
+            days.push(DayCompliance {
+                date,
+                worked,
+                breaks,
+                rest,
+                violations,
+            });
+            last_end = Some(last);
+        }
+
+        Compliance { days }
+    }
+
+    /// The working days.
+    pub fn days(&self) -> &[DayCompliance] {
+        &self.days
+    }
+
+    /// Whether all days comply with the rules.
+    pub fn is_compliant(&self) -> bool {
+        self.days.iter().all(|day| day.violations.is_empty())
+    }
+}
+
+impl fmt::Display for Compliance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH_DATE: usize = 12;
+        const WIDTH_DURATION: usize = 10;
+        const WIDTH_STATUS: usize = 8;
+
+        let columns = [
+            (" Date ", WIDTH_DATE),
+            (" Worked ", WIDTH_DURATION),
+            (" Breaks ", WIDTH_DURATION),
+            (" Rest ", WIDTH_DURATION),
+            (" Status ", WIDTH_STATUS),
+        ];
+        writeln!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.to_vec(),
+                position: Position::Top,
+            }
+        )?;
+
+        for day in &self.days {
+            let rest = day
+                .rest
+                .map(|rest| DurationView(&rest).to_string())
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "│ {:<WIDTH_DATE$}│{:>WIDTH_DURATION$}│{:>WIDTH_DURATION$}│{:>WIDTH_DURATION$}│{:^WIDTH_STATUS$} │",
+                day.date.to_string(),
+                DurationView(&day.worked),
+                DurationView(&day.breaks),
+                rest,
+                if day.violations.is_empty() { "ok" } else { "VIOLATED" },
+            )?;
+        }
+
+        write!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.iter().map(|(_, width)| ("", *width)).collect(),
+                position: Position::Bottom,
+            }
+        )?;
+
+        for day in self.days.iter().filter(|day| !day.violations.is_empty()) {
+            write!(f, "\n{}:", day.date)?;
+            for violation in &day.violations {
+                write!(f, "\n  - {violation}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Facet;
+    use chrono::{NaiveDateTime, TimeZone};
+
+    const H: u64 = 60;
+
+    fn entry(facet: usize, start: &str, minutes: u64, pause: bool) -> Entry {
+        let start = NaiveDateTime::parse_from_str(start, "%Y-%m-%d %H:%M").unwrap();
+        Entry {
+            id: 0,
+            facet: Facet::new(facet).unwrap(),
+            pause,
+            time: Local.from_local_datetime(&start).unwrap().into(),
+            duration: Duration::from_secs(minutes * 60),
+        }
+    }
+
+    fn compliance(entries: &[Entry], rules: &ComplianceConfig) -> Compliance {
+        let history = HistoryFiltered {
+            entries: entries.iter().collect(),
+            names: &[],
+            rounding: None,
+        };
+        Compliance::new(&history, rules)
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn exactly_six_hours_need_no_break() {
+        let entries = [entry(1, "2024-06-03 08:00", 6 * H, false)];
+        let compliance = compliance(&entries, &ComplianceConfig::default());
+
+        assert!(compliance.is_compliant());
+        assert_eq!(compliance.days()[0].worked, minutes(6 * H));
+    }
+
+    #[test]
+    fn more_than_six_hours_need_thirty_minutes() {
+        let entries = [
+            entry(1, "2024-06-03 08:00", 3 * H, false),
+            entry(2, "2024-06-03 11:00", 20, true),
+            entry(1, "2024-06-03 11:20", 3 * H + 1, false),
+        ];
+        let days = compliance(&entries, &ComplianceConfig::default()).days;
+
+        assert_eq!(
+            days[0].violations,
+            vec![Violation::InsufficientBreak {
+                required: minutes(30),
+                taken: minutes(20),
+            }]
+        );
+    }
+
+    #[test]
+    fn exactly_nine_hours_need_thirty_minutes() {
+        let entries = [
+            entry(1, "2024-06-03 08:00", 4 * H, false),
+            entry(2, "2024-06-03 12:00", 30, true),
+            entry(1, "2024-06-03 12:30", 5 * H, false),
+        ];
+        let compliance = compliance(&entries, &ComplianceConfig::default());
+
+        assert!(compliance.is_compliant());
+        assert_eq!(compliance.days()[0].breaks, minutes(30));
+    }
+
+    #[test]
+    fn more_than_nine_hours_need_forty_five_minutes() {
+        let entries = [
+            entry(1, "2024-06-03 08:00", 4 * H, false),
+            entry(2, "2024-06-03 12:00", 30, true),
+            entry(1, "2024-06-03 12:30", 5 * H + 1, false),
+        ];
+        let days = compliance(&entries, &ComplianceConfig::default()).days;
+
+        assert_eq!(
+            days[0].violations,
+            vec![Violation::InsufficientBreak {
+                required: minutes(45),
+                taken: minutes(30),
+            }]
+        );
+    }
+
+    #[test]
+    fn short_interruptions_and_breaks_outside_work_do_not_count() {
+        let rules = ComplianceConfig {
+            break_facets: vec![Facet::new(3).unwrap()],
+            ..Default::default()
+        };
+        let entries = [
+            entry(3, "2024-06-03 07:00", 60, false),
+            entry(1, "2024-06-03 08:00", 3 * H, false),
+            entry(3, "2024-06-03 11:00", 10, false),
+            entry(1, "2024-06-03 11:10", 2 * H, false),
+            entry(3, "2024-06-03 13:10", 20, false),
+            entry(1, "2024-06-03 13:30", 2 * H, false),
+        ];
+        let days = compliance(&entries, &rules).days;
+
+        assert_eq!(days[0].worked, minutes(7 * H));
+        assert_eq!(days[0].breaks, minutes(20));
+        assert_eq!(days[0].violations.len(), 1);
+    }
+
+    #[test]
+    fn maximum_daily_time() {
+        let entries = [
+            entry(1, "2024-06-03 06:00", 5 * H, false),
+            entry(2, "2024-06-03 11:00", 45, true),
+            entry(1, "2024-06-03 11:45", 5 * H + 1, false),
+        ];
+        let days = compliance(&entries, &ComplianceConfig::default()).days;
+
+        assert_eq!(
+            days[0].violations,
+            vec![Violation::MaxDailyTime {
+                worked: minutes(10 * H + 1),
+                maximum: minutes(10 * H),
+            }]
+        );
+    }
+
+    #[test]
+    fn rest_period() {
+        let entries = [
+            entry(1, "2024-06-03 12:00", 6 * H, false),
+            // Exactly 11 hours later.
+            entry(1, "2024-06-04 05:00", 4 * H, false),
+            entry(1, "2024-06-04 18:00", 60, false),
+            entry(1, "2024-06-05 05:00", 60, false),
+        ];
+        let days = compliance(&entries, &ComplianceConfig::default()).days;
+
+        assert_eq!(days[0].rest, None);
+        assert_eq!(days[1].rest, Some(minutes(11 * H)));
+        assert!(days[1].violations.is_empty());
+        assert_eq!(
+            days[2].violations,
+            vec![Violation::InsufficientRest {
+                rest: minutes(10 * H),
+                required: minutes(11 * H),
+            }]
+        );
+    }
+
+    #[test]
+    fn days_with_breaks_only_are_skipped() {
+        let entries = [entry(1, "2024-06-03 08:00", 60, true)];
+
+        assert!(compliance(&entries, &ComplianceConfig::default())
+            .days()
+            .is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+use chrono::{Datelike, Local, NaiveDate};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+use thiserror::Error;
+
+use super::{DurationView, HistoryFiltered, Totals};
+use crate::{
+    config::{InvoiceConfig, InvoiceGrouping},
+    timeflip::Entry,
+    types::{Amount, Facet, TaxRate},
+};
+
+const HTML_TEMPLATE: &str = include_str!("../../templates/invoice.html");
+const MARKDOWN_TEMPLATE: &str = include_str!("../../templates/invoice.md");
+
+/// Error rendering an [Invoice].
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum InvoiceError {
+    #[error("cannot render invoice: {0}")]
+    Template(#[from] minijinja::Error),
+}
+
+/// Output format of an [Invoice].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceFormat {
+    Html,
+    Markdown,
+}
+
+impl InvoiceFormat {
+    /// The template used when none is configured.
+    pub fn builtin_template(&self) -> &'static str {
+        match self {
+            InvoiceFormat::Html => HTML_TEMPLATE,
+            InvoiceFormat::Markdown => MARKDOWN_TEMPLATE,
+        }
+    }
+
+    /// Template name, its extension decides whether values are escaped.
+    fn template_name(&self) -> &'static str {
+        match self {
+            InvoiceFormat::Html => "invoice.html",
+            InvoiceFormat::Markdown => "invoice.md",
+        }
+    }
+}
+
+/// A single billed position of an [Invoice].
+#[derive(Debug, Clone)]
+pub struct LineItem {
+    pub description: String,
+    /// The tracked duration.
+    pub tracked: Duration,
+    /// The billed duration, i.e., the tracked one after rounding.
+    pub billed: Duration,
+    pub rate: Amount,
+    pub amount: Amount,
+}
+
+/// An invoice for the entries of a [HistoryFiltered].
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    pub number: String,
+    pub date: NaiveDate,
+    pub period: Option<(NaiveDate, NaiveDate)>,
+    pub currency: String,
+    pub issuer: Vec<String>,
+    pub recipient: Vec<String>,
+    pub items: Vec<LineItem>,
+    pub tax_rate: TaxRate,
+}
+
+impl Invoice {
+    pub(super) fn new(history: &HistoryFiltered<'_>, config: &InvoiceConfig, number: u32) -> Self {
+        let timezone = Local::now().timezone();
+        let date = Local::now().date_naive();
+
+        let billable = history
+            .entries
+            .iter()
+            .filter(|entry| !entry.pause && !config.exclude.contains(&entry.facet));
+
+        let mut groups = BTreeMap::<(Option<NaiveDate>, Facet), Vec<&Entry>>::new();
+        let mut period: Option<(NaiveDate, NaiveDate)> = None;
+        for entry in billable {
+            let day = entry.time.with_timezone(&timezone).date_naive();
+            period = Some(match period {
+                Some((first, last)) => (first.min(day), last.max(day)),
+                None => (day, day),
+            });
+
+            let key = match config.group_by {
+                InvoiceGrouping::Project => (None, entry.facet.clone()),
+                InvoiceGrouping::Day => (Some(day), entry.facet.clone()),
+            };
+            groups.entry(key).or_default().push(entry);
+        }
+
+        let items = groups
+            .into_iter()
+            .map(|((day, facet), entries)| {
+                let name = &history.names[facet.index_zero()];
+                let totals = Totals::new(&entries, history.rounding);
+                let rate = config.rate(&facet);
+                LineItem {
+                    description: match day {
+                        Some(day) => format!("{day}: {name}"),
+                        None => name.clone(),
+                    },
+                    tracked: totals.raw,
+                    billed: totals.rounded,
+                    rate,
+                    amount: rate.for_duration(&totals.rounded),
+                }
+            })
+            .collect();
+
+        Invoice {
+            number: config.format_number(date.year(), number),
+            date,
+            period,
+            currency: config.currency.clone(),
+            issuer: config.issuer.clone(),
+            recipient: config.recipient.clone(),
+            items,
+            tax_rate: config.tax_rate,
+        }
+    }
+
+    /// The sum of all line items.
+    pub fn net(&self) -> Amount {
+        self.items.iter().map(|item| item.amount).sum()
+    }
+
+    /// The tax on the net amount.
+    pub fn tax(&self) -> Amount {
+        self.tax_rate.apply(self.net())
+    }
+
+    /// The amount due.
+    pub fn total(&self) -> Amount {
+        self.net() + self.tax()
+    }
+
+    /// Render the invoice with the given template.
+    ///
+    /// See [InvoiceFormat::builtin_template] for the variables available in the template.
+    pub fn render(&self, format: InvoiceFormat, template: &str) -> Result<String, InvoiceError> {
+        let mut env = minijinja::Environment::new();
+        env.add_template(format.template_name(), template)?;
+        Ok(env
+            .get_template(format.template_name())?
+            .render(InvoiceContext::from(self))?)
+    }
+}
+
+#[derive(Serialize)]
+struct LineItemContext {
+    description: String,
+    tracked: String,
+    billed: String,
+    hours: String,
+    rate: String,
+    amount: String,
+}
+
+#[derive(Serialize)]
+struct InvoiceContext {
+    number: String,
+    date: String,
+    period_start: Option<String>,
+    period_end: Option<String>,
+    currency: String,
+    issuer: Vec<String>,
+    recipient: Vec<String>,
+    items: Vec<LineItemContext>,
+    tracked: String,
+    billed: String,
+    net: String,
+    tax_rate: String,
+    tax: String,
+    total: String,
+}
+
+impl From<&Invoice> for InvoiceContext {
+    fn from(invoice: &Invoice) -> Self {
+        let sum = |f: fn(&LineItem) -> Duration| {
+            invoice
+                .items
+                .iter()
+                .map(f)
+                .fold(Duration::ZERO, Duration::saturating_add)
+        };
+
+        InvoiceContext {
+            number: invoice.number.clone(),
+            date: invoice.date.to_string(),
+            period_start: invoice.period.map(|(start, _)| start.to_string()),
+            period_end: invoice.period.map(|(_, end)| end.to_string()),
+            currency: invoice.currency.clone(),
+            issuer: invoice.issuer.clone(),
+            recipient: invoice.recipient.clone(),
+            items: invoice
+                .items
+                .iter()
+                .map(|item| LineItemContext {
+                    description: item.description.clone(),
+                    tracked: DurationView(&item.tracked).to_string(),
+                    billed: DurationView(&item.billed).to_string(),
+                    hours: format!("{:.2}", item.billed.as_secs_f64() / 3600.0),
+                    rate: item.rate.to_string(),
+                    amount: item.amount.to_string(),
+                })
+                .collect(),
+            tracked: DurationView(&sum(|item| item.tracked)).to_string(),
+            billed: DurationView(&sum(|item| item.billed)).to_string(),
+            net: invoice.net().to_string(),
+            tax_rate: invoice.tax_rate.to_string(),
+            tax: invoice.tax().to_string(),
+            total: invoice.total().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::FacetRate, view::History, Config};
+    use chrono::DateTime;
+
+    fn entry(facet: usize, start: &str, seconds: u64, pause: bool) -> Entry {
+        Entry {
+            id: 0,
+            facet: Facet::new(facet).unwrap(),
+            pause,
+            time: DateTime::parse_from_rfc3339(start).unwrap().into(),
+            duration: Duration::from_secs(seconds),
+        }
+    }
+
+    fn history() -> History {
+        let mut config = Config::default();
+        config.sides[0].name = Some("R&D".into());
+        config.sides[1].name = Some("Meeting".into());
+        config.sides[2].name = Some("Break".into());
+        // Between 10:00 and 12:00 UTC, it is the same day from UTC-10 to UTC+12.
+        let entries = vec![
+            entry(1, "2024-06-03T10:00:00Z", 3600, false),
+            entry(2, "2024-06-03T11:00:00Z", 1800, false),
+            entry(3, "2024-06-03T11:30:00Z", 3600, false),
+            entry(1, "2024-06-04T10:00:00Z", 1800, false),
+            entry(1, "2024-06-04T10:30:00Z", 3600, true),
+            entry(3, "2024-06-05T10:00:00Z", 600, false),
+        ];
+        History::new(entries, config)
+    }
+
+    fn config(group_by: InvoiceGrouping) -> InvoiceConfig {
+        InvoiceConfig {
+            currency: "EUR".into(),
+            hourly_rate: Amount::new(80.0).unwrap(),
+            rates: vec![FacetRate {
+                facet: Facet::new(2).unwrap(),
+                rate: Amount::new(100.0).unwrap(),
+            }],
+            exclude: vec![Facet::new(3).unwrap()],
+            tax_rate: TaxRate::new(19.0).unwrap(),
+            group_by,
+            number_format: "INV-{number}".into(),
+            number_file: None,
+            html_template: None,
+            markdown_template: None,
+            issuer: vec!["Issuer".into()],
+            recipient: vec!["Recipient".into()],
+        }
+    }
+
+    fn items(invoice: &Invoice) -> Vec<(&str, u64, i64)> {
+        invoice
+            .items
+            .iter()
+            .map(|item| {
+                (
+                    item.description.as_str(),
+                    item.billed.as_secs(),
+                    item.amount.cents(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn group_by_project() {
+        let history = history();
+        let invoice = history.all().invoice(&config(InvoiceGrouping::Project), 7);
+
+        // Paused entries and excluded sides are not billed, not even in the period.
+        assert_eq!(
+            items(&invoice),
+            [("R&D", 5400, 12000), ("Meeting", 1800, 5000)]
+        );
+        assert_eq!(invoice.number, "INV-7");
+        assert_eq!(
+            invoice.period,
+            Some((
+                NaiveDate::from_ymd_opt(2024, 6, 3).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 6, 4).unwrap()
+            ))
+        );
+        assert_eq!(invoice.net(), Amount::from_cents(17000));
+        assert_eq!(invoice.tax(), Amount::from_cents(3230));
+        assert_eq!(invoice.total(), Amount::from_cents(20230));
+    }
+
+    #[test]
+    fn group_by_day() {
+        let history = history();
+        let invoice = history.all().invoice(&config(InvoiceGrouping::Day), 7);
+
+        assert_eq!(
+            items(&invoice),
+            [
+                ("2024-06-03: R&D", 3600, 8000),
+                ("2024-06-03: Meeting", 1800, 5000),
+                ("2024-06-04: R&D", 1800, 4000),
+            ]
+        );
+        assert_eq!(invoice.net(), Amount::from_cents(17000));
+    }
+
+    #[test]
+    fn render() {
+        let history = history();
+        let invoice = history.all().invoice(&config(InvoiceGrouping::Project), 7);
+
+        let markdown = invoice
+            .render(
+                InvoiceFormat::Markdown,
+                InvoiceFormat::Markdown.builtin_template(),
+            )
+            .unwrap();
+        assert!(markdown.starts_with("# Invoice INV-7\n"));
+        assert!(markdown.contains("Period: 2024-06-03 to 2024-06-04"));
+        assert!(markdown.contains("| R&D | 01:30:00 | 01:30:00 | 1.50 | 80.00 | 120.00 |"));
+        assert!(markdown.contains("| | 02:00:00 | 02:00:00 | | **Net** | 170.00 |"));
+        assert!(markdown.contains("| **Total** | **202.30 EUR** |"));
+
+        let html = invoice
+            .render(InvoiceFormat::Html, InvoiceFormat::Html.builtin_template())
+            .unwrap();
+        assert!(html.contains("<td>R&amp;D</td>"));
+        assert!(!html.contains("R&D"));
+
+        let custom = invoice
+            .render(InvoiceFormat::Markdown, "{{ number }}: {{ total }}")
+            .unwrap();
+        assert_eq!(custom, "INV-7: 202.30");
+        assert!(invoice
+            .render(InvoiceFormat::Markdown, "{{ number")
+            .is_err());
+    }
+}
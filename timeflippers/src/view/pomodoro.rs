@@ -0,0 +1,90 @@
+use chrono::{Local, NaiveDate};
+use std::{collections::BTreeMap, fmt};
+
+use super::side_names;
+use crate::config::Config;
+use crate::pomodoro::PomodoroRecord;
+use crate::view::table::{Position, TableHeader};
+
+/// Completed and interrupted pomodoros per side and day.
+pub struct PomodoroReport {
+    groups: BTreeMap<NaiveDate, BTreeMap<String, (usize, usize)>>,
+}
+
+impl PomodoroReport {
+    pub fn new(records: &[PomodoroRecord], config: &Config) -> Self {
+        let names = side_names(config);
+        let timezone = Local::now().timezone();
+
+        let mut groups = BTreeMap::<NaiveDate, BTreeMap<String, (usize, usize)>>::new();
+        for record in records {
+            let (completed, interrupted) = groups
+                .entry(record.started.with_timezone(&timezone).date_naive())
+                .or_default()
+                .entry(names[record.facet.index_zero()].clone())
+                .or_default();
+            if record.completed {
+                *completed += 1;
+            } else {
+                *interrupted += 1;
+            }
+        }
+
+        PomodoroReport { groups }
+    }
+}
+
+impl fmt::Display for PomodoroReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH_COUNT: usize = 13;
+        let width_name = self
+            .groups
+            .values()
+            .filter_map(|sides| sides.keys().map(String::len).max())
+            .max()
+            .unwrap_or(15)
+            + 1;
+
+        let columns = [
+            (" Side ", width_name),
+            (" Completed ", WIDTH_COUNT),
+            (" Interrupted ", WIDTH_COUNT),
+        ];
+        writeln!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.to_vec(),
+                position: Position::Top,
+            }
+        )?;
+
+        for (date, sides) in &self.groups {
+            let date = date.to_string();
+            writeln!(
+                f,
+                "{}",
+                TableHeader {
+                    columns: vec![(&date, width_name), ("", WIDTH_COUNT), ("", WIDTH_COUNT)],
+                    position: Position::Center,
+                }
+            )?;
+            for (name, (completed, interrupted)) in sides {
+                writeln!(
+                    f,
+                    "│ {:<width_name$}│{:>WIDTH_COUNT$}│{:>WIDTH_COUNT$} │",
+                    name, completed, interrupted,
+                )?;
+            }
+        }
+
+        write!(
+            f,
+            "{}",
+            TableHeader {
+                columns: columns.iter().map(|(_, width)| ("", *width)).collect(),
+                position: Position::Bottom,
+            }
+        )
+    }
+}